@@ -0,0 +1,69 @@
+//! Throughput of digest computation for stored (gzipped) and raw content.
+//!
+//! Run with `cargo bench --bench digest` (optionally with `--features sha1-asm`
+//! to compare SHA-1 backends). The size of the input in MiB can be given as an
+//! argument.
+
+use flate2::{write::GzEncoder, Compression};
+use std::io::Write;
+use std::time::{Duration, Instant};
+use wayback_rs::digest::{compute_digest, compute_digest_gz};
+
+const DEFAULT_SIZE_MIB: usize = 64;
+const ITERATIONS: u32 = 5;
+
+fn main() {
+    let size_mib = std::env::args()
+        .skip(1)
+        .find_map(|arg| arg.parse().ok())
+        .unwrap_or(DEFAULT_SIZE_MIB);
+
+    let content = sample_content(size_mib * 1024 * 1024);
+    let mut encoder = GzEncoder::new(vec![], Compression::default());
+    encoder.write_all(&content).unwrap();
+    let compressed = encoder.finish().unwrap();
+
+    report("compute_digest", content.len(), || {
+        compute_digest(&mut content.as_slice()).unwrap()
+    });
+    report("compute_digest_gz", content.len(), || {
+        compute_digest_gz(&mut compressed.as_slice()).unwrap()
+    });
+}
+
+/// Compressible content that resembles HTML.
+fn sample_content(len: usize) -> Vec<u8> {
+    let mut state = 0x2545_f491_4f6c_dd1du64;
+
+    (0..len / 64)
+        .flat_map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            format!("<p class=\"c{:016x}\">{:036}</p>\n", state, state)
+                .into_bytes()
+                .into_iter()
+                .take(64)
+        })
+        .collect()
+}
+
+fn report<F: FnMut() -> String>(name: &str, len: usize, mut f: F) {
+    // Warm up (and check that the result is stable).
+    let expected = f();
+    let mut total = Duration::ZERO;
+
+    for _ in 0..ITERATIONS {
+        let start = Instant::now();
+        assert_eq!(f(), expected);
+        total += start.elapsed();
+    }
+
+    let per_iteration = total / ITERATIONS;
+    let mib_per_sec = len as f64 / (1024.0 * 1024.0) / per_iteration.as_secs_f64();
+
+    println!(
+        "{:<20} {:>10.2?} {:>10.1} MiB/s",
+        name, per_iteration, mib_per_sec
+    );
+}
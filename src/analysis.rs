@@ -0,0 +1,161 @@
+//! Analyses of downloaded collections.
+
+use crate::Item;
+use flate2::read::GzDecoder;
+use serde::Serialize;
+use std::fs::File;
+use std::path::PathBuf;
+
+/// CDX and actual lengths for a downloaded item.
+///
+/// The CDX length is the length of the compressed WARC record, so it is not
+/// expected to match the payload length exactly.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct LengthRecord {
+    pub digest: String,
+    pub cdx_length: u64,
+    pub payload_length: u64,
+    pub stored_length: u64,
+}
+
+impl LengthRecord {
+    /// The ratio of the CDX length to the payload length.
+    pub fn cdx_ratio(&self) -> f64 {
+        self.cdx_length as f64 / self.payload_length.max(1) as f64
+    }
+
+    /// The ratio of the stored (compressed) length to the payload length.
+    pub fn compression_ratio(&self) -> f64 {
+        self.stored_length as f64 / self.payload_length.max(1) as f64
+    }
+}
+
+/// Summary statistics for a set of values.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Distribution {
+    pub count: usize,
+    pub min: f64,
+    pub p10: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+impl Distribution {
+    pub fn of(mut values: Vec<f64>) -> Option<Self> {
+        if values.is_empty() {
+            None
+        } else {
+            values.sort_by(f64::total_cmp);
+            let percentile = |p: usize| values[(values.len() - 1) * p / 100];
+
+            Some(Self {
+                count: values.len(),
+                min: values[0],
+                p10: percentile(10),
+                p50: percentile(50),
+                p90: percentile(90),
+                max: values[values.len() - 1],
+                mean: values.iter().sum::<f64>() / values.len() as f64,
+            })
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct LengthReport {
+    /// The payload length minus the CDX length.
+    pub difference: Distribution,
+    pub cdx_ratio: Distribution,
+    pub compression_ratio: Distribution,
+}
+
+impl LengthReport {
+    pub fn of(records: &[LengthRecord]) -> Option<Self> {
+        Some(Self {
+            difference: Distribution::of(
+                records
+                    .iter()
+                    .map(|record| record.payload_length as f64 - record.cdx_length as f64)
+                    .collect(),
+            )?,
+            cdx_ratio: Distribution::of(records.iter().map(LengthRecord::cdx_ratio).collect())?,
+            compression_ratio: Distribution::of(
+                records
+                    .iter()
+                    .map(LengthRecord::compression_ratio)
+                    .collect(),
+            )?,
+        })
+    }
+}
+
+/// Measure the lengths of items that have been downloaded.
+///
+/// The lookup function should return the path of the GZip-compressed data
+/// file for a digest, if it has been downloaded. Items without data files are
+/// skipped.
+pub fn measure_lengths<'a, I, F>(items: I, lookup: F) -> std::io::Result<Vec<LengthRecord>>
+where
+    I: IntoIterator<Item = &'a Item>,
+    F: Fn(&str) -> Option<PathBuf>,
+{
+    let mut records = vec![];
+
+    for item in items {
+        if let Some(path) = lookup(&item.digest) {
+            let file = File::open(&path)?;
+            let stored_length = file.metadata()?.len();
+            let payload_length = std::io::copy(&mut GzDecoder::new(file), &mut std::io::sink())?;
+
+            records.push(LengthRecord {
+                digest: item.digest.clone(),
+                cdx_length: item.length,
+                payload_length,
+                stored_length,
+            });
+        }
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{measure_lengths, Distribution, LengthReport};
+
+    #[test]
+    fn distribution() {
+        let distribution = Distribution::of((1..=11).map(f64::from).collect()).unwrap();
+
+        assert_eq!(distribution.count, 11);
+        assert_eq!(distribution.min, 1.0);
+        assert_eq!(distribution.p50, 6.0);
+        assert_eq!(distribution.max, 11.0);
+        assert_eq!(distribution.mean, 6.0);
+        assert!(Distribution::of(vec![]).is_none());
+    }
+
+    #[test]
+    fn measure() {
+        let base = crate::fixtures::temp_dir("lengths").unwrap();
+        let (items, contents): (Vec<_>, Vec<_>) = crate::fixtures::items(5).into_iter().unzip();
+        crate::fixtures::store_tree(&base, &contents).unwrap();
+
+        let store = crate::store::data::Store::new(&base);
+        let records = measure_lengths(&items, |digest| {
+            store.lookup(digest).map(|path| path.into())
+        })
+        .unwrap();
+
+        assert_eq!(records.len(), 5);
+        for (record, content) in records.iter().zip(&contents) {
+            assert_eq!(record.payload_length, content.len() as u64);
+            assert_eq!(record.cdx_length, content.len() as u64);
+        }
+        assert_eq!(LengthReport::of(&records).unwrap().difference.max, 0.0);
+
+        std::fs::remove_dir_all(base).unwrap();
+    }
+}
@@ -0,0 +1,280 @@
+//! A client for the Wayback Machine's availability API.
+//!
+//! The availability API returns the capture closest to a given time for each
+//! URL, which makes it useful for triaging URL lists before running full CDX
+//! searches.
+
+use super::util::{retry_future, ErrorClass, Retryable};
+use reqwest::{Client, RequestBuilder};
+use serde::{de::DeserializeOwned, Deserialize};
+use std::collections::HashMap;
+use std::time::Duration;
+use tryhard::RetryPolicy;
+
+const DEFAULT_AVAILABILITY_URL: &str = "https://archive.org/wayback/available";
+const DEFAULT_BATCH_SIZE: usize = 50;
+const DEFAULT_PACING: Duration = Duration::from_millis(500);
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("HTTP client error: {0}")]
+    HttpClientError(#[from] reqwest::Error),
+    #[error("JSON decoding error: {0}")]
+    JsonError(#[from] serde_json::Error),
+}
+
+impl Retryable for Error {
+    fn max_retries() -> u32 {
+        5
+    }
+
+    fn log_level() -> Option<log::Level> {
+        Some(log::Level::Warn)
+    }
+
+    fn default_initial_delay() -> Duration {
+        Duration::from_millis(250)
+    }
+
+    fn custom_retry_policy(&self) -> Option<RetryPolicy> {
+        match self {
+            Error::HttpClientError(error) => match ErrorClass::of(error) {
+                ErrorClass::Certificate => Some(RetryPolicy::Break),
+                _ => None,
+            },
+            Error::JsonError(_) => Some(RetryPolicy::Break),
+        }
+    }
+}
+
+/// The closest capture of a URL.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub struct Capture {
+    pub url: String,
+    pub timestamp: String,
+    pub status: String,
+    pub available: bool,
+}
+
+#[derive(Deserialize)]
+struct Snapshots {
+    closest: Option<Capture>,
+}
+
+#[derive(Deserialize)]
+struct Availability {
+    url: String,
+    archived_snapshots: Snapshots,
+}
+
+#[derive(Deserialize)]
+struct BatchResponse {
+    results: Vec<Availability>,
+}
+
+impl BatchResponse {
+    /// Pair the results with the submitted URLs.
+    ///
+    /// The API may echo back a normalized form of each URL, so results are
+    /// matched by position when there is one for each URL, and otherwise by
+    /// normalized URL.
+    fn into_captures<S: AsRef<str>>(self, batch: &[S]) -> Vec<(String, Option<Capture>)> {
+        if self.results.len() == batch.len() {
+            batch
+                .iter()
+                .zip(self.results)
+                .map(|(url, availability)| {
+                    (
+                        url.as_ref().to_string(),
+                        availability.archived_snapshots.closest,
+                    )
+                })
+                .collect()
+        } else {
+            let mut captures = self
+                .results
+                .into_iter()
+                .map(|availability| {
+                    (
+                        normalize_url(&availability.url),
+                        availability.archived_snapshots.closest,
+                    )
+                })
+                .collect::<HashMap<_, _>>();
+
+            batch
+                .iter()
+                .map(|url| {
+                    let capture = captures.remove(&normalize_url(url.as_ref())).flatten();
+                    (url.as_ref().to_string(), capture)
+                })
+                .collect()
+        }
+    }
+}
+
+/// Ignore the scheme, case, and any trailing slash when matching URLs.
+fn normalize_url(url: &str) -> String {
+    let url = url.to_lowercase();
+    let url = url
+        .strip_prefix("https://")
+        .or_else(|| url.strip_prefix("http://"))
+        .unwrap_or(&url);
+
+    url.trim_end_matches('/').to_string()
+}
+
+pub struct AvailabilityClient {
+    base: String,
+    batch_size: usize,
+    pacing: Duration,
+    underlying: Client,
+}
+
+impl AvailabilityClient {
+    pub fn new(base: String) -> Result<Self, Error> {
+        Ok(Self {
+            base,
+            batch_size: DEFAULT_BATCH_SIZE,
+            pacing: DEFAULT_PACING,
+            underlying: Client::builder()
+                .user_agent(super::util::DEFAULT_USER_AGENT)
+                .build()?,
+        })
+    }
+
+    /// Send at most this many URLs per request.
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Wait this long between batch requests.
+    pub fn with_pacing(mut self, pacing: Duration) -> Self {
+        self.pacing = pacing;
+        self
+    }
+
+    /// Find the capture closest to the timestamp (or the most recent capture)
+    /// for a single URL.
+    pub async fn check(
+        &self,
+        url: &str,
+        timestamp: Option<&str>,
+    ) -> Result<Option<Capture>, Error> {
+        let mut params = vec![("url", url)];
+        params.extend(timestamp.map(|timestamp| ("timestamp", timestamp)));
+
+        let availability: Availability =
+            retry_future(|| self.fetch(self.underlying.get(&self.base).query(&params))).await?;
+
+        Ok(availability.archived_snapshots.closest)
+    }
+
+    /// Find the closest captures for many URLs, using batched requests.
+    ///
+    /// The result contains an entry for every URL that was checked.
+    pub async fn check_many<S: AsRef<str>>(
+        &self,
+        urls: &[S],
+        timestamp: Option<&str>,
+    ) -> Result<HashMap<String, Option<Capture>>, Error> {
+        let mut results = HashMap::with_capacity(urls.len());
+
+        for (i, batch) in urls.chunks(self.batch_size).enumerate() {
+            if i > 0 {
+                async_std::task::sleep(self.pacing).await;
+            }
+
+            let mut params = batch
+                .iter()
+                .map(|url| ("url", url.as_ref()))
+                .collect::<Vec<_>>();
+            params.extend(timestamp.map(|timestamp| ("timestamp", timestamp)));
+
+            let response: BatchResponse =
+                retry_future(|| self.fetch(self.underlying.post(&self.base).form(&params))).await?;
+
+            results.extend(response.into_captures(batch));
+        }
+
+        Ok(results)
+    }
+
+    async fn fetch<T: DeserializeOwned>(&self, request: RequestBuilder) -> Result<T, Error> {
+        let contents = request.send().await?.error_for_status()?.text().await?;
+
+        Ok(serde_json::from_str(&contents)?)
+    }
+}
+
+impl Default for AvailabilityClient {
+    fn default() -> Self {
+        Self::new(DEFAULT_AVAILABILITY_URL.to_string()).unwrap()
+    }
+}
+
+/// Find the closest captures for many URLs using the default client.
+pub async fn check_many<S: AsRef<str>>(
+    urls: &[S],
+    timestamp: Option<&str>,
+) -> Result<HashMap<String, Option<Capture>>, Error> {
+    AvailabilityClient::default()
+        .check_many(urls, timestamp)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BatchResponse;
+
+    #[test]
+    fn decode_batch() {
+        let response: BatchResponse = serde_json::from_str(
+            r#"{"results":[{"url":"example.com","archived_snapshots":{"closest":{"status":"200","available":true,"url":"http://web.archive.org/web/20130919044612/http://example.com/","timestamp":"20130919044612"}}},{"url":"example.invalid","archived_snapshots":{}}]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(response.results.len(), 2);
+        assert_eq!(
+            response.results[0]
+                .archived_snapshots
+                .closest
+                .as_ref()
+                .map(|capture| capture.timestamp.as_str()),
+            Some("20130919044612")
+        );
+        assert!(response.results[1].archived_snapshots.closest.is_none());
+    }
+
+    #[test]
+    fn normalized_response_urls() {
+        let json = r#"{"results":[{"url":"http://example.com/","archived_snapshots":{"closest":{"status":"200","available":true,"url":"http://web.archive.org/web/20130919044612/http://example.com/","timestamp":"20130919044612"}}},{"url":"example.invalid","archived_snapshots":{}}]}"#;
+
+        let batch = ["Example.com", "example.invalid"];
+        let captures = serde_json::from_str::<BatchResponse>(json)
+            .unwrap()
+            .into_captures(&batch);
+        assert_eq!(captures[0].0, "Example.com");
+        assert!(captures[0].1.is_some());
+        assert_eq!(captures[1].0, "example.invalid");
+        assert!(captures[1].1.is_none());
+
+        // Results are matched by normalized URL if some are missing.
+        let batch = ["https://EXAMPLE.com", "example.invalid", "example.net"];
+        let captures = serde_json::from_str::<BatchResponse>(json)
+            .unwrap()
+            .into_captures(&batch);
+        assert_eq!(
+            captures
+                .iter()
+                .map(|(url, capture)| (url.as_str(), capture.is_some()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("https://EXAMPLE.com", true),
+                ("example.invalid", false),
+                ("example.net", false)
+            ]
+        );
+    }
+}
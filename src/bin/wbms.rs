@@ -1,12 +1,76 @@
 use clap::{ArgAction, Parser};
 use log::LevelFilter;
+use serde::Serialize;
 use std::collections::HashSet;
-use wayback_rs::store::data::Store;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+use wayback_rs::{
+    analysis::{measure_lengths, LengthReport},
+    cdx::{BlockedRegistry, CdxQuery, Filter, IndexClient, ResponseCache},
+    sample::{self, Stratum},
+    session::{
+        Budget, CaptureSelection, HarvestConfig, MimeClass, MimeRouting, OutputCompression,
+        PoisonDigests, Rotation, Slack, Webhook,
+    },
+    store::{data::Store, gz::GzOptions},
+    util::CancellationToken,
+};
+
+/// Exit code for runs that completed but failed to download some items.
+const EXIT_FAILURES: u8 = 2;
+/// Exit code for runs that completed but had blocked queries.
+const EXIT_BLOCKED: u8 = 3;
+/// Exit code for runs that ended with a fatal error.
+const EXIT_FATAL: u8 = 1;
+/// Exit code for runs that were interrupted.
+const EXIT_INTERRUPTED: u8 = 130;
+/// Exit code for runs that stopped early because their budget was used up.
+const EXIT_BUDGET_EXHAUSTED: u8 = 4;
 
 #[tokio::main]
-async fn main() -> Result<(), Error> {
+async fn main() -> ExitCode {
     let opts: Opts = Opts::parse();
     let _ = init_logging(opts.verbose);
+    let json = opts.json.clone();
+    let start = Instant::now();
+
+    let mut summary = match run(opts).await {
+        Ok(summary) => summary,
+        Err(error) => {
+            log::error!("{:?}", error);
+            Summary {
+                error_class: Some(error.class().to_string()),
+                error: Some(error.to_string()),
+                ..Default::default()
+            }
+        }
+    };
+    summary.elapsed_secs = start.elapsed().as_secs_f64();
+
+    // The summary isn't written to stdout, which some commands use for their
+    // output.
+    if let Some(path) = json {
+        let result = serde_json::to_string(&summary)
+            .map_err(Error::from)
+            .and_then(|output| {
+                match path {
+                    Some(path) => std::fs::write(path, format!("{}\n", output))?,
+                    None => eprintln!("{}", output),
+                }
+                Ok(())
+            });
+
+        if let Err(error) = result {
+            log::error!("Summary output error: {:?}", error);
+        }
+    }
+
+    summary.exit_code()
+}
+
+async fn run(opts: Opts) -> Result<Summary, Error> {
+    let mut summary = Summary::default();
 
     match opts.command {
         Command::Digests { prefix } => {
@@ -21,43 +85,283 @@ async fn main() -> Result<(), Error> {
                 panic!("Must provide directory to list digests")
             }
         }
+        Command::Lookup { digest, query } => {
+            if let Some(base) = opts.base {
+                if let Some(path) = Store::new(base).lookup(&digest) {
+                    println!("store\t{}", path.to_string_lossy());
+                }
+            }
+
+            if let Some(query) = query {
+                let client = IndexClient::default();
+                let query = CdxQuery::new(&query).with_filter(Filter::digest(&digest));
+                for item in client.search(&query).await? {
+                    println!("cdx\t{}\t{}", item.url, item.timestamp());
+                }
+            }
+        }
+        Command::Sample {
+            name,
+            size,
+            by,
+            seed,
+        } => {
+            let base = opts.base.expect("Must provide session directory to sample");
+            let session = wayback_rs::session::Session::new(base, None::<String>, 1)?;
+            let items = session.items(&name)?;
+
+            let sampled = match by {
+                Some(stratum) => sample::sample_stratified(items, stratum, size, seed)
+                    .into_values()
+                    .flatten()
+                    .collect(),
+                None => sample::sample(items, size, seed),
+            };
+
+            let mut writer = csv::WriterBuilder::new().from_writer(std::io::stdout());
+            for item in sampled {
+                writer.write_record(item.to_record())?;
+            }
+            writer.flush()?;
+        }
+        Command::Lengths => {
+            let base = opts
+                .base
+                .expect("Must provide session directory to analyze");
+            let session = wayback_rs::session::Session::new(&base, None::<String>, 1)?;
+            let mut items = session.items("originals")?;
+            items.extend(session.items("extras")?);
+
+            let records = measure_lengths(&items, |digest| session.lookup_data(digest))?;
+
+            let mut writer =
+                csv::Writer::from_path(std::path::Path::new(&base).join("lengths.csv"))?;
+            for record in &records {
+                writer.serialize(record)?;
+            }
+            writer.flush()?;
+
+            if let Some(report) = LengthReport::of(&records) {
+                println!("{}", serde_json::to_string_pretty(&report)?);
+            }
+        }
+        Command::Mirror { urls, parallelism } => {
+            let urls = std::fs::read_to_string(urls)?
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(str::to_string)
+                .collect::<Vec<_>>();
+
+            let session = if let Some(base) = opts.base {
+                wayback_rs::session::Session::new(base, None::<String>, parallelism)
+            } else {
+                wayback_rs::session::Session::new_timestamped(None::<String>, parallelism)
+            }?;
+
+            let result = session.mirror_latest(&urls).await?;
+
+            for url in &result.missing {
+                log::warn!("No valid captures: {}", url);
+            }
+
+            summary.success = result.saved;
+            summary.skipped = result.missing.len();
+            summary.failed = result.failed.len();
+        }
         Command::Download {
             query,
             twitter,
             known,
             parallelism,
+            data_dirs,
+            split_by_mime,
+            skip_mime_classes,
+            rotate_rows,
+            rotate_bytes,
+            compress,
+            headers,
+            blocked,
+            poison,
+            webhook,
+            slack_webhook,
+            cdx_cache,
+            cdx_cache_ttl,
+            pipeline,
+            max_items,
+            max_bytes,
+            max_secs,
+            select,
+            deterministic,
+            #[cfg(feature = "encryption")]
+            key_file,
         } => {
-            let session = if let Some(base) = opts.base {
+            let cancellation = cancel_on_interrupt();
+            let mut session = if let Some(base) = opts.base {
                 wayback_rs::session::Session::new(base, known, parallelism)
             } else {
                 wayback_rs::session::Session::new_timestamped(known, parallelism)
-            }?;
+            }?
+            .with_data_dirs(&data_dirs)
+            .with_mime_routing(if split_by_mime {
+                MimeRouting::subdirectories()
+            } else {
+                MimeRouting::default()
+            })
+            .with_skipped_mime_classes(&skip_mime_classes)
+            .with_compression(compress)
+            .with_capture_selection(select)
+            .with_budget(Budget {
+                max_items,
+                max_bytes,
+                max_duration: max_secs.map(Duration::from_secs),
+            })
+            .with_cancellation(cancellation.clone());
+
+            if let Some(blocked) = blocked {
+                session = session.with_blocked_registry(BlockedRegistry::load(
+                    blocked,
+                    wayback_rs::cdx::blocked::DEFAULT_EXPIRY,
+                )?);
+            }
+
+            if let Some(poison) = poison {
+                session = session.with_poison_digests(PoisonDigests::load(poison)?);
+            }
+
+            if let Some(webhook) = webhook {
+                session = session.with_notifier(Webhook::new(webhook));
+            }
+
+            if let Some(slack_webhook) = slack_webhook {
+                session = session.with_notifier(Slack::new(slack_webhook));
+            }
+
+            if let Some(cdx_cache) = cdx_cache {
+                session = session.with_cdx_cache(ResponseCache::new(
+                    cdx_cache,
+                    Duration::from_secs(cdx_cache_ttl),
+                )?);
+            }
+
+            if let Some(headers) = headers {
+                session = session.with_header_store(headers);
+            }
+
+            if deterministic {
+                session = session.with_gz_options(GzOptions::deterministic());
+            }
+
+            #[cfg(feature = "encryption")]
+            if let Some(key_file) = key_file {
+                session =
+                    session.with_encryption_key(wayback_rs::store::crypto::Key::load(key_file)?);
+            }
+
+            if rotate_rows.is_some() || rotate_bytes.is_some() {
+                session = session.with_rotation(Rotation {
+                    max_rows: rotate_rows,
+                    max_bytes: rotate_bytes,
+                });
+            }
+
+            if let Some(query) = query.as_ref().filter(|_| pipeline) {
+                let queries = expand_queries(query, twitter);
+                let result = session.harvest(&queries, HarvestConfig::default()).await?;
+
+                summary.success = result.success;
+                summary.invalid = result.invalid;
+                summary.skipped = result.skipped;
+                summary.failed = result.failed;
+                summary.blocked_queries = result.blocked_queries;
+                summary.budget_exhausted = result.budget_exhausted;
+                summary.cancelled = result.cancelled;
+                summary.retries = result.retries;
+                summary.retry_delay_secs = result.retry_delay.as_secs_f64();
+
+                return Ok(summary);
+            }
 
             if let Some(query) = query {
                 let queries = expand_queries(&query, twitter);
-                session.save_cdx_results(&queries).await?;
-                session.resolve_redirects().await?;
-                let (success_count, invalid_count, skipped_count, error_count) =
-                    session.download_items().await?;
-
-                log::info!("Successfully downloaded: {}", success_count);
-                log::info!("Downloaded by invalid hash: {}", invalid_count);
-                log::info!("Skipped: {}", skipped_count);
-                log::info!("Failed: {}", error_count);
-            } else {
-                session.resolve_redirects().await?;
-                let (success_count, invalid_count, skipped_count, error_count) =
-                    session.download_items().await?;
-
-                log::info!("Successfully downloaded: {}", success_count);
-                log::info!("Downloaded by invalid hash: {}", invalid_count);
-                log::info!("Skipped: {}", skipped_count);
-                log::info!("Failed: {}", error_count);
+                summary.blocked_queries = session.save_cdx_results(&queries).await?;
             }
+
+            session.resolve_redirects().await?;
+            let result = session.download_items().await?;
+
+            log::info!("Successfully downloaded: {}", result.success);
+            log::info!("Downloaded by invalid hash: {}", result.invalid);
+            log::info!("Skipped: {}", result.skipped);
+            log::info!("Failed: {}", result.failed);
+
+            summary.success = result.success;
+            summary.invalid = result.invalid;
+            summary.skipped = result.skipped;
+            summary.failed = result.failed;
+            summary.budget_exhausted = result.budget_exhausted;
+            summary.retries = result.retries;
+            summary.retry_delay_secs = result.retry_delay.as_secs_f64();
+            summary.cancelled = cancellation.is_cancelled();
         }
     };
 
-    Ok(())
+    Ok(summary)
+}
+
+/// Cancel the returned token on the first Ctrl-C, so that work in progress can
+/// be saved, and exit immediately on the second.
+fn cancel_on_interrupt() -> CancellationToken {
+    let token = CancellationToken::new();
+    let handle = token.clone();
+
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            log::warn!("Interrupted; finishing work in progress (press Ctrl-C again to exit)");
+            handle.cancel();
+
+            if tokio::signal::ctrl_c().await.is_ok() {
+                std::process::exit(EXIT_INTERRUPTED.into());
+            }
+        }
+    });
+
+    token
+}
+
+/// A machine-readable summary of a run.
+#[derive(Debug, Default, Serialize)]
+struct Summary {
+    success: usize,
+    invalid: usize,
+    skipped: usize,
+    failed: usize,
+    blocked_queries: Vec<String>,
+    budget_exhausted: bool,
+    cancelled: bool,
+    retries: u32,
+    retry_delay_secs: f64,
+    elapsed_secs: f64,
+    error_class: Option<String>,
+    error: Option<String>,
+}
+
+impl Summary {
+    fn exit_code(&self) -> ExitCode {
+        if self.error.is_some() {
+            ExitCode::from(EXIT_FATAL)
+        } else if self.cancelled {
+            ExitCode::from(EXIT_INTERRUPTED)
+        } else if self.budget_exhausted {
+            ExitCode::from(EXIT_BUDGET_EXHAUSTED)
+        } else if self.failed > 0 {
+            ExitCode::from(EXIT_FAILURES)
+        } else if !self.blocked_queries.is_empty() {
+            ExitCode::from(EXIT_BLOCKED)
+        } else {
+            ExitCode::SUCCESS
+        }
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -68,14 +372,47 @@ pub enum Error {
     Store(#[from] wayback_rs::store::data::Error),
     #[error("Session error")]
     Session(#[from] wayback_rs::session::Error),
+    #[error("CDX error")]
+    Cdx(#[from] wayback_rs::cdx::Error),
+    #[error("CSV error")]
+    Csv(#[from] csv::Error),
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("JSON error")]
+    Json(#[from] serde_json::Error),
+    #[cfg(feature = "encryption")]
+    #[error("Encryption error")]
+    Crypto(#[from] wayback_rs::store::crypto::Error),
 }
 
+impl Error {
+    fn class(&self) -> &'static str {
+        match self {
+            Error::LogInit(_) => "log_init",
+            Error::Store(_) => "store",
+            Error::Session(_) => "session",
+            Error::Cdx(_) => "cdx",
+            Error::Csv(_) => "csv",
+            Error::Io(_) => "io",
+            Error::Json(_) => "json",
+            #[cfg(feature = "encryption")]
+            Error::Crypto(_) => "crypto",
+        }
+    }
+}
+
+/// Exits with 1 on fatal errors, 2 if some items failed, 3 if some queries were blocked, 4 if the
+/// budget was used up, and 130 if interrupted
 #[derive(Parser)]
 #[clap(name = "wbms", version, author)]
 struct Opts {
     /// Level of verbosity
     #[clap(short, long, action = ArgAction::Count)]
     verbose: u8,
+    /// Write a JSON summary of the run to stderr, or to the given file (with
+    /// `--json=PATH`)
+    #[clap(long, value_name = "PATH", num_args = 0..=1, require_equals = true)]
+    json: Option<Option<PathBuf>>,
     /// The base directory path
     #[clap(long)]
     base: Option<String>,
@@ -83,6 +420,8 @@ struct Opts {
     command: Command,
 }
 
+// Parsed once, so the size of the largest variant doesn't matter.
+#[allow(clippy::large_enum_variant)]
 #[derive(Parser)]
 enum Command {
     /// Print the digests in the given store to stdout
@@ -91,6 +430,46 @@ enum Command {
         #[clap(long, short)]
         prefix: Option<String>,
     },
+    /// Report where a digest is known (the local store and optionally the CDX server)
+    Lookup {
+        /// The digest to look up
+        #[clap(long, short)]
+        digest: String,
+        /// A URL query to check against the CDX server's digest filter
+        #[clap(long, short)]
+        query: Option<String>,
+    },
+    /// Print a random sample of a session's items to stdout as CSV
+    Sample {
+        /// The item file to sample (originals, redirects, or extras)
+        #[clap(long, default_value = "originals")]
+        name: String,
+        /// The sample size (per stratum if stratified)
+        #[clap(long, short)]
+        size: usize,
+        /// Stratify the sample by host, year, or mime
+        #[clap(long)]
+        by: Option<Stratum>,
+        /// Random seed
+        #[clap(long, default_value = "0")]
+        seed: u64,
+    },
+    /// Compare CDX lengths with downloaded payload lengths for a session
+    ///
+    /// Writes per-item lengths to lengths.csv in the session directory and
+    /// prints a summary of their distributions.
+    Lengths,
+    /// Download the most recent valid capture of each URL in a file
+    ///
+    /// Content is written to the mirror directory in the session directory.
+    Mirror {
+        /// File containing one URL per line
+        #[clap(long, short)]
+        urls: String,
+        /// Level of parallelism
+        #[clap(long, default_value = "6")]
+        parallelism: usize,
+    },
     Download {
         /// The query to search for (if not provided, will resume processing)
         #[clap(long, short)]
@@ -104,6 +483,71 @@ enum Command {
         /// Level of parallelism
         #[clap(long, default_value = "6")]
         parallelism: usize,
+        /// Data directory for downloaded items (may be repeated for spillover)
+        #[clap(long = "data-dir")]
+        data_dirs: Vec<String>,
+        /// Place downloaded data in html, images, media, and other
+        /// subdirectories of the data directories
+        #[clap(long)]
+        split_by_mime: bool,
+        /// Don't download content in this MIME class (html, images, media,
+        /// or other; may be repeated)
+        #[clap(long = "skip-mime-class")]
+        skip_mime_classes: Vec<MimeClass>,
+        /// Maximum number of rows per item CSV part
+        #[clap(long)]
+        rotate_rows: Option<u64>,
+        /// Maximum number of bytes per item CSV part
+        #[clap(long)]
+        rotate_bytes: Option<u64>,
+        /// Compression for session CSV files (none, gzip, or zstd if enabled)
+        #[clap(long, default_value = "none")]
+        compress: OutputCompression,
+        /// Directory for saving original response headers as JSON sidecar files
+        #[clap(long)]
+        headers: Option<String>,
+        /// Blocked query registry file (known blocked queries will be skipped)
+        #[clap(long)]
+        blocked: Option<String>,
+        /// File of digests of junk captures to set aside (one per line,
+        /// optionally followed by a label)
+        #[clap(long)]
+        poison: Option<String>,
+        /// URL to post run milestones to as JSON
+        #[clap(long)]
+        webhook: Option<url::Url>,
+        /// Slack incoming webhook URL for run milestone messages
+        #[clap(long)]
+        slack_webhook: Option<url::Url>,
+        /// Directory for caching CDX search results between runs
+        #[clap(long)]
+        cdx_cache: Option<String>,
+        /// Number of seconds after which cached CDX search results expire
+        #[clap(long, default_value = "86400")]
+        cdx_cache_ttl: u64,
+        /// Search, resolve redirects, and download in a single streaming pipeline
+        #[clap(long, requires = "query")]
+        pipeline: bool,
+        /// Stop after downloading this many items
+        #[clap(long)]
+        max_items: Option<usize>,
+        /// Stop after downloading this many bytes
+        #[clap(long)]
+        max_bytes: Option<u64>,
+        /// Stop after running for this many seconds
+        #[clap(long)]
+        max_secs: Option<u64>,
+        /// Which captures of each URL to download (all, first, year, or month)
+        #[clap(long, default_value = "all")]
+        select: CaptureSelection,
+        /// Write data files without file names or timestamps, so that identical
+        /// content always produces identical files
+        #[clap(long)]
+        deterministic: bool,
+        /// File containing a hex-encoded key for encrypting downloaded data
+        #[cfg(feature = "encryption")]
+        #[clap(long)]
+        key_file: Option<String>,
     },
 }
 
@@ -2,6 +2,9 @@ use async_std::task::sleep;
 use fantoccini::{error::CmdError, Client as FClient, Locator};
 use std::time::Duration;
 
+#[cfg(feature = "render")]
+pub mod render;
+
 pub struct Client {
     underlying: FClient,
 }
@@ -0,0 +1,149 @@
+//! Rendering of stored HTML captures to PNG screenshots and PDF documents.
+//!
+//! Captures are served from a temporary local HTTP server (with any Wayback
+//! Machine banner markup removed) and loaded in a WebDriver-controlled browser.
+
+use crate::store::data::Store;
+use data_encoding::BASE64;
+use fantoccini::{error::CmdError, wd::WebDriverCompatibleCommand, Client};
+use reqwest::Method;
+use std::borrow::Cow;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use url::Url;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Item not found: {0}")]
+    NotFound(String),
+    #[error("I/O error: {0:?}")]
+    Io(#[from] std::io::Error),
+    #[error("WebDriver error: {0:?}")]
+    WebDriver(#[from] CmdError),
+    #[error("Invalid PDF response")]
+    InvalidPdf,
+}
+
+lazy_static::lazy_static! {
+    static ref BANNER_RES: Vec<regex::Regex> = vec![
+        regex::Regex::new(
+            r"(?s)<!-- BEGIN WAYBACK TOOLBAR INSERT -->.*?<!-- END WAYBACK TOOLBAR INSERT -->",
+        )
+        .unwrap(),
+        regex::Regex::new(
+            r"(?s)<script[^>]*archive\.org/includes/.*?<!-- End Wayback Rewrite JS Include -->",
+        )
+        .unwrap(),
+    ];
+}
+
+/// Remove the toolbar and script inserts that the Wayback Machine adds to
+/// non-original captures.
+pub fn strip_banner(html: &str) -> Cow<'_, str> {
+    let mut result = Cow::Borrowed(html);
+
+    for re in BANNER_RES.iter() {
+        if let Cow::Owned(stripped) = re.replace_all(&result, "") {
+            result = Cow::Owned(stripped);
+        }
+    }
+
+    result
+}
+
+#[derive(Debug)]
+struct PrintCommand;
+
+impl WebDriverCompatibleCommand for PrintCommand {
+    fn endpoint(&self, base_url: &Url, session_id: Option<&str>) -> Result<Url, url::ParseError> {
+        base_url.join(&format!("session/{}/print", session_id.unwrap_or_default()))
+    }
+
+    fn method_and_body(&self, _request_url: &Url) -> (Method, Option<String>) {
+        (Method::POST, Some("{}".to_string()))
+    }
+}
+
+pub struct Renderer {
+    client: Client,
+    store: Store,
+}
+
+impl Renderer {
+    pub fn new(client: Client, store: Store) -> Self {
+        Self { client, store }
+    }
+
+    /// Render the capture with the given digest as a PNG screenshot.
+    pub async fn screenshot(&self, digest: &str) -> Result<Vec<u8>, Error> {
+        self.with_capture(digest, || self.client.screenshot()).await
+    }
+
+    /// Render the capture with the given digest as a PDF document.
+    pub async fn pdf(&self, digest: &str) -> Result<Vec<u8>, Error> {
+        let result = self
+            .with_capture(digest, || self.client.issue_cmd(PrintCommand))
+            .await?;
+
+        result
+            .as_str()
+            .and_then(|encoded| BASE64.decode(encoded.as_bytes()).ok())
+            .ok_or(Error::InvalidPdf)
+    }
+
+    async fn with_capture<T, F, Fut>(&self, digest: &str, f: F) -> Result<T, Error>
+    where
+        F: FnOnce() -> Fut,
+        Fut: std::future::Future<Output = Result<T, CmdError>>,
+    {
+        let bytes = self
+            .store
+            .extract_bytes(digest)
+            .ok_or_else(|| Error::NotFound(digest.to_string()))??;
+        let html = strip_banner(&String::from_utf8_lossy(&bytes)).into_owned();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let address = listener.local_addr()?;
+        let server = tokio::spawn(serve(listener, html));
+
+        let result = async {
+            self.client.goto(&format!("http://{}/", address)).await?;
+            f().await
+        }
+        .await;
+
+        server.abort();
+
+        Ok(result?)
+    }
+}
+
+async fn serve(listener: TcpListener, html: String) -> std::io::Result<()> {
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        let mut buffer = [0; 4096];
+        // We serve the same page for every request, so the request is ignored.
+        let _ = stream.read(&mut buffer).await?;
+
+        let header = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            html.len()
+        );
+        stream.write_all(header.as_bytes()).await?;
+        stream.write_all(html.as_bytes()).await?;
+        stream.shutdown().await?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn strip_banner() {
+        let html = "<html><body><!-- BEGIN WAYBACK TOOLBAR INSERT --><div>toolbar</div><!-- END WAYBACK TOOLBAR INSERT --><p>Hi</p></body></html>";
+
+        assert_eq!(
+            super::strip_banner(html),
+            "<html><body><p>Hi</p></body></html>"
+        );
+    }
+}
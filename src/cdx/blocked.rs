@@ -0,0 +1,216 @@
+//! A registry of queries that the CDX server has blocked.
+//!
+//! Queries are recorded with a normalized host (without scheme, port, or `www.`
+//! prefix) and path, and a block on a path also applies to any query beneath
+//! it. A block on a subdomain wildcard (`*.example.com`) applies to the domain
+//! and all of its subdomains. Blocks are occasionally lifted, so entries expire
+//! after a configurable duration.
+
+use super::Error;
+use chrono::Utc;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The default time after which a blocked query will be tried again.
+pub const DEFAULT_EXPIRY: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+pub struct BlockedRegistry {
+    path: Option<PathBuf>,
+    expiry: Duration,
+    /// Blocked (normalized) queries with the Unix timestamp at which the block
+    /// was seen.
+    entries: BTreeMap<String, i64>,
+}
+
+impl BlockedRegistry {
+    /// Create an empty registry that is not persisted.
+    pub fn new(expiry: Duration) -> Self {
+        Self {
+            path: None,
+            expiry,
+            entries: BTreeMap::new(),
+        }
+    }
+
+    /// Load a registry from the given path (which does not need to exist).
+    ///
+    /// The registry will be saved to the same path by [`BlockedRegistry::save`].
+    pub fn load<P: AsRef<Path>>(path: P, expiry: Duration) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let entries = match File::open(&path) {
+            Ok(file) => serde_json::from_reader(BufReader::new(file))?,
+            Err(error) if error.kind() == ErrorKind::NotFound => BTreeMap::new(),
+            Err(error) => return Err(error.into()),
+        };
+
+        Ok(Self {
+            path: Some(path),
+            expiry,
+            entries,
+        })
+    }
+
+    /// Save the registry (without expired entries) if it was loaded from a file.
+    pub fn save(&self) -> Result<(), Error> {
+        if let Some(path) = &self.path {
+            let now = Utc::now().timestamp();
+            let current = self
+                .entries
+                .iter()
+                .filter(|(_, seen)| !self.is_expired(**seen, now))
+                .collect::<BTreeMap<_, _>>();
+
+            serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), &current)?;
+        }
+
+        Ok(())
+    }
+
+    fn is_expired(&self, seen: i64, now: i64) -> bool {
+        now.saturating_sub(seen) >= self.expiry.as_secs() as i64
+    }
+
+    /// Check whether the query, or a query for a path containing it, is known
+    /// to be blocked.
+    pub fn is_blocked(&self, query: &str) -> bool {
+        let now = Utc::now().timestamp();
+        let key = normalize(query);
+
+        let is_current = |key: &str| {
+            self.entries
+                .get(key)
+                .is_some_and(|seen| !self.is_expired(*seen, now))
+        };
+
+        // Entries saved before queries were normalized are matched exactly.
+        std::iter::once(query)
+            .chain(path_prefixes(&key))
+            .any(is_current)
+            || domain_wildcards(&key).any(|key| is_current(&key))
+    }
+
+    /// Record that the query has been blocked.
+    pub fn insert(&mut self, query: &str) {
+        self.entries
+            .insert(normalize(query), Utc::now().timestamp());
+    }
+
+    /// Forget a blocked query.
+    pub fn remove(&mut self, query: &str) -> bool {
+        let removed_normalized = self.entries.remove(&normalize(query)).is_some();
+        let removed_exact = self.entries.remove(query).is_some();
+
+        removed_normalized || removed_exact
+    }
+
+    /// All (normalized) queries that are currently known to be blocked.
+    pub fn queries(&self) -> Vec<&str> {
+        let now = Utc::now().timestamp();
+
+        self.entries
+            .iter()
+            .filter(|(_, seen)| !self.is_expired(**seen, now))
+            .map(|(query, _)| query.as_str())
+            .collect()
+    }
+}
+
+/// Normalize a query to its lowercase host (without scheme, port, or `www.`
+/// prefix, but keeping any subdomain wildcard) and path (without query
+/// string, fragment, or trailing wildcard).
+fn normalize(query: &str) -> String {
+    let query = query.trim();
+    let without_scheme = query.split_once("://").map_or(query, |(_, rest)| rest);
+    let without_params = without_scheme.split(['?', '#']).next().unwrap_or_default();
+    let (authority, path) = without_params
+        .split_once('/')
+        .unwrap_or((without_params, ""));
+
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let host = host.split_once(':').map_or(host, |(host, _)| host);
+    let host = host.to_lowercase();
+    let (wildcard, host) = match host.strip_prefix("*.") {
+        Some(host) => ("*.", host),
+        None => ("", host.as_str()),
+    };
+    let host = host.strip_prefix("www.").unwrap_or(host);
+
+    let path = path.trim_end_matches(['*', '/']);
+
+    if path.is_empty() {
+        format!("{}{}", wildcard, host)
+    } else {
+        format!("{}{}/{}", wildcard, host, path)
+    }
+}
+
+/// The normalized query and each of its parent paths (down to the host).
+fn path_prefixes(key: &str) -> impl Iterator<Item = &str> {
+    std::iter::once(key).chain(key.rmatch_indices('/').map(move |(i, _)| &key[..i]))
+}
+
+/// The subdomain wildcards for the normalized query's host and each of its
+/// parent domains.
+fn domain_wildcards(key: &str) -> impl Iterator<Item = String> + '_ {
+    let host = key.split('/').next().unwrap_or_default();
+    let host = host.trim_start_matches("*.");
+
+    std::iter::once(host)
+        .chain(host.match_indices('.').map(move |(i, _)| &host[i + 1..]))
+        .map(|domain| format!("*.{}", domain))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BlockedRegistry;
+    use std::time::Duration;
+
+    #[test]
+    fn persistence_and_expiry() {
+        let dir = crate::fixtures::temp_dir("blocked").unwrap();
+        let path = dir.join("blocked.json");
+
+        let mut registry = BlockedRegistry::load(&path, Duration::from_secs(60)).unwrap();
+        registry.insert("example.com/*");
+        registry.save().unwrap();
+
+        let registry = BlockedRegistry::load(&path, Duration::from_secs(60)).unwrap();
+        assert!(registry.is_blocked("example.com/*"));
+        assert!(!registry.is_blocked("example.org/*"));
+
+        let registry = BlockedRegistry::load(&path, Duration::ZERO).unwrap();
+        assert!(!registry.is_blocked("example.com/*"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn normalization() {
+        let mut registry = BlockedRegistry::new(Duration::from_secs(60));
+        registry.insert("https://www.Example.com:443/user/*");
+
+        assert!(registry.is_blocked("example.com/user"));
+        assert!(registry.is_blocked("http://www.example.com/user/status/*"));
+        assert!(!registry.is_blocked("example.com/other/*"));
+        assert!(!registry.is_blocked("example.com/*"));
+        assert_eq!(registry.queries(), vec!["example.com/user"]);
+
+        registry.insert("*.example.org");
+        assert!(registry.is_blocked("example.org/anything/*"));
+        assert!(registry.is_blocked("https://sub.example.org/anything"));
+        assert!(registry.is_blocked("*.deep.sub.example.org"));
+        assert!(!registry.is_blocked("example.net"));
+        assert!(!registry.is_blocked("sub.example.com/user/*"));
+
+        registry.insert("example.net");
+        assert!(!registry.is_blocked("sub.example.net"));
+
+        assert!(registry.remove("www.example.com/user/*"));
+        assert!(!registry.is_blocked("example.com/user"));
+    }
+}
@@ -0,0 +1,137 @@
+//! A disk-backed cache for CDX search results.
+//!
+//! Results are stored as JSON rows in files named by a digest of the search
+//! URL, and expire after a configurable duration.
+
+use super::Error;
+use serde::{Deserialize, Serialize};
+use std::fs::{create_dir_all, read_dir, remove_file, rename, File};
+use std::io::{BufReader, BufWriter, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+#[derive(Deserialize, Serialize)]
+struct Entry {
+    url: String,
+    rows: Vec<Vec<String>>,
+}
+
+pub struct ResponseCache {
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+impl ResponseCache {
+    /// Open a cache in the given directory (which is created if necessary).
+    pub fn new<P: AsRef<Path>>(dir: P, ttl: Duration) -> Result<Self, Error> {
+        create_dir_all(&dir)?;
+
+        Ok(Self {
+            dir: dir.as_ref().to_path_buf(),
+            ttl,
+        })
+    }
+
+    fn path(&self, url: &str) -> PathBuf {
+        let digest = crate::digest::compute_digest(&mut url.as_bytes())
+            .expect("Digest computation should not fail for in-memory input");
+
+        self.dir.join(format!("{}.json", digest))
+    }
+
+    fn is_expired(&self, path: &Path) -> std::io::Result<bool> {
+        let modified = path.metadata()?.modified()?;
+
+        Ok(SystemTime::now()
+            .duration_since(modified)
+            .is_ok_and(|age| age >= self.ttl))
+    }
+
+    /// Look up the rows for a search URL, if they are cached and not expired.
+    ///
+    /// Unreadable entries are logged and treated as missing.
+    pub(super) fn get(&self, url: &str) -> Option<Vec<Vec<String>>> {
+        let path = self.path(url);
+
+        let read = || -> Result<Option<Entry>, Error> {
+            if self.is_expired(&path)? {
+                return Ok(None);
+            }
+
+            Ok(Some(serde_json::from_reader(BufReader::new(File::open(
+                &path,
+            )?))?))
+        };
+
+        match read() {
+            Ok(entry) => entry
+                .filter(|entry| entry.url == url)
+                .map(|entry| entry.rows),
+            Err(Error::Io(error)) if error.kind() == ErrorKind::NotFound => None,
+            Err(error) => {
+                log::warn!("Ignoring unreadable cache entry {:?}: {:?}", path, error);
+                None
+            }
+        }
+    }
+
+    /// Save the rows for a search URL.
+    pub(super) fn put(&self, url: &str, rows: Vec<Vec<String>>) -> Result<(), Error> {
+        let path = self.path(url);
+        let temp_path = path.with_extension("json.tmp");
+        let entry = Entry {
+            url: url.to_string(),
+            rows,
+        };
+
+        serde_json::to_writer(BufWriter::new(File::create(&temp_path)?), &entry)?;
+        rename(temp_path, path)?;
+
+        Ok(())
+    }
+
+    /// Remove expired entries, returning the number removed.
+    pub fn remove_expired(&self) -> Result<usize, Error> {
+        let mut count = 0;
+
+        for entry in read_dir(&self.dir)? {
+            let path = entry?.path();
+
+            if path.extension().is_some_and(|ext| ext == "json") && self.is_expired(&path)? {
+                remove_file(path)?;
+                count += 1;
+            }
+        }
+
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ResponseCache;
+    use std::time::Duration;
+
+    #[test]
+    fn round_trip_and_expiry() {
+        let dir = crate::fixtures::temp_dir("cdx-cache").unwrap();
+        let url = "http://web.archive.org/cdx/search/cdx?url=example.com";
+        let rows = vec![
+            vec!["original".to_string()],
+            vec!["https://example.com/".to_string()],
+        ];
+
+        let cache = ResponseCache::new(&dir, Duration::from_secs(60)).unwrap();
+        assert_eq!(cache.get(url), None);
+
+        cache.put(url, rows.clone()).unwrap();
+        assert_eq!(cache.get(url), Some(rows));
+        assert_eq!(cache.get("http://example.com/other"), None);
+
+        let expired = ResponseCache::new(&dir, Duration::ZERO).unwrap();
+        assert_eq!(expired.get(url), None);
+        assert_eq!(expired.remove_expired().unwrap(), 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,253 @@
+//! A client for the Common Crawl URL index.
+//!
+//! The Common Crawl index server (pywb) supports the same query parameters as
+//! the Wayback Machine's CDX server, including the field names used in
+//! filters, but returns one JSON object per line, and pages results by page
+//! number instead of by resume key.
+//!
+//! Note that Common Crawl lengths are the compressed lengths of the WARC
+//! records, not the lengths of the captured content.
+
+use super::{CdxQuery, Error, ItemExt, RateLimit};
+use crate::{util::retry_future, Item};
+use futures::{Stream, StreamExt, TryStreamExt};
+use reqwest::{Client, Response, StatusCode};
+use serde::Deserialize;
+use url::Url;
+
+const DEFAULT_COMMON_CRAWL_BASE: &str = "https://index.commoncrawl.org/";
+const DATA_BASE: &str = "https://data.commoncrawl.org/";
+
+/// A Common Crawl index collection (one per crawl).
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub struct Collection {
+    /// The crawl identifier (e.g. `CC-MAIN-2024-10`).
+    pub id: String,
+    pub name: String,
+    #[serde(rename = "cdx-api")]
+    pub cdx_api: String,
+}
+
+#[derive(Deserialize)]
+struct Record {
+    url: Option<String>,
+    timestamp: Option<String>,
+    digest: Option<String>,
+    mime: Option<String>,
+    status: Option<String>,
+    length: Option<String>,
+    offset: Option<String>,
+    filename: Option<String>,
+}
+
+impl Record {
+    fn into_item_ext(self) -> Result<ItemExt, Error> {
+        let item = Item::parse_optional_record(
+            self.url.as_deref(),
+            self.timestamp.as_deref(),
+            self.digest.as_deref(),
+            self.mime.as_deref(),
+            self.length.as_deref(),
+            self.status.as_deref(),
+        )?;
+
+        let offset = self
+            .offset
+            .map(|value| value.parse().map_err(|_| Error::InvalidOffset(value)))
+            .transpose()?;
+
+        Ok(ItemExt {
+            item,
+            robot_flags: None,
+            redirect: None,
+            offset,
+            filename: self.filename,
+            dupe_count: None,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+struct PageCount {
+    pages: usize,
+}
+
+pub struct CommonCrawlClient {
+    base: Url,
+    collection: String,
+    underlying: Client,
+}
+
+impl CommonCrawlClient {
+    /// Create a client for the given collection (e.g. `CC-MAIN-2024-10`).
+    pub fn new(collection: &str) -> Result<Self, Error> {
+        Ok(Self {
+            base: Url::parse(DEFAULT_COMMON_CRAWL_BASE)?,
+            collection: collection.to_string(),
+            underlying: Client::builder()
+                .user_agent(crate::util::DEFAULT_USER_AGENT)
+                .build()?,
+        })
+    }
+
+    /// Use a different index server.
+    pub fn with_base(mut self, base: &str) -> Result<Self, Error> {
+        self.base = Url::parse(base)?;
+        Ok(self)
+    }
+
+    /// Use an existing HTTP client.
+    pub fn with_client(mut self, client: Client) -> Self {
+        self.underlying = client;
+        self
+    }
+
+    /// List the available collections, most recent first.
+    pub async fn collections(&self) -> Result<Vec<Collection>, Error> {
+        let url = self.base.join("collinfo.json")?;
+        let contents = retry_future(|| async {
+            Ok::<_, Error>(
+                Self::check_status(self.underlying.get(url.clone()).send().await?)?
+                    .text()
+                    .await?,
+            )
+        })
+        .await?;
+
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    pub async fn search(&self, query: &CdxQuery) -> Result<Vec<Item>, Error> {
+        self.stream_search(query).try_collect().await
+    }
+
+    /// Search for items along with the WARC filename and offset for each.
+    pub async fn search_ext(&self, query: &CdxQuery) -> Result<Vec<ItemExt>, Error> {
+        self.stream_search_ext(query).try_collect().await
+    }
+
+    /// Stream the results of a query, one page at a time.
+    pub fn stream_search<'a>(
+        &'a self,
+        query: &'a CdxQuery,
+    ) -> impl Stream<Item = Result<Item, Error>> + 'a {
+        self.stream_search_ext(query).map_ok(|item| item.item)
+    }
+
+    pub fn stream_search_ext<'a>(
+        &'a self,
+        query: &'a CdxQuery,
+    ) -> impl Stream<Item = Result<ItemExt, Error>> + 'a {
+        futures::stream::once(retry_future(move || self.page_count(query)))
+            .map_ok(move |pages| {
+                futures::stream::iter(0..pages)
+                    .then(move |page| retry_future(move || self.get_page(query, page)))
+                    .map_ok(|items| futures::stream::iter(items.into_iter().map(Ok)))
+                    .try_flatten()
+            })
+            .try_flatten()
+    }
+
+    /// The download URL for the WARC file containing a result.
+    pub fn warc_url(item: &ItemExt) -> Option<String> {
+        item.filename
+            .as_ref()
+            .map(|filename| format!("{}{}", DATA_BASE, filename))
+    }
+
+    fn query_url(&self, query: &CdxQuery, extra_params: &[(&str, String)]) -> Result<Url, Error> {
+        let mut url = self.base.join(&format!("{}-index", self.collection))?;
+        url.query_pairs_mut()
+            .extend_pairs(query.params().into_iter().filter(|(name, _)| *name != "fl"))
+            .extend_pairs(extra_params);
+
+        Ok(url)
+    }
+
+    async fn page_count(&self, query: &CdxQuery) -> Result<usize, Error> {
+        let url = self.query_url(query, &[("showNumPages", "true".to_string())])?;
+        log::info!("Common Crawl page count URL: {}", url);
+
+        match Self::send(&self.underlying, url).await? {
+            Some(contents) => Ok(serde_json::from_str::<PageCount>(&contents)?.pages),
+            None => Ok(0),
+        }
+    }
+
+    async fn get_page(&self, query: &CdxQuery, page: usize) -> Result<Vec<ItemExt>, Error> {
+        let url = self.query_url(query, &[("page", page.to_string())])?;
+        log::info!("Common Crawl search URL: {}", url);
+
+        match Self::send(&self.underlying, url).await? {
+            Some(contents) => decode_lines(&contents),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Returns an empty value when there are no captures.
+    async fn send(client: &Client, url: Url) -> Result<Option<String>, Error> {
+        let response = client.get(url).send().await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            Ok(None)
+        } else {
+            Ok(Some(Self::check_status(response)?.text().await?))
+        }
+    }
+
+    fn check_status(response: Response) -> Result<Response, Error> {
+        match RateLimit::of(&response) {
+            Some(limit) => Err(Error::RateLimited(limit)),
+            None => Ok(response.error_for_status()?),
+        }
+    }
+}
+
+fn decode_lines(contents: &str) -> Result<Vec<ItemExt>, Error> {
+    contents
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str::<Record>(line)?.into_item_ext())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_lines, CommonCrawlClient};
+    use crate::cdx::{CdxQuery, Filter};
+
+    #[test]
+    fn decode() {
+        let items = decode_lines(
+            r#"{"urlkey": "com,example)/", "timestamp": "20240221132316", "url": "https://example.com/", "mime": "text/html", "mime-detected": "text/html", "status": "200", "digest": "HMPF7YKVZA5CDW4NGQMMEZFFSMIJMMDX", "length": "1253", "offset": "53926405", "filename": "crawl-data/CC-MAIN-2024-10/segments/1707947473518.6/warc/CC-MAIN-20240221134259-20240221164259-00720.warc.gz", "languages": "eng", "encoding": "UTF-8"}
+{"urlkey": "com,example)/", "timestamp": "20240229042911", "url": "https://example.com/", "mime": "unk", "mime-detected": "application/x-empty", "status": "301", "digest": "3I42H3S6NNFQ2MSVX7XZKYAYSCX5QBYJ", "length": "547", "offset": "4711", "filename": "crawl-data/CC-MAIN-2024-10/segments/1707947474775.80/crawldiagnostics/CC-MAIN-20240229003536-20240229033536-00411.warc.gz", "redirect": "https://www.example.com/"}
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].item.status, Some(200));
+        assert_eq!(items[0].item.length, 1253);
+        assert_eq!(items[1].offset, Some(4711));
+        assert_eq!(
+            CommonCrawlClient::warc_url(&items[0]).as_deref(),
+            Some("https://data.commoncrawl.org/crawl-data/CC-MAIN-2024-10/segments/1707947473518.6/warc/CC-MAIN-20240221134259-20240221164259-00720.warc.gz")
+        );
+    }
+
+    #[test]
+    fn query_url() {
+        let client = CommonCrawlClient::new("CC-MAIN-2024-10").unwrap();
+        let url = client
+            .query_url(
+                &CdxQuery::new("example.com").with_filter(Filter::status(200)),
+                &[("page", "1".to_string())],
+            )
+            .unwrap();
+
+        assert_eq!(
+            url.as_str(),
+            "https://index.commoncrawl.org/CC-MAIN-2024-10-index?url=example.com&filter=statuscode%3A200&output=json&page=1"
+        );
+    }
+}
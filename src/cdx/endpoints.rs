@@ -0,0 +1,167 @@
+//! Prioritized CDX endpoints with failover.
+//!
+//! Requests go to the first available endpoint. An endpoint becomes
+//! unavailable for a cooldown period after several consecutive failures, and
+//! becomes available again after the cooldown or a successful request.
+
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// Consecutive failures before an endpoint is skipped.
+const FAILURE_THRESHOLD: u32 = 2;
+const COOLDOWN: Duration = Duration::from_secs(300);
+
+/// The health of a CDX endpoint.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EndpointStatus {
+    pub base: Url,
+    pub consecutive_failures: u32,
+    pub available: bool,
+}
+
+#[derive(Default)]
+struct Health {
+    consecutive_failures: u32,
+    retry_at: Option<Instant>,
+}
+
+impl Health {
+    fn retry_at(&self, now: Instant) -> Option<Instant> {
+        self.retry_at.filter(|retry_at| *retry_at > now)
+    }
+}
+
+struct Endpoint {
+    base: Url,
+    health: Mutex<Health>,
+}
+
+impl Endpoint {
+    fn new(base: Url) -> Self {
+        Self {
+            base,
+            health: Mutex::default(),
+        }
+    }
+
+    /// When the endpoint will be available, or `None` if it is available now.
+    fn retry_at(&self, now: Instant) -> Option<Instant> {
+        self.health.lock().unwrap().retry_at(now)
+    }
+}
+
+pub(super) struct Endpoints {
+    endpoints: Vec<Endpoint>,
+}
+
+impl Endpoints {
+    pub(super) fn new(base: Url) -> Self {
+        Self {
+            endpoints: vec![Endpoint::new(base)],
+        }
+    }
+
+    pub(super) fn primary(&self) -> &Url {
+        &self.endpoints[0].base
+    }
+
+    pub(super) fn primary_mut(&mut self) -> &mut Url {
+        &mut self.endpoints[0].base
+    }
+
+    pub(super) fn push(&mut self, base: Url) {
+        self.endpoints.push(Endpoint::new(base));
+    }
+
+    /// Select the endpoint for the next request.
+    ///
+    /// If no endpoint is available, the one that will be available soonest is
+    /// used.
+    pub(super) fn select(&self) -> (usize, &Url) {
+        let now = Instant::now();
+        let mut soonest: Option<(usize, Instant)> = None;
+
+        for (index, endpoint) in self.endpoints.iter().enumerate() {
+            match endpoint.retry_at(now) {
+                None => return (index, &endpoint.base),
+                Some(retry_at) => {
+                    if soonest.is_none_or(|(_, soonest_at)| retry_at < soonest_at) {
+                        soonest = Some((index, retry_at));
+                    }
+                }
+            }
+        }
+
+        let index = soonest.map_or(0, |(index, _)| index);
+        (index, &self.endpoints[index].base)
+    }
+
+    pub(super) fn record_success(&self, index: usize) {
+        *self.endpoints[index].health.lock().unwrap() = Health::default();
+    }
+
+    pub(super) fn record_failure(&self, index: usize) {
+        let endpoint = &self.endpoints[index];
+        let mut health = endpoint.health.lock().unwrap();
+        health.consecutive_failures += 1;
+
+        if health.consecutive_failures >= FAILURE_THRESHOLD && self.endpoints.len() > 1 {
+            log::warn!(
+                "CDX endpoint unavailable after {} failures: {}",
+                health.consecutive_failures,
+                endpoint.base
+            );
+            health.retry_at = Some(Instant::now() + COOLDOWN);
+        }
+    }
+
+    pub(super) fn status(&self) -> Vec<EndpointStatus> {
+        let now = Instant::now();
+
+        self.endpoints
+            .iter()
+            .map(|endpoint| {
+                let health = endpoint.health.lock().unwrap();
+
+                EndpointStatus {
+                    base: endpoint.base.clone(),
+                    consecutive_failures: health.consecutive_failures,
+                    available: health.retry_at(now).is_none(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Endpoints;
+    use url::Url;
+
+    #[test]
+    fn failover() {
+        let primary = Url::parse("https://web.archive.org/cdx/search/cdx").unwrap();
+        let fallback = Url::parse("http://localhost:8080/pywb/cdx").unwrap();
+        let mut endpoints = Endpoints::new(primary.clone());
+        endpoints.push(fallback.clone());
+
+        assert_eq!(endpoints.select(), (0, &primary));
+
+        endpoints.record_failure(0);
+        assert_eq!(endpoints.select(), (0, &primary));
+
+        endpoints.record_failure(0);
+        assert_eq!(endpoints.select(), (1, &fallback));
+        assert!(!endpoints.status()[0].available);
+
+        // With every endpoint unavailable, the one that recovers first is used.
+        endpoints.record_failure(1);
+        endpoints.record_failure(1);
+        assert_eq!(endpoints.select(), (0, &primary));
+
+        endpoints.record_success(0);
+        assert_eq!(endpoints.status()[0].consecutive_failures, 0);
+        assert!(endpoints.status()[0].available);
+    }
+}
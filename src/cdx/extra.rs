@@ -0,0 +1,131 @@
+//! Additional CDX columns beyond the ones used for items.
+
+use super::Error;
+use crate::Item;
+
+/// A CDX field that can be requested in addition to the item fields.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ExtraField {
+    RobotFlags,
+    Redirect,
+    /// The offset of the record in its WARC file.
+    Offset,
+    /// The name of the WARC file containing the record.
+    Filename,
+    /// The number of earlier captures of the URL with the same digest.
+    DupeCount,
+}
+
+impl ExtraField {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ExtraField::RobotFlags => "robotflags",
+            ExtraField::Redirect => "redirect",
+            ExtraField::Offset => "offset",
+            ExtraField::Filename => "filename",
+            ExtraField::DupeCount => "dupecount",
+        }
+    }
+}
+
+/// An item with any additional fields that were requested.
+///
+/// The CDX server uses `-` for missing values, which are represented here as
+/// `None`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ItemExt {
+    pub item: Item,
+    pub robot_flags: Option<String>,
+    pub redirect: Option<String>,
+    pub offset: Option<u64>,
+    pub filename: Option<String>,
+    pub dupe_count: Option<u64>,
+}
+
+impl ItemExt {
+    /// Decode a row containing the item fields followed by the given extra fields.
+    pub(super) fn from_row(row: &[String], extras: &[ExtraField]) -> Result<Self, Error> {
+        let field = |i: usize| row.get(i).map(|value| value.as_str());
+
+        let item = Item::parse_optional_record(
+            field(0),
+            field(1),
+            field(2),
+            field(3),
+            field(4),
+            field(5),
+        )?;
+
+        let mut result = Self {
+            item,
+            robot_flags: None,
+            redirect: None,
+            offset: None,
+            filename: None,
+            dupe_count: None,
+        };
+
+        for (i, extra) in extras.iter().enumerate() {
+            let value = field(6 + i)
+                .filter(|value| *value != "-" && !value.is_empty())
+                .map(|value| value.to_string());
+
+            match extra {
+                ExtraField::RobotFlags => result.robot_flags = value,
+                ExtraField::Redirect => result.redirect = value,
+                ExtraField::Offset => {
+                    result.offset = value
+                        .map(|value| value.parse().map_err(|_| Error::InvalidOffset(value)))
+                        .transpose()?
+                }
+                ExtraField::Filename => result.filename = value,
+                ExtraField::DupeCount => {
+                    result.dupe_count = value
+                        .map(|value| value.parse().map_err(|_| Error::InvalidDupeCount(value)))
+                        .transpose()?
+                }
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ExtraField, ItemExt};
+
+    #[test]
+    fn from_row() {
+        let row = [
+            "https://example.com/",
+            "20200101000000",
+            "2G3EOT7X6IEQZXKSM3OJJDW6RBCHB7YE",
+            "text/html",
+            "1234",
+            "200",
+            "-",
+            "5678",
+            "EXAMPLE-20200101000000-00000.warc.gz",
+        ]
+        .map(String::from);
+
+        let item = ItemExt::from_row(
+            &row,
+            &[
+                ExtraField::RobotFlags,
+                ExtraField::Offset,
+                ExtraField::Filename,
+            ],
+        )
+        .unwrap();
+
+        assert_eq!(item.item.length, 1234);
+        assert_eq!(item.robot_flags, None);
+        assert_eq!(item.offset, Some(5678));
+        assert_eq!(
+            item.filename.as_deref(),
+            Some("EXAMPLE-20200101000000-00000.warc.gz")
+        );
+    }
+}
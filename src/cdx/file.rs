@@ -0,0 +1,231 @@
+//! Streaming items from local CDX files, such as the `.cdx.gz` indexes in
+//! Internet Archive crawl items.
+//!
+//! Files may be plain-text CDX (with or without a header) or CDXJ, and may be
+//! gzipped (including multi-member gzip files). The format is detected from
+//! the first non-empty line, and malformed lines are reported with their line
+//! numbers without ending the stream.
+
+use super::formats::PlainFormat;
+use crate::Item;
+use flate2::read::MultiGzDecoder;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Lines, Read};
+use std::path::Path;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("I/O error at line {number}: {error:?}")]
+    Io { number: usize, error: io::Error },
+    #[error("Malformed line {number}: {error}")]
+    Malformed {
+        number: usize,
+        line: String,
+        error: super::Error,
+    },
+}
+
+impl Error {
+    /// The 1-based line number the error occurred at.
+    pub fn line_number(&self) -> usize {
+        match self {
+            Error::Io { number, .. } => *number,
+            Error::Malformed { number, .. } => *number,
+        }
+    }
+}
+
+enum LineFormat {
+    Cdxj,
+    Plain(PlainFormat),
+}
+
+impl LineFormat {
+    fn detect(line: &str) -> Option<Self> {
+        if line
+            .splitn(3, ' ')
+            .nth(2)
+            .is_some_and(|rest| rest.starts_with('{'))
+        {
+            Some(LineFormat::Cdxj)
+        } else {
+            PlainFormat::for_field_count(line.split(' ').count()).map(LineFormat::Plain)
+        }
+    }
+
+    fn parse(&self, line: &str) -> Result<Item, super::Error> {
+        match self {
+            LineFormat::Cdxj => Ok(Item::from_cdxj(line)?),
+            LineFormat::Plain(format) => format.parse_line(line),
+        }
+    }
+}
+
+/// An iterator over the items in a CDX source.
+pub struct CdxReader<R> {
+    lines: Lines<R>,
+    format: Option<LineFormat>,
+    line_number: usize,
+    malformed_count: usize,
+}
+
+impl CdxReader<BufReader<Box<dyn Read + Send>>> {
+    /// Open a CDX file, decompressing it if it is gzipped.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_reader(File::open(path)?)
+    }
+
+    /// Read CDX lines from a source, decompressing it if it is gzipped.
+    pub fn from_reader<S: Read + Send + 'static>(source: S) -> io::Result<Self> {
+        let mut buffered = BufReader::new(source);
+        let gzipped = buffered.fill_buf()?.starts_with(&GZIP_MAGIC);
+
+        let reader: Box<dyn Read + Send> = if gzipped {
+            Box::new(MultiGzDecoder::new(buffered))
+        } else {
+            Box::new(buffered)
+        };
+
+        Ok(Self::new(BufReader::new(reader)))
+    }
+}
+
+impl<R: BufRead> CdxReader<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            lines: reader.lines(),
+            format: None,
+            line_number: 0,
+            malformed_count: 0,
+        }
+    }
+
+    /// The number of malformed lines seen so far.
+    pub fn malformed_count(&self) -> usize {
+        self.malformed_count
+    }
+
+    /// Iterate over the valid items, passing malformed lines to the given
+    /// function.
+    ///
+    /// I/O errors end the iteration.
+    pub fn items_reporting<F: FnMut(&Error)>(
+        self,
+        mut report: F,
+    ) -> impl Iterator<Item = io::Result<Item>> {
+        self.filter_map(move |result| match result {
+            Ok(item) => Some(Ok(item)),
+            Err(Error::Io { error, .. }) => Some(Err(error)),
+            Err(error) => {
+                report(&error);
+                None
+            }
+        })
+    }
+
+    fn malformed(&mut self, line: String, error: super::Error) -> Error {
+        self.malformed_count += 1;
+
+        Error::Malformed {
+            number: self.line_number,
+            line,
+            error,
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for CdxReader<R> {
+    type Item = Result<Item, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let line = self.lines.next()?;
+            self.line_number += 1;
+
+            let line = match line {
+                Ok(line) => line,
+                Err(error) => {
+                    return Some(Err(Error::Io {
+                        number: self.line_number,
+                        error,
+                    }))
+                }
+            };
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            if self.format.is_none() {
+                if let Some(header) = PlainFormat::from_header(&line) {
+                    self.format = Some(LineFormat::Plain(header));
+                    continue;
+                }
+
+                self.format = LineFormat::detect(&line);
+            }
+
+            let result = match &self.format {
+                Some(format) => format.parse(&line),
+                None => Err(super::Error::InvalidCdxLine(line.clone())),
+            };
+
+            return Some(result.map_err(|error| self.malformed(line, error)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CdxReader;
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    #[test]
+    fn read_gzipped() {
+        let items = crate::fixtures::items(3)
+            .into_iter()
+            .map(|(item, _)| item)
+            .collect::<Vec<_>>();
+
+        let mut input = String::new();
+        for (i, item) in items.iter().enumerate() {
+            input.push_str(&item.to_cdxj());
+            input.push('\n');
+            if i == 0 {
+                input.push_str("not a valid line\n");
+            }
+        }
+
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(input.as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut malformed = vec![];
+        let result = CdxReader::from_reader(std::io::Cursor::new(compressed))
+            .unwrap()
+            .items_reporting(|error| malformed.push(error.line_number()))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(result, items);
+        assert_eq!(malformed, vec![2]);
+    }
+
+    #[test]
+    fn read_plain() {
+        let input = " CDX N b a m s k r M S V g
+com,example)/ 20200101000000 https://example.com/ text/html 200 2G3EOT7X6IEQZXKSM3OJJDW6RBCHB7YE - - 1234 5678 EXAMPLE-00000.warc.gz
+com,example)/ 2020 https://example.com/ text/html 200 2G3EOT7X6IEQZXKSM3OJJDW6RBCHB7YE - - 1234 5678 EXAMPLE-00000.warc.gz
+";
+        let mut reader =
+            CdxReader::from_reader(std::io::Cursor::new(input.as_bytes().to_vec())).unwrap();
+
+        assert!(reader.next().unwrap().is_ok());
+        assert_eq!(reader.next().unwrap().unwrap_err().line_number(), 3);
+        assert!(reader.next().is_none());
+        assert_eq!(reader.malformed_count(), 1);
+    }
+}
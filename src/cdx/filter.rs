@@ -0,0 +1,157 @@
+//! Typed CDX search filters.
+//!
+//! The CDX server matches each filter's regular expression against the whole
+//! field value, and a leading `!` negates the filter.
+
+use std::fmt::{self, Display};
+use std::ops::RangeInclusive;
+
+/// A CDX field that can be filtered on.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Field {
+    /// The canonical (SURT) form of the URL.
+    UrlKey,
+    Url,
+    Timestamp,
+    Digest,
+    MimeType,
+    StatusCode,
+    Length,
+}
+
+impl Field {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Field::UrlKey => "urlkey",
+            Field::Url => "original",
+            Field::Timestamp => "timestamp",
+            Field::Digest => "digest",
+            Field::MimeType => "mimetype",
+            Field::StatusCode => "statuscode",
+            Field::Length => "length",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Filter {
+    field: Field,
+    pattern: String,
+    negated: bool,
+}
+
+impl Filter {
+    /// A filter with the given regular expression.
+    pub fn regex(field: Field, pattern: &str) -> Self {
+        Self {
+            field,
+            pattern: pattern.to_string(),
+            negated: false,
+        }
+    }
+
+    /// A filter that matches the value exactly.
+    pub fn exact(field: Field, value: &str) -> Self {
+        Self::regex(field, &regex::escape(value))
+    }
+
+    /// Match timestamps that start with the given digits (e.g. `2020` or `202001`).
+    pub fn timestamp_prefix(prefix: &str) -> Self {
+        Self::regex(Field::Timestamp, &format!("{}.*", regex::escape(prefix)))
+    }
+
+    pub fn timestamp(timestamp: &str) -> Self {
+        Self::exact(Field::Timestamp, timestamp)
+    }
+
+    pub fn digest(digest: &str) -> Self {
+        Self::exact(Field::Digest, digest)
+    }
+
+    pub fn status(code: u16) -> Self {
+        Self::exact(Field::StatusCode, &code.to_string())
+    }
+
+    /// Match status codes in the given range.
+    pub fn status_range(range: RangeInclusive<u16>) -> Self {
+        let (start, end) = range.into_inner();
+
+        // Whole hundreds (e.g. 200..=299 or 200..=399) can use a shorter pattern.
+        let pattern = if start % 100 == 0 && end % 100 == 99 && end / 100 == start / 100 {
+            format!("{}[0-9][0-9]", start / 100)
+        } else if start % 100 == 0 && end % 100 == 99 && end / 100 < 10 {
+            format!("[{}-{}][0-9][0-9]", start / 100, end / 100)
+        } else {
+            (start..=end)
+                .map(|code| code.to_string())
+                .collect::<Vec<_>>()
+                .join("|")
+        };
+
+        Self::regex(Field::StatusCode, &pattern)
+    }
+
+    pub fn mime_type(mime_type: &str) -> Self {
+        Self::exact(Field::MimeType, mime_type)
+    }
+
+    pub fn mime_type_regex(pattern: &str) -> Self {
+        Self::regex(Field::MimeType, pattern)
+    }
+
+    /// Exclude matching rows instead of including them.
+    pub fn negate(mut self) -> Self {
+        self.negated = !self.negated;
+        self
+    }
+
+    /// The URL-encoded query parameter for this filter.
+    pub fn to_param(&self) -> String {
+        format!(
+            "filter={}",
+            url::form_urlencoded::byte_serialize(self.to_string().as_bytes()).collect::<String>()
+        )
+    }
+}
+
+impl Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.negated {
+            write!(f, "!")?;
+        }
+        write!(f, "{}:{}", self.field.as_str(), self.pattern)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Filter;
+
+    #[test]
+    fn filter_params() {
+        assert_eq!(
+            Filter::timestamp_prefix("2020").to_param(),
+            "filter=timestamp%3A2020.*"
+        );
+        assert_eq!(
+            Filter::status_range(200..=299).negate().to_string(),
+            "!statuscode:2[0-9][0-9]"
+        );
+        assert_eq!(
+            Filter::status_range(200..=399).to_string(),
+            "statuscode:[2-3][0-9][0-9]"
+        );
+        assert_eq!(
+            Filter::status_range(301..=302).to_string(),
+            "statuscode:301|302"
+        );
+        assert_eq!(
+            Filter::mime_type("text/html").to_param(),
+            "filter=mimetype%3Atext%2Fhtml"
+        );
+        assert_eq!(
+            Filter::mime_type_regex("image/.+").to_param(),
+            "filter=mimetype%3Aimage%2F.%2B"
+        );
+    }
+}
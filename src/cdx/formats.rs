@@ -0,0 +1,213 @@
+//! Parsers for the CDXJ and plain-text CDX formats.
+//!
+//! Plain-text CDX files list one capture per line as space-separated fields.
+//! Files from Internet Archive crawls usually start with a header such as
+//! ` CDX N b a m s k r M S V g`, where each letter identifies a field. Files
+//! without a header are identified by their number of fields.
+
+use super::{Error, ItemExt};
+use crate::Item;
+use std::io::{BufRead, BufReader, Read};
+
+/// The layout of a plain-text CDX file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PlainFormat {
+    url: usize,
+    timestamp: usize,
+    digest: usize,
+    mime_type: usize,
+    length: usize,
+    status: usize,
+    offset: Option<usize>,
+    filename: Option<usize>,
+    field_count: usize,
+}
+
+impl PlainFormat {
+    /// The default output of the Wayback Machine's CDX server (`urlkey`,
+    /// `timestamp`, `original`, `mimetype`, `statuscode`, `digest`, `length`).
+    pub fn cdx_server() -> Self {
+        Self::from_letters(&["N", "b", "a", "m", "s", "k", "S"]).unwrap()
+    }
+
+    /// The standard 11-field CDX format (`N b a m s k r M S V g`).
+    pub fn cdx11() -> Self {
+        Self::from_letters(&["N", "b", "a", "m", "s", "k", "r", "M", "S", "V", "g"]).unwrap()
+    }
+
+    /// Read the format from a header line (e.g. ` CDX N b a m s k r M S V g`).
+    pub fn from_header(line: &str) -> Option<Self> {
+        let mut fields = line.split_whitespace();
+
+        if fields.next() == Some("CDX") {
+            Self::from_letters(&fields.collect::<Vec<_>>())
+        } else {
+            None
+        }
+    }
+
+    /// Guess the format of a line without a header from its number of fields.
+    pub fn for_field_count(count: usize) -> Option<Self> {
+        match count {
+            7 => Some(Self::cdx_server()),
+            11 => Some(Self::cdx11()),
+            _ => None,
+        }
+    }
+
+    fn from_letters(letters: &[&str]) -> Option<Self> {
+        let position = |letter: &str| letters.iter().position(|value| *value == letter);
+
+        Some(Self {
+            url: position("a")?,
+            timestamp: position("b")?,
+            digest: position("k")?,
+            mime_type: position("m")?,
+            length: position("S")?,
+            status: position("s")?,
+            offset: position("V"),
+            filename: position("g"),
+            field_count: letters.len(),
+        })
+    }
+
+    pub fn parse_line(&self, line: &str) -> Result<Item, Error> {
+        self.parse_line_ext(line).map(|item| item.item)
+    }
+
+    /// Parse a line, including the WARC offset and filename if available.
+    pub fn parse_line_ext(&self, line: &str) -> Result<ItemExt, Error> {
+        let fields = line.split(' ').collect::<Vec<_>>();
+
+        if fields.len() != self.field_count {
+            return Err(Error::InvalidCdxLine(line.to_string()));
+        }
+
+        let optional = |index: Option<usize>| {
+            index
+                .map(|index| fields[index])
+                .filter(|value| *value != "-")
+        };
+
+        let item = Item::parse_optional_record(
+            Some(fields[self.url]),
+            Some(fields[self.timestamp]),
+            Some(fields[self.digest].trim_start_matches("sha1:")),
+            Some(fields[self.mime_type]),
+            Some(fields[self.length]),
+            Some(fields[self.status]),
+        )?;
+
+        Ok(ItemExt {
+            item,
+            robot_flags: None,
+            redirect: None,
+            dupe_count: None,
+            offset: optional(self.offset)
+                .map(|value| {
+                    value
+                        .parse()
+                        .map_err(|_| Error::InvalidOffset(value.to_string()))
+                })
+                .transpose()?,
+            filename: optional(self.filename).map(str::to_string),
+        })
+    }
+}
+
+/// Iterate over the items in a CDXJ source, skipping empty lines.
+pub fn cdxj_items<R: Read>(reader: R) -> impl Iterator<Item = Result<Item, Error>> {
+    BufReader::new(reader)
+        .lines()
+        .filter_map(|line| match line {
+            Ok(line) if line.trim().is_empty() => None,
+            Ok(line) => Some(Item::from_cdxj(&line).map_err(Error::from)),
+            Err(error) => Some(Err(error.into())),
+        })
+}
+
+/// Iterate over the items in a plain-text CDX source, skipping empty lines.
+///
+/// The format is read from a header line if there is one, and otherwise
+/// guessed from the first line.
+pub fn plain_items<R: Read>(reader: R) -> impl Iterator<Item = Result<Item, Error>> {
+    let mut format: Option<PlainFormat> = None;
+
+    BufReader::new(reader).lines().filter_map(move |line| {
+        let line = match line {
+            Ok(line) => line,
+            Err(error) => return Some(Err(error.into())),
+        };
+
+        if line.trim().is_empty() {
+            return None;
+        }
+
+        if format.is_none() {
+            if let Some(header) = PlainFormat::from_header(&line) {
+                format = Some(header);
+                return None;
+            }
+
+            format = PlainFormat::for_field_count(line.split(' ').count());
+        }
+
+        Some(match &format {
+            Some(format) => format.parse_line(&line),
+            None => Err(Error::InvalidCdxLine(line)),
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{cdxj_items, plain_items, PlainFormat};
+
+    #[test]
+    fn plain() {
+        let input = " CDX N b a m s k r M S V g
+com,example)/ 20200101000000 https://example.com/ text/html 200 2G3EOT7X6IEQZXKSM3OJJDW6RBCHB7YE - - 1234 5678 EXAMPLE-00000.warc.gz
+com,example)/robots.txt 20200101000001 https://example.com/robots.txt text/plain - 3I42H3S6NNFQ2MSVX7XZKYAYSCX5QBYJ - - 567 6912 EXAMPLE-00000.warc.gz
+";
+
+        let items = plain_items(input.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].length, 1234);
+        assert_eq!(items[1].status, None);
+
+        let item = PlainFormat::cdx11()
+            .parse_line_ext(input.lines().nth(1).unwrap())
+            .unwrap();
+        assert_eq!(item.offset, Some(5678));
+        assert_eq!(item.filename.as_deref(), Some("EXAMPLE-00000.warc.gz"));
+
+        let items = plain_items(
+            "com,example)/ 20200101000000 https://example.com/ text/html 200 2G3EOT7X6IEQZXKSM3OJJDW6RBCHB7YE 1234\nbad line\n"
+                .as_bytes(),
+        )
+        .collect::<Vec<_>>();
+        assert!(items[0].is_ok());
+        assert!(items[1].is_err());
+    }
+
+    #[test]
+    fn cdxj() {
+        let items = crate::fixtures::items(3)
+            .into_iter()
+            .map(|(item, _)| item)
+            .collect::<Vec<_>>();
+        let input = items
+            .iter()
+            .map(|item| format!("{}\n", item.to_cdxj()))
+            .collect::<String>();
+
+        let result = cdxj_items(input.as_bytes())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(result, items);
+    }
+}
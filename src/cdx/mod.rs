@@ -0,0 +1,1351 @@
+use super::{
+    item,
+    util::{
+        open_connections, CancellationToken, ErrorClass, PinnedHosts, RateLimit, RateLimiter,
+        RetryConfig, Retryable, Surface,
+    },
+    Item,
+};
+use bytes::Bytes;
+use chrono::Utc;
+use futures::{stream::BoxStream, Stream, StreamExt, TryStreamExt};
+use reqwest::{
+    header::{HeaderMap, CONTENT_TYPE},
+    Client, Response, StatusCode,
+};
+use std::collections::HashSet;
+use std::io::{BufReader, Read};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thiserror::Error;
+use tryhard::RetryPolicy;
+use url::Url;
+
+pub mod blocked;
+pub mod cache;
+pub mod common_crawl;
+mod endpoints;
+mod extra;
+pub mod file;
+mod filter;
+pub mod formats;
+mod query;
+mod resume;
+mod rows;
+pub use blocked::BlockedRegistry;
+pub use cache::ResponseCache;
+pub use common_crawl::CommonCrawlClient;
+pub use endpoints::EndpointStatus;
+use endpoints::Endpoints;
+pub use extra::{ExtraField, ItemExt};
+pub use filter::{Field, Filter};
+pub use query::{CdxQuery, MatchType, Sort, Timestamp};
+pub use resume::ResumeKey;
+use rows::RowDecoder;
+
+const TCP_KEEPALIVE_SECS: u64 = 20;
+const DEFAULT_CDX_BASE: &str = "http://web.archive.org/cdx/search/cdx";
+/// The page size for streaming searches that don't specify a limit.
+const DEFAULT_PAGE_SIZE: usize = 10000;
+/// The delay before retrying after a server or network error.
+#[cfg(not(test))]
+const TRANSIENT_ERROR_DELAY: Duration = Duration::from_secs(30);
+#[cfg(test)]
+const TRANSIENT_ERROR_DELAY: Duration = Duration::from_millis(10);
+
+lazy_static::lazy_static! {
+    /// Exception names and messages that the CDX server uses for blocked or
+    /// excluded sites, which may be returned as plain text or wrapped in HTML.
+    static ref BLOCKED_SITE_RE: regex::bytes::Regex = regex::bytes::Regex::new(
+        r"[A-Za-z]*AccessControlException|Blocked Site Error|Blocked By Robots|ExclusionException"
+    )
+    .unwrap();
+}
+
+/// Whether a non-JSON response body indicates that the site is blocked.
+fn is_blocked_site_message(body: &[u8]) -> bool {
+    BLOCKED_SITE_RE.is_match(body)
+}
+
+/// The number of characters of an error page included in errors.
+const ERROR_PAGE_PREVIEW_LENGTH: usize = 200;
+
+fn is_html(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            value
+                .trim_start()
+                .to_ascii_lowercase()
+                .starts_with("text/html")
+        })
+}
+
+/// Identify an empty body or an HTML error page in a non-JSON response body.
+fn unexpected_body(status: StatusCode, body: &[u8]) -> Option<Error> {
+    let body = body.trim_ascii();
+
+    if body.is_empty() {
+        Some(Error::EmptyResponse { status })
+    } else if body.starts_with(b"<") {
+        Some(Error::HtmlErrorPage {
+            status,
+            preview: preview(body),
+        })
+    } else {
+        None
+    }
+}
+
+/// The start of a body with whitespace collapsed.
+fn preview(body: &[u8]) -> String {
+    String::from_utf8_lossy(body)
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .take(ERROR_PAGE_PREVIEW_LENGTH)
+        .collect()
+}
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Item parsing error: {0}")]
+    ItemParsingError(#[from] item::Error),
+    #[error("HTTP client error: {0}")]
+    HttpClientError(#[from] reqwest::Error),
+    #[error("JSON decoding error: {0}")]
+    JsonError(#[from] serde_json::Error),
+    #[error("Blocked query: {0}")]
+    BlockedQuery(String),
+    #[error("Invalid resume key: {0}")]
+    InvalidResumeKey(String),
+    #[error("Resume key query mismatch: expected {expected}, found {found}")]
+    ResumeKeyQueryMismatch { expected: String, found: String },
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid URL: {0}")]
+    InvalidUrl(#[from] url::ParseError),
+    #[error("Invalid offset: {0}")]
+    InvalidOffset(String),
+    #[error("Invalid duplicate count: {0}")]
+    InvalidDupeCount(String),
+    #[error("Invalid timestamp: {0}")]
+    InvalidTimestamp(String),
+    #[error("Rate limited: {0:?}")]
+    RateLimited(RateLimit),
+    #[error("HTML error page ({status}): {preview}")]
+    HtmlErrorPage { status: StatusCode, preview: String },
+    #[error("Empty response ({status})")]
+    EmptyResponse { status: StatusCode },
+    #[error("Invalid URL scheme: {0}")]
+    InvalidScheme(String),
+    #[error("Invalid CDX line: {0}")]
+    InvalidCdxLine(String),
+    #[error("Unexpected column count: expected {expected}, found {found}")]
+    UnexpectedColumnCount { expected: usize, found: usize },
+    #[error("Cancelled")]
+    Cancelled,
+}
+
+impl Error {
+    /// The classification of the underlying HTTP client error, if any.
+    pub fn class(&self) -> Option<ErrorClass> {
+        match self {
+            Error::HttpClientError(error) => Some(ErrorClass::of(error)),
+            _ => None,
+        }
+    }
+}
+
+impl Retryable for Error {
+    fn max_retries() -> u32 {
+        7
+    }
+
+    fn log_level() -> Option<log::Level> {
+        Some(log::Level::Warn)
+    }
+
+    fn default_initial_delay() -> Duration {
+        Duration::from_millis(250)
+    }
+
+    fn custom_retry_policy(&self) -> Option<RetryPolicy> {
+        match self {
+            Error::HttpClientError(error) => match ErrorClass::of(error) {
+                ErrorClass::Dns => Some(RetryPolicy::Delay(Duration::from_secs(60))),
+                ErrorClass::Certificate => Some(RetryPolicy::Break),
+                _ => Some(RetryPolicy::Delay(TRANSIENT_ERROR_DELAY)),
+            },
+            // The CDX server occasionally returns an empty body or a truncated response.
+            Error::EmptyResponse { .. } | Error::JsonError(_) => {
+                Some(RetryPolicy::Delay(TRANSIENT_ERROR_DELAY))
+            }
+            Error::RateLimited(limit) => Some(RetryPolicy::Delay(limit.delay())),
+            Error::HtmlErrorPage { status, .. } if !status.is_client_error() => {
+                Some(RetryPolicy::Delay(TRANSIENT_ERROR_DELAY))
+            }
+            _ => Some(RetryPolicy::Break),
+        }
+    }
+}
+
+pub struct IndexClient {
+    endpoints: Endpoints,
+    underlying: Client,
+    user_agent: Option<String>,
+    pinned: PinnedHosts,
+    gzip: bool,
+    extras: Vec<ExtraField>,
+    blocked_registry: Option<Arc<Mutex<BlockedRegistry>>>,
+    cache: Option<ResponseCache>,
+    lenient_rows: bool,
+    skipped_rows: AtomicUsize,
+    limiter: Option<RateLimiter>,
+    retry: RetryConfig,
+    cancellation: Option<CancellationToken>,
+}
+
+impl IndexClient {
+    pub fn new(base: String) -> Result<Self, Error> {
+        let user_agent = Some(super::util::DEFAULT_USER_AGENT.to_string());
+        let pinned = PinnedHosts::default();
+
+        Ok(Self {
+            endpoints: Endpoints::new(Url::parse(&base)?),
+            underlying: Self::build_client(user_agent.as_deref(), &pinned, true)?,
+            user_agent,
+            pinned,
+            gzip: true,
+            extras: vec![],
+            blocked_registry: None,
+            cache: None,
+            lenient_rows: false,
+            skipped_rows: AtomicUsize::new(0),
+            limiter: None,
+            retry: RetryConfig::for_error::<Error>(),
+            cancellation: None,
+        })
+    }
+
+    fn build_client(
+        user_agent: Option<&str>,
+        pinned: &PinnedHosts,
+        gzip: bool,
+    ) -> reqwest::Result<Client> {
+        let mut builder = pinned.apply(
+            Client::builder()
+                .tcp_keepalive(Some(Duration::from_secs(TCP_KEEPALIVE_SECS)))
+                .gzip(gzip),
+        );
+
+        if let Some(user_agent) = user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+
+        builder.build()
+    }
+
+    fn rebuild(mut self) -> Result<Self, Error> {
+        self.underlying = Self::build_client(self.user_agent.as_deref(), &self.pinned, self.gzip)?;
+        Ok(self)
+    }
+
+    /// Send the given user agent instead of the default.
+    pub fn with_user_agent(mut self, user_agent: &str) -> Result<Self, Error> {
+        self.user_agent = Some(user_agent.to_string());
+        self.rebuild()
+    }
+
+    /// Don't send a user agent.
+    pub fn without_user_agent(mut self) -> Result<Self, Error> {
+        self.user_agent = None;
+        self.rebuild()
+    }
+
+    /// Request gzip-compressed responses (the default), which are decompressed
+    /// transparently.
+    ///
+    /// Responses that are gzipped without a `Content-Encoding` header are
+    /// decompressed whether or not this is enabled.
+    pub fn with_gzip(mut self, enabled: bool) -> Result<Self, Error> {
+        self.gzip = enabled;
+        self.rebuild()
+    }
+
+    /// Use an existing HTTP client (for example to share a connection pool, or
+    /// for proxy or TLS configuration).
+    ///
+    /// The client's own user agent and timeouts are used.
+    pub fn with_client(mut self, client: Client) -> Self {
+        self.underlying = client;
+        self
+    }
+
+    /// Send searches to a different primary CDX endpoint.
+    pub fn with_base(mut self, base: &str) -> Result<Self, Error> {
+        *self.endpoints.primary_mut() = Url::parse(base)?;
+        Ok(self)
+    }
+
+    /// Use the given scheme (e.g. `https`) for the primary CDX endpoint.
+    pub fn with_scheme(mut self, scheme: &str) -> Result<Self, Error> {
+        self.endpoints
+            .primary_mut()
+            .set_scheme(scheme)
+            .map_err(|_| Error::InvalidScheme(scheme.to_string()))?;
+        Ok(self)
+    }
+
+    /// Use the given host (e.g. an internal mirror) for the primary CDX
+    /// endpoint.
+    pub fn with_host(mut self, host: &str) -> Result<Self, Error> {
+        self.endpoints.primary_mut().set_host(Some(host))?;
+        Ok(self)
+    }
+
+    /// Add a fallback CDX endpoint, which is used when the endpoints before it
+    /// are failing.
+    ///
+    /// Resume keys are passed between endpoints as-is, so fallbacks should
+    /// serve the same index.
+    pub fn with_fallback_base(mut self, base: &str) -> Result<Self, Error> {
+        self.endpoints.push(Url::parse(base)?);
+        Ok(self)
+    }
+
+    /// Skip malformed rows in streaming searches (rows with the wrong number of
+    /// columns, or that can't be decoded) instead of failing the search.
+    ///
+    /// Skipped rows are logged and counted in [`IndexClient::skipped_rows`].
+    pub fn with_lenient_rows(mut self, enabled: bool) -> Self {
+        self.lenient_rows = enabled;
+        self
+    }
+
+    /// The number of malformed rows skipped in lenient mode.
+    pub fn skipped_rows(&self) -> usize {
+        self.skipped_rows.load(Ordering::Relaxed)
+    }
+
+    /// Request the given fields (in addition to any requested by the query) in
+    /// extended searches.
+    pub fn with_extra_fields(mut self, extras: &[ExtraField]) -> Self {
+        for extra in extras {
+            if !self.extras.contains(extra) {
+                self.extras.push(*extra);
+            }
+        }
+        self
+    }
+
+    /// Fail queries that are known to be blocked without sending them, and
+    /// record newly blocked queries in the registry.
+    pub fn with_blocked_registry(mut self, registry: Arc<Mutex<BlockedRegistry>>) -> Self {
+        self.blocked_registry = Some(registry);
+        self
+    }
+
+    /// Cache the results of non-streaming searches (such as
+    /// [`IndexClient::search`]) on disk.
+    ///
+    /// Entries are keyed by the search URL for the primary endpoint.
+    pub fn with_cache(mut self, cache: ResponseCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// The settings used for retrying failed requests.
+    pub fn retry_config(&self) -> &RetryConfig {
+        &self.retry
+    }
+
+    /// Retry failed requests with the given settings instead of the defaults.
+    pub fn with_retry_config(mut self, config: RetryConfig) -> Self {
+        self.retry = config;
+        self
+    }
+
+    /// Draw search requests from a shared request budget.
+    pub fn with_rate_limiter(mut self, limiter: RateLimiter) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    /// Stop sending search requests once the token is cancelled.
+    ///
+    /// Requests made after cancellation fail with [`Error::Cancelled`], so
+    /// streams end after the page that is being read.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Check whether the query is known to be blocked by the client's
+    /// registry.
+    pub fn is_blocked(&self, query: &CdxQuery) -> bool {
+        self.blocked_registry.as_ref().is_some_and(|registry| {
+            registry
+                .lock()
+                .expect("Blocked registry lock poisoned")
+                .is_blocked(query.url())
+        })
+    }
+
+    fn check_blocked(&self, query: &CdxQuery) -> Result<(), Error> {
+        if self.is_blocked(query) {
+            Err(Error::BlockedQuery(query.url().to_string()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Record a blocked query, returning the corresponding error.
+    fn blocked(&self, query: &CdxQuery) -> Error {
+        if let Some(registry) = &self.blocked_registry {
+            let mut registry = registry.lock().expect("Blocked registry lock poisoned");
+            registry.insert(query.url());
+
+            if let Err(error) = registry.save() {
+                log::warn!("Failed to save blocked registry: {:?}", error);
+            }
+        }
+
+        Error::BlockedQuery(query.url().to_string())
+    }
+
+    /// Add the client's extra fields to a query.
+    fn with_client_extras(&self, query: &CdxQuery) -> CdxQuery {
+        self.extras
+            .iter()
+            .fold(query.clone(), |query, extra| query.with_extra_field(*extra))
+    }
+
+    /// Resolve the primary endpoint's host and open the given number of
+    /// connections to it, so that a large run doesn't start with a burst of
+    /// lookups and handshakes.
+    pub async fn warm_up(&self, connections: usize) -> Result<(), Error> {
+        let (_, base) = self.endpoints.select();
+
+        Ok(open_connections(&self.underlying, &base.join("/")?, connections).await?)
+    }
+
+    /// Resolve the hosts of all endpoints now and use the resulting addresses
+    /// for all later requests, instead of following DNS changes during a run.
+    ///
+    /// This replaces any client provided with [`IndexClient::with_client`].
+    pub async fn with_pinned_dns(mut self) -> Result<Self, Error> {
+        for status in self.endpoints.status() {
+            if let Some(host) = status.base.host_str() {
+                self.pinned.pin(host).await?;
+            }
+        }
+
+        self.rebuild()
+    }
+
+    /// The health of each endpoint, in priority order.
+    pub fn endpoint_status(&self) -> Vec<EndpointStatus> {
+        self.endpoints.status()
+    }
+
+    /// Build a search URL, with all parameters percent-encoded.
+    fn query_url(base: &Url, query: &CdxQuery, extra_params: &[(&str, String)]) -> Url {
+        let mut url = base.clone();
+        url.query_pairs_mut()
+            .extend_pairs(query.params())
+            .extend_pairs(extra_params);
+        url
+    }
+
+    fn decode_rows(rows: Vec<Vec<String>>) -> Result<Vec<Item>, Error> {
+        rows.iter()
+            .skip(1)
+            .map(|row| Self::decode_row(row))
+            .collect()
+    }
+
+    fn decode_row(row: &[String]) -> Result<Item, Error> {
+        Item::parse_optional_record(
+            row.first().map(|v| v.as_str()),
+            row.get(1).map(|v| v.as_str()),
+            row.get(2).map(|v| v.as_str()),
+            row.get(3).map(|v| v.as_str()),
+            row.get(4).map(|v| v.as_str()),
+            row.get(5).map(|v| v.as_str()),
+        )
+        .map_err(From::from)
+    }
+
+    pub fn load_json<R: Read>(reader: R) -> Result<Vec<Item>, Error> {
+        let buffered = BufReader::new(reader);
+
+        let rows = serde_json::from_reader::<BufReader<R>, Vec<Vec<String>>>(buffered)?;
+
+        Self::decode_rows(rows)
+    }
+
+    /// Load items from CDXJ lines (as returned by pywb and OutbackCDX).
+    pub fn load_cdxj<R: Read>(reader: R) -> Result<Vec<Item>, Error> {
+        formats::cdxj_items(reader).collect()
+    }
+
+    /// Load items from plain-text CDX lines, with or without a header.
+    pub fn load_plain<R: Read>(reader: R) -> Result<Vec<Item>, Error> {
+        formats::plain_items(reader).collect()
+    }
+
+    /// Stream the results of a query, paging with resume keys.
+    ///
+    /// The query's limit is used as the page size. Each page is decoded as it
+    /// is received, so items are available before the page has been fully
+    /// downloaded. Requests for pages are retried, as are errors while reading
+    /// a page body if none of the page's items have been yielded yet.
+    ///
+    /// Items are yielded in the order the CDX server returns them, which is
+    /// usually (but not always) by URL key and then capture time, or the order
+    /// requested with [`CdxQuery::with_sort`]. Use [`CdxQuery::with_page_sort`]
+    /// to enforce the order within each page.
+    pub fn stream_search<'a>(
+        &'a self,
+        query: &'a CdxQuery,
+    ) -> impl Stream<Item = Result<Item, Error>> + 'a {
+        self.stream_search_from(query, None, |_| {})
+    }
+
+    /// Stream the results of a query, starting from the given resume key.
+    ///
+    /// The checkpoint function is called with the resume key for the next page
+    /// after each page has been read (and all of its items have been yielded),
+    /// so callers can persist it and restart an interrupted search.
+    pub fn stream_search_from<'a, C: FnMut(&ResumeKey) + 'a>(
+        &'a self,
+        query: &CdxQuery,
+        resume_key: Option<ResumeKey>,
+        checkpoint: C,
+    ) -> impl Stream<Item = Result<Item, Error>> + 'a {
+        self.stream_decoded(
+            query.clone(),
+            resume_key,
+            |_, row| Self::decode_row(row),
+            checkpoint,
+        )
+    }
+
+    /// Stream the results of a query split into the given number of time
+    /// slices, which are searched concurrently (up to the given parallelism).
+    ///
+    /// Results from different slices are interleaved, and duplicate captures
+    /// are dropped.
+    pub fn stream_search_sliced<'a>(
+        &'a self,
+        query: &CdxQuery,
+        slices: usize,
+        parallelism: usize,
+    ) -> impl Stream<Item = Result<Item, Error>> + 'a {
+        let mut seen = HashSet::new();
+
+        futures::stream::iter(query.time_slices(slices, Utc::now().naive_utc()))
+            .map(move |slice| {
+                self.stream_decoded(slice, None, |_, row| Self::decode_row(row), |_| {})
+                    .boxed()
+            })
+            .flatten_unordered(parallelism.max(1))
+            .try_filter(move |item| {
+                futures::future::ready(seen.insert((
+                    item.url.clone(),
+                    item.archived_at,
+                    item.digest.clone(),
+                )))
+            })
+    }
+
+    /// Search for several queries concurrently (up to the given parallelism).
+    ///
+    /// Each result is paired with the query that produced it. A query that
+    /// fails (for example with [`Error::BlockedQuery`] for blocked sites)
+    /// yields a single error, and captures already returned for an earlier
+    /// query are dropped.
+    pub fn search_many<'a, I>(
+        &'a self,
+        queries: I,
+        parallelism: usize,
+    ) -> impl Stream<Item = (Arc<CdxQuery>, Result<Item, Error>)> + 'a
+    where
+        I: IntoIterator<Item = CdxQuery>,
+        I::IntoIter: 'a,
+    {
+        let mut seen = HashSet::new();
+
+        futures::stream::iter(queries)
+            .map(move |query| async move {
+                let query = Arc::new(query);
+                let results = match self.search(&query).await {
+                    Ok(items) => items.into_iter().map(Ok).collect(),
+                    Err(error) => vec![Err(error)],
+                };
+
+                futures::stream::iter(
+                    results
+                        .into_iter()
+                        .map(move |result| (query.clone(), result)),
+                )
+            })
+            .buffer_unordered(parallelism.max(1))
+            .flatten()
+            .filter(move |(_, result)| {
+                futures::future::ready(match result {
+                    Ok(item) => {
+                        seen.insert((item.url.clone(), item.archived_at, item.digest.clone()))
+                    }
+                    Err(_) => true,
+                })
+            })
+    }
+
+    /// Stream the results of a query with the additional fields requested by
+    /// the query and the client.
+    pub fn stream_search_ext<'a>(
+        &'a self,
+        query: &CdxQuery,
+    ) -> impl Stream<Item = Result<ItemExt, Error>> + 'a {
+        self.stream_decoded(
+            self.with_client_extras(query),
+            None,
+            |query, row| ItemExt::from_row(row, query.extras()),
+            |_| {},
+        )
+    }
+
+    fn stream_decoded<'a, T: 'a, F, C>(
+        &'a self,
+        query: CdxQuery,
+        resume_key: Option<ResumeKey>,
+        decode: F,
+        mut checkpoint: C,
+    ) -> impl Stream<Item = Result<T, Error>> + 'a
+    where
+        F: Fn(&CdxQuery, &[String]) -> Result<T, Error> + Copy + 'a,
+        C: FnMut(&ResumeKey) + 'a,
+    {
+        let query = Arc::new(query);
+
+        let start = PageState::Next {
+            resume_key,
+            retries: 0,
+        };
+
+        futures::stream::try_unfold(start, move |state| {
+            let query = query.clone();
+
+            async move {
+                let next = match state {
+                    PageState::Next {
+                        resume_key,
+                        retries,
+                    } => {
+                        let (endpoint, response) = self
+                            .retry
+                            .retry(|| self.request_page(&query, &resume_key))
+                            .await?;
+
+                        Some((
+                            (vec![], None),
+                            PageState::Reading {
+                                endpoint,
+                                status: response.status(),
+                                body: response.bytes_stream().boxed(),
+                                decoder: RowDecoder::default(),
+                                page: vec![],
+                                resume_key,
+                                retries,
+                                yielded: false,
+                                row_count: 0,
+                                byte_count: 0,
+                            },
+                        ))
+                    }
+                    PageState::Reading {
+                        endpoint,
+                        status,
+                        mut body,
+                        mut decoder,
+                        mut page,
+                        resume_key,
+                        retries,
+                        yielded,
+                        mut row_count,
+                        mut byte_count,
+                    } => match body.next().await {
+                        Some(chunk) => {
+                            let rows = match chunk.map_err(Error::from).and_then(|chunk| {
+                                byte_count += chunk.len();
+                                decoder.push(&chunk)
+                            }) {
+                                Ok(rows) => rows,
+                                Err(error) => {
+                                    self.endpoints.record_failure(endpoint);
+                                    return self
+                                        .retry_page(error, resume_key, retries, yielded)
+                                        .await;
+                                }
+                            };
+
+                            row_count += rows.len();
+
+                            let items = if query.sorts_pages() {
+                                page.extend(rows);
+                                vec![]
+                            } else {
+                                self.decode_page(&query, &rows, decode)?
+                            };
+                            let yielded = yielded || !items.is_empty();
+
+                            Some((
+                                (items, None),
+                                PageState::Reading {
+                                    endpoint,
+                                    status,
+                                    body,
+                                    decoder,
+                                    page,
+                                    resume_key,
+                                    retries,
+                                    yielded,
+                                    row_count,
+                                    byte_count,
+                                },
+                            ))
+                        }
+                        None if decoder.raw_body().is_some_and(is_blocked_site_message) => {
+                            return Err(self.blocked(&query));
+                        }
+                        None => {
+                            let result = match decoder
+                                .raw_body()
+                                .and_then(|body| unexpected_body(status, body))
+                            {
+                                Some(error) => Err(error),
+                                None => decoder.finish(),
+                            };
+
+                            let next_key = match result {
+                                Ok(next_key) => next_key,
+                                Err(error) => {
+                                    self.endpoints.record_failure(endpoint);
+                                    return self
+                                        .retry_page(error, resume_key, retries, yielded)
+                                        .await;
+                                }
+                            };
+                            self.endpoints.record_success(endpoint);
+                            log::info!("Rows received {} ({} bytes)", row_count, byte_count);
+                            log::info!("Resume key: {:?}", next_key);
+
+                            query.sort_page(&mut page);
+                            let items = self.decode_page(&query, &page, decode)?;
+
+                            Some((
+                                (items, next_key.clone()),
+                                next_key.map_or(PageState::Done, |key| PageState::Next {
+                                    resume_key: Some(key),
+                                    retries: 0,
+                                }),
+                            ))
+                        }
+                    },
+                    PageState::Done => None,
+                };
+
+                let result: Result<_, Error> = Ok(next);
+                result
+            }
+        })
+        .map_ok(|(items, resume_key)| {
+            // The checkpoint follows the page's items, so that it is only
+            // reached once they have all been consumed.
+            let events = items
+                .into_iter()
+                .map(PageEvent::Item)
+                .chain(resume_key.map(PageEvent::Checkpoint));
+
+            futures::stream::iter(events.map(Ok))
+        })
+        .try_flatten()
+        .try_filter_map(move |event| {
+            futures::future::ok(match event {
+                PageEvent::Item(item) => Some(item),
+                PageEvent::Checkpoint(resume_key) => {
+                    checkpoint(&resume_key);
+                    None
+                }
+            })
+        })
+    }
+
+    /// Request a page again after an error while reading it, if the error is
+    /// transient and none of the page's items have been yielded yet.
+    async fn retry_page<T>(
+        &self,
+        error: Error,
+        resume_key: Option<ResumeKey>,
+        retries: u32,
+        yielded: bool,
+    ) -> Result<Option<PageStep<T>>, Error> {
+        match self.retry.delay_for(&error, retries).filter(|_| !yielded) {
+            Some(delay) => {
+                log::warn!(
+                    "Retrying page {}; waiting {:?} after error: {:?}",
+                    retries + 1,
+                    delay,
+                    error
+                );
+                async_std::task::sleep(delay).await;
+
+                Ok(Some((
+                    (vec![], None),
+                    PageState::Next {
+                        resume_key,
+                        retries: retries + 1,
+                    },
+                )))
+            }
+            None => Err(error),
+        }
+    }
+
+    /// Decode rows, skipping (and counting) malformed ones in lenient mode.
+    fn decode_page<T, F>(
+        &self,
+        query: &CdxQuery,
+        rows: &[Vec<String>],
+        decode: F,
+    ) -> Result<Vec<T>, Error>
+    where
+        F: Fn(&CdxQuery, &[String]) -> Result<T, Error>,
+    {
+        if !self.lenient_rows {
+            return rows.iter().map(|row| decode(query, row)).collect();
+        }
+
+        let field_count = query.field_count();
+
+        Ok(rows
+            .iter()
+            .filter_map(|row| {
+                let result = if row.len() == field_count {
+                    decode(query, row)
+                } else {
+                    Err(Error::UnexpectedColumnCount {
+                        expected: field_count,
+                        found: row.len(),
+                    })
+                };
+
+                result
+                    .inspect_err(|error| {
+                        log::warn!("Skipping malformed row {:?}: {}", row, error);
+                        self.skipped_rows.fetch_add(1, Ordering::Relaxed);
+                    })
+                    .ok()
+            })
+            .collect())
+    }
+
+    async fn request_page(
+        &self,
+        query: &CdxQuery,
+        resume_key: &Option<ResumeKey>,
+    ) -> Result<(usize, Response), Error> {
+        let mut params = vec![("showResumeKey", "true".to_string())];
+        if query.limit().is_none() {
+            params.push(("limit", DEFAULT_PAGE_SIZE.to_string()));
+        }
+        params.extend(
+            resume_key
+                .as_ref()
+                .map(|key| ("resumeKey", key.as_str().to_string())),
+        );
+
+        self.send(query, &params).await
+    }
+
+    /// Send a search request to the selected endpoint, returning the index of
+    /// the endpoint along with the response.
+    ///
+    /// Failures are recorded against the endpoint, but successes are only
+    /// recorded once the body has been read.
+    async fn send(
+        &self,
+        query: &CdxQuery,
+        extra_params: &[(&str, String)],
+    ) -> Result<(usize, Response), Error> {
+        if self
+            .cancellation
+            .as_ref()
+            .is_some_and(|token| token.is_cancelled())
+        {
+            return Err(Error::Cancelled);
+        }
+
+        self.check_blocked(query)?;
+
+        let (endpoint, base) = self.endpoints.select();
+        let query_url = Self::query_url(base, query, extra_params);
+        log::info!("Search URL: {}", query_url);
+
+        if let Some(limiter) = &self.limiter {
+            limiter.acquire(Surface::Cdx).await;
+        }
+
+        let result = match self.underlying.get(query_url).send().await {
+            Ok(response) => match Self::check_rate_limit(response) {
+                Ok(response) if is_html(response.headers()) => {
+                    Err(self.error_page(query, response).await)
+                }
+                other => other,
+            },
+            Err(error) => Err(error.into()),
+        };
+
+        match result {
+            Ok(response) => Ok((endpoint, response)),
+            Err(error) => {
+                if !matches!(error, Error::BlockedQuery(_)) {
+                    self.endpoints.record_failure(endpoint);
+                }
+                Err(error)
+            }
+        }
+    }
+
+    /// Read an HTML response, which is either a blocked site message or an
+    /// error page.
+    async fn error_page(&self, query: &CdxQuery, response: Response) -> Error {
+        let status = response.status();
+
+        match response.bytes().await {
+            Ok(body) => match rows::decompress(&body) {
+                Ok(contents) if is_blocked_site_message(&contents) => self.blocked(query),
+                Ok(contents) => Error::HtmlErrorPage {
+                    status,
+                    preview: preview(&contents),
+                },
+                Err(error) => error,
+            },
+            Err(error) => error.into(),
+        }
+    }
+
+    fn check_rate_limit(response: Response) -> Result<Response, Error> {
+        match RateLimit::of(&response) {
+            Some(limit) => Err(Error::RateLimited(limit)),
+            None => Ok(response),
+        }
+    }
+
+    pub async fn search(&self, query: &CdxQuery) -> Result<Vec<Item>, Error> {
+        let rows = self.get_rows(query).await?;
+
+        Self::decode_rows(rows)
+    }
+
+    /// Search for items with the additional fields requested by the query and
+    /// the client.
+    pub async fn search_ext(&self, query: &CdxQuery) -> Result<Vec<ItemExt>, Error> {
+        let query = self.with_client_extras(query);
+        let rows = self.get_rows(&query).await?;
+
+        rows.iter()
+            .skip(1)
+            .map(|row| ItemExt::from_row(row, query.extras()))
+            .collect()
+    }
+
+    /// Count the captures matching a query.
+    ///
+    /// Results are paged through as in [`IndexClient::stream_search`], but only
+    /// timestamps are transferred.
+    pub async fn count(&self, query: &CdxQuery) -> Result<u64, Error> {
+        self.stream_decoded(query.clone().for_count(), None, |_, _| Ok(()), |_| {})
+            .try_fold(0, |count, ()| futures::future::ok(count + 1))
+            .await
+    }
+
+    /// The number of pages of results for a query in the CDX server's
+    /// pagination API (using `showNumPages`).
+    ///
+    /// Pages are blocks of the index, so this is a rough measure of the size
+    /// of a query that is much cheaper than counting.
+    pub async fn page_count(&self, query: &CdxQuery) -> Result<usize, Error> {
+        let params = [("showNumPages", "true".to_string())];
+        let (endpoint, response) = self.retry.retry(|| self.send(query, &params)).await?;
+        let status = response.status();
+        let body = response
+            .bytes()
+            .await
+            .inspect_err(|_| self.endpoints.record_failure(endpoint))?;
+        let contents =
+            rows::decompress(&body).inspect_err(|_| self.endpoints.record_failure(endpoint))?;
+
+        if is_blocked_site_message(&contents) {
+            return Err(self.blocked(query));
+        }
+
+        if let Some(error) = unexpected_body(status, &contents) {
+            self.endpoints.record_failure(endpoint);
+            return Err(error);
+        }
+
+        let pages = serde_json::from_slice(contents.trim_ascii())
+            .inspect_err(|_| self.endpoints.record_failure(endpoint))?;
+        self.endpoints.record_success(endpoint);
+
+        Ok(pages)
+    }
+
+    async fn get_rows(&self, query: &CdxQuery) -> Result<Vec<Vec<String>>, Error> {
+        match &self.cache {
+            Some(cache) => {
+                let url = Self::query_url(self.endpoints.primary(), query, &[]);
+
+                if let Some(rows) = cache.get(url.as_str()) {
+                    log::info!("Cached search URL: {}", url);
+                    return Ok(rows);
+                }
+
+                let rows = self.get_rows_uncached(query).await?;
+                if let Err(error) = cache.put(url.as_str(), rows.clone()) {
+                    log::warn!("Failed to cache search results: {:?}", error);
+                }
+
+                Ok(rows)
+            }
+            None => self.get_rows_uncached(query).await,
+        }
+    }
+
+    async fn get_rows_uncached(&self, query: &CdxQuery) -> Result<Vec<Vec<String>>, Error> {
+        let (endpoint, response) = self.send(query, &[]).await?;
+        let status = response.status();
+        let body = response
+            .bytes()
+            .await
+            .inspect_err(|_| self.endpoints.record_failure(endpoint))?;
+        let contents =
+            rows::decompress(&body).inspect_err(|_| self.endpoints.record_failure(endpoint))?;
+
+        if !contents.trim_ascii_start().starts_with(b"[") {
+            if is_blocked_site_message(&contents) {
+                return Err(self.blocked(query));
+            }
+
+            if let Some(error) = unexpected_body(status, &contents) {
+                self.endpoints.record_failure(endpoint);
+                return Err(error);
+            }
+        }
+
+        let rows = serde_json::from_slice(&contents)
+            .inspect_err(|_| self.endpoints.record_failure(endpoint))?;
+        self.endpoints.record_success(endpoint);
+
+        Ok(rows)
+    }
+}
+
+/// The progress of a streaming search.
+enum PageState {
+    /// Request the page for the resume key (after the given number of
+    /// failed attempts to read it).
+    Next {
+        resume_key: Option<ResumeKey>,
+        retries: u32,
+    },
+    Reading {
+        endpoint: usize,
+        status: StatusCode,
+        body: BoxStream<'static, reqwest::Result<Bytes>>,
+        decoder: RowDecoder,
+        /// Rows held back until the end of the page, for re-sorting.
+        page: Vec<Vec<String>>,
+        /// The resume key the page was requested with.
+        resume_key: Option<ResumeKey>,
+        retries: u32,
+        /// Whether any of the page's items have been yielded.
+        yielded: bool,
+        /// The number of rows and bytes of the page read so far.
+        row_count: usize,
+        byte_count: usize,
+    },
+    Done,
+}
+
+/// An item from a streaming search, or the resume key for the next page after
+/// the last item of a page.
+enum PageEvent<T> {
+    Item(T),
+    Checkpoint(ResumeKey),
+}
+
+/// Items read in one step of a streaming search (with the resume key for the
+/// next page at the end of a page), and the next state.
+type PageStep<T> = ((Vec<T>, Option<ResumeKey>), PageState);
+
+impl Default for IndexClient {
+    fn default() -> Self {
+        Self::new(DEFAULT_CDX_BASE.to_string()).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IndexClient;
+    use futures::StreamExt;
+    use std::fs::File;
+
+    #[test]
+    fn query_url_encoding() {
+        let client = IndexClient::default();
+        let url = IndexClient::query_url(
+            client.endpoints.select().1,
+            &super::CdxQuery::new("example.com/search?q=a b&lang=en#top")
+                .with_filter(super::Filter::mime_type("text/html")),
+            &[],
+        );
+
+        assert_eq!(
+            url.as_str(),
+            "http://web.archive.org/cdx/search/cdx?url=example.com%2Fsearch%3Fq%3Da+b%26lang%3Den%23top&filter=mimetype%3Atext%2Fhtml&output=json&fl=original%2Ctimestamp%2Cdigest%2Cmimetype%2Clength%2Cstatuscode"
+        );
+
+        let pairs = url.query_pairs().collect::<Vec<_>>();
+        assert_eq!(pairs[0].1, "example.com/search?q=a b&lang=en#top");
+    }
+
+    #[test]
+    fn blocked_site_messages() {
+        assert!(super::is_blocked_site_message(
+            b"org.archive.util.io.RuntimeIOException: org.archive.wayback.exception.AdministrativeAccessControlException: Blocked Site Error\n"
+        ));
+        assert!(super::is_blocked_site_message(
+            b"<html><body><p>org.archive.wayback.exception.RobotAccessControlException: Blocked By Robots</p></body></html>"
+        ));
+        assert!(!super::is_blocked_site_message(
+            b"<html><body>Bad Gateway</body></html>"
+        ));
+    }
+
+    #[test]
+    fn unexpected_bodies() {
+        use reqwest::{header::HeaderMap, StatusCode};
+
+        let mut headers = HeaderMap::new();
+        assert!(!super::is_html(&headers));
+        headers.insert("content-type", "Text/HTML; charset=utf-8".parse().unwrap());
+        assert!(super::is_html(&headers));
+
+        assert!(matches!(
+            super::unexpected_body(StatusCode::OK, b" \n"),
+            Some(super::Error::EmptyResponse {
+                status: StatusCode::OK
+            })
+        ));
+        match super::unexpected_body(
+            StatusCode::BAD_GATEWAY,
+            b"<html>\n  <body>Bad   Gateway</body>\n</html>",
+        ) {
+            Some(super::Error::HtmlErrorPage { status, preview }) => {
+                assert_eq!(status, StatusCode::BAD_GATEWAY);
+                assert_eq!(preview, "<html> <body>Bad Gateway</body> </html>");
+            }
+            other => panic!("Expected HTML error page, got {:?}", other),
+        }
+        assert!(super::unexpected_body(StatusCode::OK, b"12").is_none());
+        assert_eq!(
+            super::preview(&[b'a'; 1000]).len(),
+            super::ERROR_PAGE_PREVIEW_LENGTH
+        );
+    }
+
+    #[test]
+    fn client_extra_fields() {
+        let client = IndexClient::default()
+            .with_extra_fields(&[super::ExtraField::Filename, super::ExtraField::Offset]);
+        let query = client.with_client_extras(
+            &super::CdxQuery::new("example.com").with_extra_field(super::ExtraField::Offset),
+        );
+
+        assert_eq!(
+            query.extras(),
+            &[super::ExtraField::Offset, super::ExtraField::Filename]
+        );
+    }
+
+    #[test]
+    fn custom_endpoint() {
+        let client = IndexClient::default()
+            .with_client(reqwest::Client::new())
+            .with_scheme("https")
+            .unwrap()
+            .with_host("cdx.example.internal")
+            .unwrap();
+        let url = IndexClient::query_url(
+            client.endpoints.select().1,
+            &super::CdxQuery::new("example.com"),
+            &[],
+        );
+
+        assert!(url
+            .as_str()
+            .starts_with("https://cdx.example.internal/cdx/search/cdx?url=example.com&"));
+        assert!(IndexClient::default().with_scheme("not a scheme").is_err());
+        assert!(IndexClient::default().with_base("not a url").is_err());
+    }
+
+    #[tokio::test]
+    async fn search_many_blocked() {
+        let mut registry = super::BlockedRegistry::new(std::time::Duration::from_secs(60));
+        registry.insert("example.com");
+        let client = IndexClient::default()
+            .with_blocked_registry(std::sync::Arc::new(std::sync::Mutex::new(registry)));
+
+        let queries = vec![
+            super::CdxQuery::new("example.com/foo"),
+            super::CdxQuery::new("www.example.com/bar"),
+        ];
+        let mut blocked = client
+            .search_many(queries, 2)
+            .map(|(query, result)| match result {
+                Err(super::Error::BlockedQuery(blocked)) => {
+                    assert_eq!(blocked, query.url());
+                    blocked
+                }
+                other => panic!("Expected blocked query, got {:?}", other),
+            })
+            .collect::<Vec<_>>()
+            .await;
+        blocked.sort();
+
+        assert_eq!(blocked, vec!["example.com/foo", "www.example.com/bar"]);
+    }
+
+    #[tokio::test]
+    async fn retry_page_body() {
+        let items = crate::fixtures::items(3)
+            .into_iter()
+            .map(|(item, _)| item)
+            .collect::<Vec<_>>();
+        let base =
+            crate::fixtures::serve(vec![String::new(), crate::fixtures::cdx_json(&items, None)]);
+        let client = IndexClient::new(format!("{}/cdx/search/cdx", base)).unwrap();
+        let query = super::CdxQuery::new("example.com");
+
+        let result = client
+            .stream_search(&query)
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(result, items);
+    }
+
+    #[tokio::test]
+    async fn checkpoint_after_items() {
+        let items = crate::fixtures::items(5)
+            .into_iter()
+            .map(|(item, _)| item)
+            .collect::<Vec<_>>();
+        let key = "com,example)/2 20200303000000"
+            .parse::<super::ResumeKey>()
+            .unwrap();
+        let first = crate::fixtures::cdx_json(&items[..3], Some(&key));
+        let second = crate::fixtures::cdx_json(&items[3..], None);
+        let base = crate::fixtures::serve(vec![first.clone(), first, second]);
+        let client = IndexClient::new(format!("{}/cdx/search/cdx", base)).unwrap();
+        // Sorted pages are yielded together with the resume key.
+        let query = super::CdxQuery::new("example.com")
+            .with_limit(3)
+            .with_page_sort(true);
+        let saved = std::cell::RefCell::new(vec![]);
+
+        // Drop the stream partway through the first page.
+        let partial = client
+            .stream_search_from(&query, None, |key| saved.borrow_mut().push(key.clone()))
+            .take(2)
+            .collect::<Vec<_>>()
+            .await;
+        assert_eq!(partial.len(), 2);
+        assert!(saved.borrow().is_empty());
+
+        let result = client
+            .stream_search_from(&query, None, |key| saved.borrow_mut().push(key.clone()))
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+
+        assert_eq!(result, items);
+        assert_eq!(*saved.borrow(), vec![key]);
+    }
+
+    #[test]
+    fn lenient_rows() {
+        let query = super::CdxQuery::new("example.com");
+        let row = |values: &[&str]| values.iter().map(|v| v.to_string()).collect::<Vec<_>>();
+        let rows = vec![
+            row(&[
+                "https://example.com/",
+                "20200101000000",
+                "ZHYT52YPEOCHJD5FZINSDYXGQZI22WJ4",
+                "text/html",
+                "1234",
+                "200",
+            ]),
+            row(&["https://example.com/", "20200101000000"]),
+            row(&[
+                "https://example.com/",
+                "not a timestamp",
+                "ZHYT52YPEOCHJD5FZINSDYXGQZI22WJ4",
+                "text/html",
+                "1234",
+                "200",
+            ]),
+        ];
+        let decode = |_: &super::CdxQuery, row: &[String]| IndexClient::decode_row(row);
+
+        let strict = IndexClient::default();
+        assert!(strict.decode_page(&query, &rows, decode).is_err());
+
+        let lenient = IndexClient::default().with_lenient_rows(true);
+        let items = lenient.decode_page(&query, &rows, decode).unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(lenient.skipped_rows(), 2);
+    }
+
+    #[test]
+    fn load_json() {
+        let file = File::open("examples/wayback/cdx-result.json").unwrap();
+        let result = IndexClient::load_json(file).unwrap();
+
+        assert_eq!(result.len(), 37);
+    }
+
+    #[test]
+    fn load_json_fixture() {
+        let items = crate::fixtures::items(10)
+            .into_iter()
+            .map(|(item, _)| item)
+            .collect::<Vec<_>>();
+        let json = crate::fixtures::cdx_json(&items, None);
+        let result = IndexClient::load_json(json.as_bytes()).unwrap();
+
+        assert_eq!(result, items);
+    }
+}
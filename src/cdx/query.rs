@@ -0,0 +1,512 @@
+//! A builder for CDX search queries.
+
+use super::{Error, ExtraField, Field, Filter};
+use chrono::{NaiveDate, NaiveDateTime, TimeDelta};
+use std::cmp::Reverse;
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+/// The fields that are always requested, since they are needed to build items.
+const ITEM_FIELDS: &str = "original,timestamp,digest,mimetype,length,statuscode";
+
+/// Templates for completing partial timestamps to the start or end of a period.
+const START_TEMPLATE: &str = "19700101000000";
+const END_TEMPLATE: &str = "99991231235959";
+
+/// How the query URL is matched against captured URLs.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MatchType {
+    Exact,
+    /// All URLs under the given path.
+    Prefix,
+    /// All URLs on the given host.
+    Host,
+    /// All URLs on the given host and its subdomains.
+    Domain,
+}
+
+impl MatchType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MatchType::Exact => "exact",
+            MatchType::Prefix => "prefix",
+            MatchType::Host => "host",
+            MatchType::Domain => "domain",
+        }
+    }
+}
+
+/// An alternative order for CDX results.
+///
+/// By default rows are ordered by URL key and then by capture time.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Sort {
+    /// The most recent captures first.
+    Reverse,
+    /// The captures closest to the given timestamp first.
+    Closest(Timestamp),
+}
+
+/// A full or partial capture timestamp used as a search bound.
+///
+/// Partial timestamps (e.g. `2020` or `202006`) have between 4 and 14 digits,
+/// and the CDX server completes them to the start or end of the period.
+#[derive(Clone, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub struct Timestamp(String);
+
+impl Timestamp {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The first second of the period identified by this timestamp.
+    pub fn start(&self) -> Option<NaiveDateTime> {
+        crate::util::parse_timestamp(&format!("{}{}", self.0, &START_TEMPLATE[self.0.len()..]))
+    }
+
+    /// The last second of the period identified by this timestamp.
+    pub fn end(&self) -> Option<NaiveDateTime> {
+        let completed = format!("{}{}", self.0, &END_TEMPLATE[self.0.len()..]);
+
+        // If the day was filled in, it may need to be moved back to the end of
+        // a shorter month.
+        ["31", "30", "29", "28"].iter().find_map(|day| {
+            let mut candidate = completed.clone();
+            if self.0.len() < 8 {
+                candidate.replace_range(6..8, day);
+            }
+            crate::util::parse_timestamp(&candidate)
+        })
+    }
+}
+
+impl FromStr for Timestamp {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        if (4..=14).contains(&value.len()) && value.bytes().all(|byte| byte.is_ascii_digit()) {
+            Ok(Self(value.to_string()))
+        } else {
+            Err(Error::InvalidTimestamp(value.to_string()))
+        }
+    }
+}
+
+impl From<NaiveDateTime> for Timestamp {
+    fn from(value: NaiveDateTime) -> Self {
+        Self(crate::util::to_timestamp(&value))
+    }
+}
+
+impl Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CdxQuery {
+    url: String,
+    match_type: Option<MatchType>,
+    from: Option<Timestamp>,
+    to: Option<Timestamp>,
+    collapse: Vec<String>,
+    filters: Vec<Filter>,
+    limit: Option<usize>,
+    /// Whether the limit applies to the end of the results.
+    last: bool,
+    extras: Vec<ExtraField>,
+    sort: Option<Sort>,
+    /// Whether rows should be re-sorted on the client, page by page.
+    sort_pages: bool,
+    /// Whether only timestamps should be requested (for counting).
+    count_only: bool,
+}
+
+impl CdxQuery {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            match_type: None,
+            from: None,
+            to: None,
+            collapse: vec![],
+            filters: vec![],
+            limit: None,
+            last: false,
+            extras: vec![],
+            sort: None,
+            sort_pages: false,
+            count_only: false,
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    pub fn extras(&self) -> &[ExtraField] {
+        &self.extras
+    }
+
+    pub fn with_match_type(mut self, match_type: MatchType) -> Self {
+        self.match_type = Some(match_type);
+        self
+    }
+
+    /// Only include captures at or after this (possibly partial) timestamp.
+    pub fn with_from<T: Into<Timestamp>>(mut self, timestamp: T) -> Self {
+        self.from = Some(timestamp.into());
+        self
+    }
+
+    /// Only include captures at or before this (possibly partial) timestamp.
+    pub fn with_to<T: Into<Timestamp>>(mut self, timestamp: T) -> Self {
+        self.to = Some(timestamp.into());
+        self
+    }
+
+    /// Only include captures between the two (possibly partial) timestamps,
+    /// inclusive.
+    ///
+    /// Either bound may be omitted. Fails if a timestamp string is invalid.
+    pub fn with_range(self, from: Option<&str>, to: Option<&str>) -> Result<Self, Error> {
+        let from = from.map(str::parse::<Timestamp>).transpose()?;
+        let to = to.map(str::parse::<Timestamp>).transpose()?;
+
+        Ok(Self { from, to, ..self })
+    }
+
+    /// Collapse adjacent rows with the same value for the field.
+    pub fn with_collapse(mut self, field: Field) -> Self {
+        self.collapse.push(field.as_str().to_string());
+        self
+    }
+
+    /// Collapse adjacent rows with the same first `length` characters of the
+    /// field (e.g. `timestamp` with 8 for one capture per day).
+    pub fn with_collapse_prefix(mut self, field: Field, length: usize) -> Self {
+        self.collapse.push(format!("{}:{}", field.as_str(), length));
+        self
+    }
+
+    pub fn with_filter(mut self, filter: Filter) -> Self {
+        self.filters.push(filter);
+        self
+    }
+
+    /// The maximum number of rows (or the page size for streaming searches).
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Only return the last rows (for example the latest capture before the end
+    /// of the range).
+    ///
+    /// This is not supported for streaming searches.
+    pub fn with_last(mut self, count: usize) -> Self {
+        self.limit = Some(count);
+        self.last = true;
+        self
+    }
+
+    /// Ask the CDX server to return results in the given order.
+    pub fn with_sort(mut self, sort: Sort) -> Self {
+        self.sort = Some(sort);
+        self
+    }
+
+    /// Stably re-sort the rows of each page by capture time (or by the
+    /// requested sort order) before yielding them, since the CDX server does
+    /// not always return rows in order.
+    ///
+    /// Items are then only yielded once their whole page has been read. Only
+    /// rows within a page are re-sorted, and for queries matching more than one
+    /// URL the rows for different URLs are interleaved.
+    pub fn with_page_sort(mut self, enabled: bool) -> Self {
+        self.sort_pages = enabled;
+        self
+    }
+
+    /// Request an additional field.
+    pub fn with_extra_field(mut self, extra: ExtraField) -> Self {
+        if !self.extras.contains(&extra) {
+            self.extras.push(extra);
+        }
+        self
+    }
+
+    /// Split the query into the given number of queries for consecutive,
+    /// non-overlapping time ranges.
+    ///
+    /// Missing bounds are taken to be the start of 1996 and the given time.
+    pub(super) fn time_slices(&self, count: usize, now: NaiveDateTime) -> Vec<CdxQuery> {
+        let start = self
+            .from
+            .as_ref()
+            .and_then(Timestamp::start)
+            .unwrap_or_else(earliest_capture);
+        let end = self.to.as_ref().and_then(Timestamp::end).unwrap_or(now);
+        let seconds = (end - start).num_seconds() + 1;
+
+        if seconds <= 0 {
+            return vec![self.clone()];
+        }
+
+        let count = (count.max(1) as i64).min(seconds);
+
+        (0..count)
+            .map(|i| {
+                let offset = |n: i64| start + TimeDelta::seconds(seconds * n / count);
+
+                let mut slice = self.clone();
+                slice.from = Some(offset(i).into());
+                slice.to = Some((offset(i + 1) - TimeDelta::seconds(1)).into());
+                slice
+            })
+            .collect()
+    }
+
+    /// The number of columns in each result row.
+    pub(super) fn field_count(&self) -> usize {
+        self.fields().split(',').count()
+    }
+
+    pub(super) fn sorts_pages(&self) -> bool {
+        self.sort_pages
+    }
+
+    /// Stably sort a page of rows by the timestamp column in the query's order.
+    pub(super) fn sort_page(&self, rows: &mut [Vec<String>]) {
+        let column = if self.count_only { 0 } else { 1 };
+        let timestamp = |row: &Vec<String>| row.get(column).cloned().unwrap_or_default();
+
+        match &self.sort {
+            None => rows.sort_by_cached_key(timestamp),
+            Some(Sort::Reverse) => rows.sort_by_cached_key(|row| Reverse(timestamp(row))),
+            Some(Sort::Closest(target)) => {
+                if let Some(target) = target.start() {
+                    rows.sort_by_cached_key(|row| {
+                        crate::util::parse_timestamp(&timestamp(row))
+                            .map_or(i64::MAX, |time| (time - target).num_seconds().abs())
+                    });
+                }
+            }
+        }
+    }
+
+    /// Request only timestamps, for counting captures.
+    pub(super) fn for_count(mut self) -> Self {
+        self.count_only = true;
+        self
+    }
+
+    /// The query parameters (other than resume key parameters).
+    pub(crate) fn params(&self) -> Vec<(&'static str, String)> {
+        let mut params = vec![("url", self.url.clone())];
+
+        params.extend(
+            self.match_type
+                .map(|match_type| ("matchType", match_type.as_str().to_string())),
+        );
+        params.extend(self.from.iter().map(|from| ("from", from.to_string())));
+        params.extend(self.to.iter().map(|to| ("to", to.to_string())));
+        params.extend(
+            self.collapse
+                .iter()
+                .map(|value| ("collapse", value.clone())),
+        );
+        params.extend(
+            self.filters
+                .iter()
+                .map(|filter| ("filter", filter.to_string())),
+        );
+        params.extend(self.limit.map(|limit| {
+            if self.last {
+                ("limit", format!("-{}", limit))
+            } else {
+                ("limit", limit.to_string())
+            }
+        }));
+        match &self.sort {
+            Some(Sort::Reverse) => params.push(("sort", "reverse".to_string())),
+            Some(Sort::Closest(timestamp)) => {
+                params.push(("sort", "closest".to_string()));
+                params.push(("closest", timestamp.to_string()));
+            }
+            None => {}
+        }
+        params.push(("output", "json".to_string()));
+        params.push(("fl", self.fields()));
+
+        if !self.count_only && self.extras.contains(&ExtraField::DupeCount) {
+            params.push(("showDupeCount", "true".to_string()));
+        }
+
+        params
+    }
+
+    fn fields(&self) -> String {
+        if self.count_only {
+            return "timestamp".to_string();
+        }
+
+        std::iter::once(ITEM_FIELDS)
+            .chain(self.extras.iter().map(|extra| extra.as_str()))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+fn earliest_capture() -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(1996, 1, 1)
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .expect("Invalid date")
+}
+
+impl From<&str> for CdxQuery {
+    fn from(url: &str) -> Self {
+        Self::new(url)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CdxQuery, MatchType, Sort, Timestamp};
+    use crate::cdx::{ExtraField, Field, Filter};
+
+    #[test]
+    fn params() {
+        let query = CdxQuery::new("example.com")
+            .with_match_type(MatchType::Domain)
+            .with_range(Some("2019"), Some("2020"))
+            .unwrap()
+            .with_collapse_prefix(Field::Timestamp, 8)
+            .with_filter(Filter::status(200))
+            .with_limit(10)
+            .with_extra_field(ExtraField::Filename);
+
+        let params = query.params();
+
+        assert_eq!(params[0], ("url", "example.com".to_string()));
+        assert!(params.contains(&("matchType", "domain".to_string())));
+        assert!(params.contains(&("from", "2019".to_string())));
+        assert!(params.contains(&("collapse", "timestamp:8".to_string())));
+        assert!(params.contains(&("filter", "statuscode:200".to_string())));
+        assert!(params.contains(&(
+            "fl",
+            "original,timestamp,digest,mimetype,length,statuscode,filename".to_string()
+        )));
+        assert!(params.contains(&("limit", "10".to_string())));
+
+        let params = CdxQuery::new("example.com").with_last(1).params();
+        assert!(params.contains(&("limit", "-1".to_string())));
+    }
+
+    #[test]
+    fn sorting() {
+        let params = CdxQuery::new("example.com")
+            .with_sort(Sort::Reverse)
+            .params();
+        assert!(params.contains(&("sort", "reverse".to_string())));
+
+        let query = CdxQuery::new("example.com")
+            .with_sort(Sort::Closest("20200601".parse().unwrap()))
+            .with_page_sort(true);
+        let params = query.params();
+        assert!(params.contains(&("sort", "closest".to_string())));
+        assert!(params.contains(&("closest", "20200601".to_string())));
+        assert!(query.sorts_pages());
+
+        let row = |url: &str, timestamp: &str| vec![url.to_string(), timestamp.to_string()];
+        let rows = vec![
+            row("a", "20200101000000"),
+            row("b", "20200602000000"),
+            row("c", "20200501000000"),
+            row("d", "20200101000000"),
+        ];
+        let urls = |rows: &[Vec<String>]| rows.iter().map(|row| row[0].clone()).collect::<String>();
+
+        let mut closest = rows.clone();
+        query.sort_page(&mut closest);
+        assert_eq!(urls(&closest), "bcad");
+
+        let mut ascending = rows.clone();
+        CdxQuery::new("example.com").sort_page(&mut ascending);
+        assert_eq!(urls(&ascending), "adcb");
+
+        let mut descending = rows;
+        CdxQuery::new("example.com")
+            .with_sort(Sort::Reverse)
+            .sort_page(&mut descending);
+        assert_eq!(urls(&descending), "bcad");
+    }
+
+    #[test]
+    fn time_slices() {
+        let now = chrono::NaiveDate::from_ymd_opt(2021, 1, 1)
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .unwrap();
+        let query = CdxQuery::new("example.com")
+            .with_range(Some("2020"), Some("202002"))
+            .unwrap();
+
+        let slices = query.time_slices(2, now);
+        let bounds = slices
+            .iter()
+            .map(|slice| {
+                (
+                    slice.from.as_ref().unwrap().to_string(),
+                    slice.to.as_ref().unwrap().to_string(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            bounds,
+            vec![
+                ("20200101000000".to_string(), "20200130235959".to_string()),
+                ("20200131000000".to_string(), "20200229235959".to_string()),
+            ]
+        );
+
+        let slices = CdxQuery::new("example.com").time_slices(4, now);
+        assert_eq!(slices.len(), 4);
+        assert_eq!(slices[0].from.as_ref().unwrap().as_str(), "19960101000000");
+        assert_eq!(slices[3].to.as_ref().unwrap().as_str(), "20210101000000");
+    }
+
+    #[test]
+    fn dupe_counts() {
+        let query = CdxQuery::new("example.com").with_extra_field(ExtraField::DupeCount);
+
+        assert!(query
+            .params()
+            .contains(&("showDupeCount", "true".to_string())));
+
+        let params = query.for_count().params();
+
+        assert!(params.contains(&("fl", "timestamp".to_string())));
+        assert!(!params.contains(&("showDupeCount", "true".to_string())));
+    }
+
+    #[test]
+    fn timestamps() {
+        let time = chrono::NaiveDate::from_ymd_opt(2020, 6, 1)
+            .and_then(|date| date.and_hms_opt(12, 30, 0))
+            .unwrap();
+
+        assert_eq!(Timestamp::from(time).as_str(), "20200601123000");
+        assert!("202006".parse::<Timestamp>().is_ok());
+        assert!("202".parse::<Timestamp>().is_err());
+        assert!("2020-06".parse::<Timestamp>().is_err());
+        assert!(CdxQuery::new("example.com")
+            .with_range(None, Some("202006011230000"))
+            .is_err());
+    }
+}
@@ -0,0 +1,200 @@
+//! Resume keys for paging through CDX search results.
+//!
+//! The CDX server returns resume keys in form-encoded form, with spaces as `+`
+//! (for example `com%2Cexample%29%2F+20200101000000`). We store the decoded
+//! value and re-encode it when building query URLs.
+//!
+//! Keys provided by users (and saved by [`ResumeKey::save`]) are
+//! percent-encoded, with spaces as `%20`, since a literal `+` may be part of
+//! a key's URL. Decoded keys can also be provided, as long as they don't
+//! contain `%`.
+
+use super::Error;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Characters that are escaped in encoded keys.
+const ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// A validated CDX resume key.
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct ResumeKey(String);
+
+impl ResumeKey {
+    /// Create a resume key from an already-decoded value.
+    pub fn new(value: &str) -> Result<Self, Error> {
+        if value.is_empty() || value.chars().any(char::is_control) {
+            Err(Error::InvalidResumeKey(value.to_string()))
+        } else {
+            Ok(Self(value.to_string()))
+        }
+    }
+
+    /// The decoded value of the key.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Parse a resume key in the form-encoded form returned by the CDX
+    /// server.
+    pub(super) fn from_form_encoded(s: &str) -> Result<Self, Error> {
+        let with_spaces = s.replace('+', " ");
+        let decoded = percent_decode_str(&with_spaces)
+            .decode_utf8()
+            .map_err(|_| Error::InvalidResumeKey(s.to_string()))?;
+
+        Self::new(&decoded)
+    }
+
+    /// The percent-encoded form of the key, suitable for use as a query
+    /// parameter value.
+    pub fn encoded(&self) -> String {
+        utf8_percent_encode(&self.0, ENCODE_SET).to_string()
+    }
+
+    /// Save this key along with the query it belongs to.
+    pub fn save<P: AsRef<Path>>(&self, path: P, query: &str) -> Result<(), Error> {
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(
+            writer,
+            &Checkpoint {
+                query: query.to_string(),
+                resume_key: self.clone(),
+            },
+        )?;
+
+        Ok(())
+    }
+
+    /// Load a key saved for the given query.
+    ///
+    /// Returns an empty value if the file does not exist, and an error if the
+    /// saved key belongs to a different query.
+    pub fn load<P: AsRef<Path>>(path: P, query: &str) -> Result<Option<Self>, Error> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error.into()),
+        };
+
+        let checkpoint: Checkpoint = serde_json::from_reader(BufReader::new(file))?;
+
+        if checkpoint.query == query {
+            Ok(Some(checkpoint.resume_key))
+        } else {
+            Err(Error::ResumeKeyQueryMismatch {
+                expected: query.to_string(),
+                found: checkpoint.query,
+            })
+        }
+    }
+}
+
+impl FromStr for ResumeKey {
+    type Err = Error;
+
+    /// Parse a possibly percent-encoded resume key.
+    ///
+    /// A `+` is kept as it is, not decoded as a space.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let decoded = percent_decode_str(s)
+            .decode_utf8()
+            .map_err(|_| Error::InvalidResumeKey(s.to_string()))?;
+
+        Self::new(&decoded)
+    }
+}
+
+impl TryFrom<String> for ResumeKey {
+    type Error = Error;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<ResumeKey> for String {
+    fn from(value: ResumeKey) -> Self {
+        value.encoded()
+    }
+}
+
+impl Display for ResumeKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.encoded())
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+struct Checkpoint {
+    query: String,
+    resume_key: ResumeKey,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ResumeKey;
+
+    const FORM_ENCODED: &str = "com%2Ctwitter%29%2Ftravisbrown+20201103091610";
+    const ENCODED: &str = "com%2Ctwitter%29%2Ftravisbrown%2020201103091610";
+
+    #[test]
+    fn round_trip() {
+        let key = ResumeKey::from_form_encoded(FORM_ENCODED).unwrap();
+
+        assert_eq!(key.as_str(), "com,twitter)/travisbrown 20201103091610");
+        assert_eq!(key.encoded(), ENCODED);
+        assert_eq!(key, ENCODED.parse::<ResumeKey>().unwrap());
+        assert_eq!(key, key.as_str().parse::<ResumeKey>().unwrap());
+    }
+
+    #[test]
+    fn round_trip_plus() {
+        let key = ResumeKey::new("com,example)/search?q=a+b 20200101000000").unwrap();
+
+        assert_eq!(
+            key.encoded(),
+            "com%2Cexample%29%2Fsearch%3Fq%3Da%2Bb%2020200101000000"
+        );
+        assert_eq!(key, key.to_string().parse::<ResumeKey>().unwrap());
+        assert_eq!(key, key.as_str().parse::<ResumeKey>().unwrap());
+        assert_eq!(
+            ResumeKey::from_form_encoded("com%2Cexample%29%2Fsearch%3Fq%3Da%2Bb+20200101000000")
+                .unwrap(),
+            key
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        assert!("".parse::<ResumeKey>().is_err());
+        assert!("abc%0A".parse::<ResumeKey>().is_err());
+    }
+
+    #[test]
+    fn persistence() {
+        let base = crate::fixtures::temp_dir("resume-key").unwrap();
+        let path = base.join("resume-key.json");
+        let key = ENCODED.parse::<ResumeKey>().unwrap();
+
+        key.save(&path, "twitter.com/travisbrown*").unwrap();
+
+        assert_eq!(
+            ResumeKey::load(&path, "twitter.com/travisbrown*").unwrap(),
+            Some(key)
+        );
+        assert!(ResumeKey::load(&path, "twitter.com/other*").is_err());
+
+        std::fs::remove_dir_all(base).unwrap();
+    }
+}
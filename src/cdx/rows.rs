@@ -0,0 +1,289 @@
+//! Incremental decoding of CDX JSON responses.
+//!
+//! A JSON response is an array of rows (arrays of strings), where the first
+//! row contains the field names and, when resume keys are requested, an empty
+//! row is followed by a row containing only the key. The decoder parses each
+//! row as soon as it is complete, so large pages never need to be held in
+//! memory as a single string.
+//!
+//! The CDX server sometimes sends gzipped bodies without a `Content-Encoding`
+//! header (so the HTTP client doesn't decompress them), and these may contain
+//! several gzip members. Bodies are therefore checked for the gzip magic bytes
+//! and decompressed here if necessary.
+
+use super::{Error, ResumeKey};
+use flate2::{read::MultiGzDecoder, write::MultiGzDecoder as MultiGzWriter};
+use std::borrow::Cow;
+use std::io::{Read, Write};
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+/// Decompress a complete body if it is gzipped.
+pub(super) fn decompress(body: &[u8]) -> Result<Cow<'_, [u8]>, Error> {
+    if body.starts_with(&GZIP_MAGIC) {
+        let mut decompressed = vec![];
+        MultiGzDecoder::new(body).read_to_end(&mut decompressed)?;
+        Ok(Cow::Owned(decompressed))
+    } else {
+        Ok(Cow::Borrowed(body))
+    }
+}
+
+enum Encoding {
+    /// Not enough of the body has been seen to tell.
+    Unknown(Vec<u8>),
+    Identity,
+    Gzip(Box<MultiGzWriter<Vec<u8>>>),
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Encoding::Unknown(vec![])
+    }
+}
+
+#[derive(Default)]
+pub(super) struct RowDecoder {
+    encoding: Encoding,
+    buffer: Vec<u8>,
+    position: usize,
+    depth: usize,
+    in_string: bool,
+    escaped: bool,
+    row_start: Option<usize>,
+    started: bool,
+    raw: bool,
+    header_seen: bool,
+    resume_key_next: bool,
+    resume_key: Option<ResumeKey>,
+}
+
+impl RowDecoder {
+    /// Add a chunk of the response body, returning any rows (other than the
+    /// header and resume key rows) that are now complete.
+    pub(super) fn push(&mut self, chunk: &[u8]) -> Result<Vec<Vec<String>>, Error> {
+        match &mut self.encoding {
+            Encoding::Unknown(prefix) => {
+                prefix.extend_from_slice(chunk);
+
+                if prefix.len() < GZIP_MAGIC.len() {
+                    return Ok(vec![]);
+                }
+
+                let prefix = std::mem::take(prefix);
+                self.encoding = if prefix.starts_with(&GZIP_MAGIC) {
+                    Encoding::Gzip(Box::new(MultiGzWriter::new(vec![])))
+                } else {
+                    Encoding::Identity
+                };
+
+                self.push(&prefix)
+            }
+            Encoding::Identity => self.decode(chunk),
+            Encoding::Gzip(decoder) => {
+                decoder.write_all(chunk)?;
+                decoder.flush()?;
+                let decompressed = std::mem::take(decoder.get_mut());
+
+                self.decode(&decompressed)
+            }
+        }
+    }
+
+    fn decode(&mut self, chunk: &[u8]) -> Result<Vec<Vec<String>>, Error> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut rows = vec![];
+
+        // Anything other than JSON (such as a blocked site message) is buffered
+        // in full so that it can be inspected.
+        if self.raw {
+            return Ok(rows);
+        }
+
+        while self.position < self.buffer.len() {
+            let byte = self.buffer[self.position];
+
+            if !self.started && byte != b'[' && !byte.is_ascii_whitespace() {
+                self.raw = true;
+                return Ok(rows);
+            }
+
+            if self.in_string {
+                if self.escaped {
+                    self.escaped = false;
+                } else if byte == b'\\' {
+                    self.escaped = true;
+                } else if byte == b'"' {
+                    self.in_string = false;
+                }
+            } else {
+                match byte {
+                    b'"' => self.in_string = true,
+                    b'[' => {
+                        self.depth += 1;
+                        if self.depth == 1 {
+                            self.started = true;
+                        } else if self.depth == 2 {
+                            self.row_start = Some(self.position);
+                        }
+                    }
+                    b']' => {
+                        if self.depth == 2 {
+                            let start = self.row_start.take().unwrap_or_default();
+                            let row = serde_json::from_slice::<Vec<String>>(
+                                &self.buffer[start..=self.position],
+                            )?;
+                            rows.extend(self.accept(row)?);
+                        }
+                        self.depth = self.depth.saturating_sub(1);
+                    }
+                    _ => {}
+                }
+            }
+
+            self.position += 1;
+        }
+
+        if self.started {
+            let consumed = self.row_start.unwrap_or(self.position);
+            self.buffer.drain(..consumed);
+            self.position -= consumed;
+            self.row_start = self.row_start.map(|start| start - consumed);
+        }
+
+        Ok(rows)
+    }
+
+    /// The body so far, if it is not JSON.
+    pub(super) fn raw_body(&self) -> Option<&[u8]> {
+        if self.started {
+            None
+        } else {
+            Some(&self.buffer)
+        }
+    }
+
+    /// Finish decoding, returning the resume key if there was one.
+    pub(super) fn finish(mut self) -> Result<Option<ResumeKey>, Error> {
+        match std::mem::replace(&mut self.encoding, Encoding::Identity) {
+            // A body too short to contain rows.
+            Encoding::Unknown(prefix) => {
+                self.decode(&prefix)?;
+            }
+            Encoding::Identity => {}
+            // Fails if the gzip stream was truncated.
+            Encoding::Gzip(mut decoder) => decoder.try_finish()?,
+        }
+
+        if !self.started || self.depth > 0 {
+            // Produce the JSON error that a complete parse would have.
+            serde_json::from_slice::<Vec<Vec<String>>>(&self.buffer)?;
+        }
+
+        Ok(self.resume_key)
+    }
+
+    fn accept(&mut self, row: Vec<String>) -> Result<Option<Vec<String>>, Error> {
+        if !self.header_seen {
+            self.header_seen = true;
+            Ok(None)
+        } else if self.resume_key_next {
+            self.resume_key_next = false;
+            self.resume_key = row
+                .first()
+                .map(|key| ResumeKey::from_form_encoded(key))
+                .transpose()?;
+            Ok(None)
+        } else if row.is_empty() {
+            self.resume_key_next = true;
+            Ok(None)
+        } else {
+            Ok(Some(row))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RowDecoder;
+    use crate::cdx::ResumeKey;
+
+    #[test]
+    fn decode_in_chunks() {
+        let items = crate::fixtures::items(5)
+            .into_iter()
+            .map(|(item, _)| item)
+            .collect::<Vec<_>>();
+        let key = ResumeKey::new("com,example)/ 20200101000000").unwrap();
+        let json = crate::fixtures::cdx_json(&items, Some(&key));
+
+        let mut decoder = RowDecoder::default();
+        let mut rows = vec![];
+
+        for chunk in json.as_bytes().chunks(7) {
+            rows.extend(decoder.push(chunk).unwrap());
+        }
+
+        assert_eq!(
+            rows,
+            items
+                .iter()
+                .map(|item| item.to_record())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(decoder.finish().unwrap(), Some(key));
+    }
+
+    #[test]
+    fn decode_gzipped() {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+
+        let items = crate::fixtures::items(5)
+            .into_iter()
+            .map(|(item, _)| item)
+            .collect::<Vec<_>>();
+        let json = crate::fixtures::cdx_json(&items, None);
+        let (first, second) = json.split_at(json.len() / 2);
+
+        // The server may send several gzip members.
+        let mut compressed = vec![];
+        for part in [first, second] {
+            let mut encoder = GzEncoder::new(vec![], Compression::default());
+            encoder.write_all(part.as_bytes()).unwrap();
+            compressed.extend(encoder.finish().unwrap());
+        }
+
+        let mut decoder = RowDecoder::default();
+        let mut rows = vec![];
+        for chunk in compressed.chunks(7) {
+            rows.extend(decoder.push(chunk).unwrap());
+        }
+
+        assert_eq!(
+            rows,
+            items
+                .iter()
+                .map(|item| item.to_record())
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(decoder.finish().unwrap(), None);
+        assert_eq!(
+            super::decompress(&compressed).unwrap().as_ref(),
+            json.as_bytes()
+        );
+    }
+
+    #[test]
+    fn decode_invalid() {
+        let mut decoder = RowDecoder::default();
+        decoder.push(b"Blocked").unwrap();
+        assert_eq!(decoder.raw_body(), Some(&b"Blocked"[..]));
+
+        let mut decoder = RowDecoder::default();
+        decoder.push(b"[[\"original\"],\n[\"a\", \"b").unwrap();
+        assert!(decoder.finish().is_err());
+        assert!(RowDecoder::default().finish().is_err());
+    }
+}
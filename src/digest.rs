@@ -6,7 +6,7 @@
 use data_encoding::BASE32;
 use flate2::read::GzDecoder;
 use sha1::{Digest, Sha1};
-use std::io::{BufWriter, Error, Read};
+use std::io::{BufWriter, Error, Read, Write};
 
 /// Decode a Base32 string into the SHA-1 bytes, returning an empty value if
 /// the input is not a valid Base2-encoded SHA-1 hash.
@@ -52,10 +52,77 @@ pub fn compute_digest_gz<R: Read>(input: &mut R) -> Result<String, Error> {
     compute_digest(&mut GzDecoder::new(input))
 }
 
+/// A reader that computes the SHA-1 hash of the bytes read through it.
+pub struct DigestReader<R> {
+    underlying: R,
+    sha1: Sha1,
+}
+
+impl<R: Read> DigestReader<R> {
+    pub fn new(underlying: R) -> Self {
+        Self {
+            underlying,
+            sha1: Sha1::new(),
+        }
+    }
+
+    /// The Base32-encoded digest of the bytes read so far.
+    pub fn digest(self) -> String {
+        BASE32.encode(&self.sha1.finalize())
+    }
+}
+
+impl<R: Read> Read for DigestReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let count = self.underlying.read(buf)?;
+        self.sha1.update(&buf[..count]);
+        Ok(count)
+    }
+}
+
+/// A writer that computes the SHA-1 hash of the bytes written through it.
+pub struct DigestWriter<W> {
+    underlying: W,
+    sha1: Sha1,
+    bytes_written: u64,
+}
+
+impl<W: Write> DigestWriter<W> {
+    pub fn new(underlying: W) -> Self {
+        Self {
+            underlying,
+            sha1: Sha1::new(),
+            bytes_written: 0,
+        }
+    }
+
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// The underlying writer and the Base32-encoded digest of the bytes written.
+    pub fn finish(self) -> (W, String) {
+        (self.underlying, BASE32.encode(&self.sha1.finalize()))
+    }
+}
+
+impl<W: Write> Write for DigestWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let count = self.underlying.write(buf)?;
+        self.sha1.update(&buf[..count]);
+        self.bytes_written += count as u64;
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.underlying.flush()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs::File;
-    use std::io::BufReader;
+    use std::io::{BufReader, Read, Write};
 
     #[test]
     fn compute_digest() {
@@ -67,11 +134,31 @@ mod tests {
         assert_eq!(super::compute_digest(&mut reader).unwrap(), digest);
     }
 
+    #[test]
+    fn digest_writer() {
+        let digest = "ZHYT52YPEOCHJD5FZINSDYXGQZI22WJ4";
+        let mut content = vec![];
+        File::open(format!("examples/wayback/{}", digest))
+            .unwrap()
+            .read_to_end(&mut content)
+            .unwrap();
+
+        let mut writer = super::DigestWriter::new(vec![]);
+        for chunk in content.chunks(1000) {
+            writer.write_all(chunk).unwrap();
+        }
+        assert_eq!(writer.bytes_written(), content.len() as u64);
+
+        let (written, computed) = writer.finish();
+        assert_eq!(written, content);
+        assert_eq!(computed, digest);
+    }
+
     #[test]
     fn round_trip() {
         let digest = "ZHYT52YPEOCHJD5FZINSDYXGQZI22WJ4";
 
-        let bytes = super::string_to_bytes(&digest).unwrap();
+        let bytes = super::string_to_bytes(digest).unwrap();
         let string = super::bytes_to_string(&bytes);
 
         assert_eq!(digest, string);
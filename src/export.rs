@@ -0,0 +1,365 @@
+//! Incremental export of collections, and packaging of session results as
+//! distributable datasets.
+//!
+//! An export manifest records the digests that a recipient already holds, so
+//! that later exports only need to include items added since then.
+
+use crate::{session, store::data::Store, Item};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs::{copy, create_dir_all, read_dir, File};
+use std::io::{BufRead, BufReader, BufWriter, ErrorKind, Write};
+use std::path::Path;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("CSV error")]
+    Csv(#[from] csv::Error),
+    #[error("JSON error")]
+    Json(#[from] serde_json::Error),
+    #[error("Session error")]
+    Session(#[from] session::Error),
+}
+
+/// The set of digests included in an export.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Manifest {
+    pub digests: BTreeSet<String>,
+}
+
+impl Manifest {
+    /// Load a manifest, returning an empty one if the file does not exist.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        match File::open(path) {
+            Ok(file) => Ok(serde_json::from_reader(BufReader::new(file))?),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(Self::default()),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        Ok(serde_json::to_writer_pretty(
+            BufWriter::new(File::create(path)?),
+            self,
+        )?)
+    }
+
+    pub fn contains(&self, digest: &str) -> bool {
+        self.digests.contains(digest)
+    }
+}
+
+/// The items added since a previous export, with the manifest for the
+/// combined collection.
+#[derive(Debug)]
+pub struct Delta {
+    pub items: Vec<Item>,
+    pub manifest: Manifest,
+}
+
+impl Delta {
+    /// The distinct digests of the new items.
+    pub fn digests(&self) -> BTreeSet<&str> {
+        self.items.iter().map(|item| item.digest.as_str()).collect()
+    }
+
+    /// Write the delta to a directory as `items.csv`, a `content.tar` bundle of
+    /// the stored data files, and the updated `manifest.json`.
+    ///
+    /// Returns the digests that were not found in the store (and are therefore
+    /// missing from the bundle).
+    pub fn write<P: AsRef<Path>>(&self, dir: P, store: &Store) -> Result<Vec<String>, Error> {
+        let dir = dir.as_ref();
+        create_dir_all(dir)?;
+
+        let mut csv = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_path(dir.join("items.csv"))?;
+
+        for item in &self.items {
+            csv.write_record(item.to_record())?;
+        }
+        csv.flush()?;
+
+        let mut bundle = tar::Builder::new(BufWriter::new(File::create(dir.join("content.tar"))?));
+        let mut missing = vec![];
+
+        for digest in self.digests() {
+            match store.lookup(digest) {
+                Some(path) => bundle.append_path_with_name(path, format!("{}.gz", digest))?,
+                None => missing.push(digest.to_string()),
+            }
+        }
+        bundle.into_inner()?;
+
+        self.manifest.save(dir.join("manifest.json"))?;
+
+        Ok(missing)
+    }
+}
+
+/// Select the items whose digests were not included in the previous export.
+pub fn delta<I: IntoIterator<Item = Item>>(previous: &Manifest, current: I) -> Delta {
+    let mut manifest = previous.clone();
+    let mut items = vec![];
+
+    for item in current {
+        if !previous.contains(&item.digest) {
+            manifest.digests.insert(item.digest.clone());
+            items.push(item);
+        }
+    }
+
+    Delta { items, manifest }
+}
+
+/// Metadata describing a packaged dataset, saved as `dataset.json`.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Dataset {
+    pub tool: String,
+    pub version: String,
+    /// The packaging time in RFC 3339 format.
+    pub created: String,
+    pub items: usize,
+    pub digests: usize,
+    pub queries: Vec<String>,
+    pub blocked_queries: Vec<String>,
+    /// The paths of the dataset's files, relative to its directory.
+    pub files: Vec<String>,
+}
+
+impl Dataset {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?)
+    }
+}
+
+/// The session item files included in a dataset.
+const ITEM_FILES: [&str; 3] = ["originals", "redirects", "extras"];
+
+/// Assemble a distributable dataset from a session directory.
+///
+/// The dataset directory contains `items.csv` (the distinct items from all of
+/// the session's item files), a `manifest.json` digest manifest, copies of
+/// the session's query lists and error reports, a generated `README.md`, and a
+/// `dataset.json` file describing the rest.
+pub fn package<P: AsRef<Path>, Q: AsRef<Path>>(session_dir: P, dir: Q) -> Result<Dataset, Error> {
+    let session_dir = session_dir.as_ref();
+    let dir = dir.as_ref();
+    create_dir_all(dir)?;
+
+    let mut items = vec![];
+    for name in ITEM_FILES {
+        match session::read_items(session_dir, name) {
+            Ok(batch) => items.extend(batch),
+            Err(session::Error::Io(error)) if error.kind() == ErrorKind::NotFound => {}
+            Err(error) => return Err(error.into()),
+        }
+    }
+    items.sort();
+    items.dedup();
+
+    let mut files = vec!["items.csv".to_string(), "manifest.json".to_string()];
+
+    let mut csv = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_path(dir.join("items.csv"))?;
+    for item in &items {
+        csv.write_record(item.to_record())?;
+    }
+    csv.flush()?;
+
+    let manifest = Manifest {
+        digests: items.iter().map(|item| item.digest.clone()).collect(),
+    };
+    manifest.save(dir.join("manifest.json"))?;
+
+    let queries = read_lines(&session_dir.join("queries.txt"))?;
+    let blocked_queries = read_lines(&session_dir.join("blocked.txt"))?;
+
+    for name in ["queries.txt", "blocked.txt"] {
+        if session_dir.join(name).is_file() {
+            copy(session_dir.join(name), dir.join(name))?;
+            files.push(name.to_string());
+        }
+    }
+
+    let errors_dir = session_dir.join("errors");
+    if errors_dir.is_dir() {
+        create_dir_all(dir.join("reports"))?;
+
+        let mut names = read_dir(errors_dir)?
+            .map(|entry| entry.map(|entry| entry.file_name()))
+            .collect::<Result<Vec<_>, _>>()?;
+        names.sort();
+
+        for name in names {
+            let source = session_dir.join("errors").join(&name);
+            if source.is_file() {
+                copy(source, dir.join("reports").join(&name))?;
+                files.push(format!("reports/{}", name.to_string_lossy()));
+            }
+        }
+    }
+
+    files.push("README.md".to_string());
+    files.push("dataset.json".to_string());
+
+    let dataset = Dataset {
+        tool: env!("CARGO_PKG_NAME").to_string(),
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        created: chrono::Utc::now().to_rfc3339(),
+        items: items.len(),
+        digests: manifest.digests.len(),
+        queries,
+        blocked_queries,
+        files,
+    };
+
+    write_readme(&dir.join("README.md"), &dataset)?;
+    serde_json::to_writer_pretty(
+        BufWriter::new(File::create(dir.join("dataset.json"))?),
+        &dataset,
+    )?;
+
+    Ok(dataset)
+}
+
+/// Read the non-empty lines of a file, returning none if it does not exist.
+fn read_lines(path: &Path) -> Result<Vec<String>, Error> {
+    match File::open(path) {
+        Ok(file) => {
+            let mut lines = vec![];
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+                if !line.trim().is_empty() {
+                    lines.push(line.trim().to_string());
+                }
+            }
+            Ok(lines)
+        }
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(vec![]),
+        Err(error) => Err(error.into()),
+    }
+}
+
+fn write_readme(path: &Path, dataset: &Dataset) -> Result<(), Error> {
+    let mut readme = BufWriter::new(File::create(path)?);
+
+    writeln!(readme, "# Wayback Machine capture dataset\n")?;
+    writeln!(
+        readme,
+        "Packaged by {} {} at {}.\n",
+        dataset.tool, dataset.version, dataset.created
+    )?;
+    writeln!(
+        readme,
+        "The dataset contains {} captures with {} distinct digests from {} queries ({} blocked).\n",
+        dataset.items,
+        dataset.digests,
+        dataset.queries.len(),
+        dataset.blocked_queries.len()
+    )?;
+    writeln!(readme, "## Files\n")?;
+
+    for file in &dataset.files {
+        let description = match file.as_str() {
+            "items.csv" => "captures (URL, timestamp, digest, MIME type, length, status)",
+            "manifest.json" => "the distinct digests of the captures",
+            "queries.txt" => "the search queries",
+            "blocked.txt" => "queries that were blocked",
+            "README.md" => "this file",
+            "dataset.json" => "machine-readable metadata for the dataset",
+            _ => "error report from the run",
+        };
+        writeln!(readme, "* `{}`: {}", file, description)?;
+    }
+
+    readme.flush()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{delta, package, Dataset, Manifest};
+
+    #[test]
+    fn delta_since_manifest() {
+        let items = crate::fixtures::items(10)
+            .into_iter()
+            .map(|(item, _)| item)
+            .collect::<Vec<_>>();
+
+        let first = delta(&Manifest::default(), items[..6].to_vec());
+        assert_eq!(first.items.len(), 6);
+
+        let second = delta(&first.manifest, items.clone());
+        assert_eq!(second.items, items[6..].to_vec());
+        assert_eq!(second.manifest.digests.len(), 10);
+    }
+
+    #[test]
+    fn package_session() {
+        let session_dir = crate::fixtures::temp_dir("package-session").unwrap();
+        let dataset_dir = crate::fixtures::temp_dir("package-dataset").unwrap();
+        let items = crate::fixtures::items(6)
+            .into_iter()
+            .map(|(item, _)| item)
+            .collect::<Vec<_>>();
+
+        let mut originals = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_path(session_dir.join("originals.csv"))
+            .unwrap();
+        for item in items.iter().chain(&items[..2]) {
+            originals.write_record(item.to_record()).unwrap();
+        }
+        originals.flush().unwrap();
+
+        std::fs::write(
+            session_dir.join("queries.txt"),
+            "example.com/*\nexample.org/*\n",
+        )
+        .unwrap();
+        std::fs::create_dir_all(session_dir.join("errors")).unwrap();
+        std::fs::write(session_dir.join("errors").join("items.csv"), "").unwrap();
+
+        let dataset = package(&session_dir, &dataset_dir).unwrap();
+
+        assert_eq!(dataset.items, 6);
+        assert_eq!(dataset.digests, 6);
+        assert_eq!(dataset.queries, vec!["example.com/*", "example.org/*"]);
+        assert!(dataset.blocked_queries.is_empty());
+        assert_eq!(
+            dataset.files,
+            vec![
+                "items.csv",
+                "manifest.json",
+                "queries.txt",
+                "reports/items.csv",
+                "README.md",
+                "dataset.json"
+            ]
+        );
+        for file in &dataset.files {
+            assert!(dataset_dir.join(file).is_file());
+        }
+        assert_eq!(
+            Dataset::load(dataset_dir.join("dataset.json")).unwrap(),
+            dataset
+        );
+        assert_eq!(
+            Manifest::load(dataset_dir.join("manifest.json"))
+                .unwrap()
+                .digests
+                .len(),
+            6
+        );
+
+        std::fs::remove_dir_all(session_dir).unwrap();
+        std::fs::remove_dir_all(dataset_dir).unwrap();
+    }
+}
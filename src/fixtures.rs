@@ -0,0 +1,158 @@
+//! Deterministic generators for test data.
+//!
+//! These helpers synthesize store trees, CDX JSON responses, and session CSV
+//! files with known digests, so that integration tests don't need to copy
+//! files from `examples/wayback`.
+
+use super::{cdx::ResumeKey, digest::compute_digest, util::parse_timestamp, Item};
+use csv::WriterBuilder;
+use flate2::{write::GzEncoder, Compression};
+use std::fs::{create_dir_all, File};
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+
+/// Generate an HTML page whose contents are determined by the seed.
+pub fn content(seed: u64) -> Vec<u8> {
+    format!(
+        "<html><head><title>Page {}</title></head><body><p>{}</p></body></html>",
+        seed,
+        seed.wrapping_mul(0x9E37_79B9_7F4A_7C15)
+    )
+    .into_bytes()
+}
+
+/// Generate an item for the given content, with the correct digest and length.
+pub fn item(url: &str, timestamp: &str, content: &[u8]) -> Item {
+    let mut bytes = content;
+
+    Item::new(
+        url.to_string(),
+        parse_timestamp(timestamp).expect("Invalid fixture timestamp"),
+        compute_digest(&mut bytes).expect("Digest computation failed"),
+        "text/html".to_string(),
+        content.len() as u64,
+        Some(200),
+    )
+}
+
+/// Generate `count` items (and their contents) for pages under `https://example.com/`.
+pub fn items(count: u64) -> Vec<(Item, Vec<u8>)> {
+    (0..count)
+        .map(|i| {
+            let content = content(i);
+            let timestamp = format!("2020{:02}{:02}000000", i % 12 + 1, i % 28 + 1);
+            let item = item(&format!("https://example.com/{}", i), &timestamp, &content);
+
+            (item, content)
+        })
+        .collect()
+}
+
+/// Create a new empty directory under the system temporary directory.
+pub fn temp_dir(name: &str) -> std::io::Result<PathBuf> {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |duration| duration.subsec_nanos());
+    let path = std::env::temp_dir().join(format!(
+        "wayback-rs-{}-{}-{}",
+        name,
+        std::process::id(),
+        nanos
+    ));
+    create_dir_all(&path)?;
+
+    Ok(path)
+}
+
+/// Write the given contents into a store tree under `base`, returning their
+/// digests in the order given.
+pub fn store_tree<P: AsRef<Path>>(base: P, contents: &[Vec<u8>]) -> std::io::Result<Vec<String>> {
+    contents
+        .iter()
+        .map(|content| {
+            let mut bytes = content.as_slice();
+            let digest = compute_digest(&mut bytes)?;
+            let dir = base.as_ref().join(&digest[0..1]);
+            create_dir_all(&dir)?;
+
+            let file = File::create(dir.join(format!("{}.gz", digest)))?;
+            let mut gz = GzEncoder::new(file, Compression::default());
+            gz.write_all(content)?;
+            gz.finish()?;
+
+            Ok(digest)
+        })
+        .collect()
+}
+
+/// Generate a CDX JSON response body for the given items.
+pub fn cdx_json(items: &[Item], resume_key: Option<&ResumeKey>) -> String {
+    let mut rows = vec![vec![
+        "original".to_string(),
+        "timestamp".to_string(),
+        "digest".to_string(),
+        "mimetype".to_string(),
+        "length".to_string(),
+        "statuscode".to_string(),
+    ]];
+    rows.extend(items.iter().map(Item::to_record));
+
+    if let Some(key) = resume_key {
+        rows.push(vec![]);
+        // The CDX server form-encodes resume keys.
+        rows.push(vec![url::form_urlencoded::byte_serialize(
+            key.as_str().as_bytes(),
+        )
+        .collect()]);
+    }
+
+    serde_json::to_string(&rows).expect("Serialization failed")
+}
+
+/// Serve the given response bodies (as JSON) to successive HTTP requests on a
+/// local port, returning the server's base URL.
+pub fn serve(bodies: Vec<String>) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Binding failed");
+    let base = format!("http://{}", listener.local_addr().expect("No address"));
+
+    std::thread::spawn(move || {
+        for body in bodies {
+            let Ok((mut stream, _)) = listener.accept() else {
+                return;
+            };
+            let mut request = vec![];
+            let mut buffer = [0; 1024];
+
+            while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+                match stream.read(&mut buffer) {
+                    Ok(0) | Err(_) => break,
+                    Ok(len) => request.extend_from_slice(&buffer[..len]),
+                }
+            }
+
+            let _ = write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+        }
+    });
+
+    base
+}
+
+/// Generate a session CSV file body (without headers) for the given items.
+pub fn items_csv(items: &[Item]) -> String {
+    let mut writer = WriterBuilder::new().from_writer(vec![]);
+
+    for item in items {
+        writer
+            .write_record(item.to_record())
+            .expect("CSV writing failed");
+    }
+
+    String::from_utf8(writer.into_inner().expect("CSV writing failed"))
+        .expect("CSV output is not UTF-8")
+}
@@ -0,0 +1,84 @@
+//! Probes for checking the availability of the Wayback Machine's services.
+
+use reqwest::{Client, Method, StatusCode};
+use std::time::{Duration, Instant};
+
+const PROBE_TIMEOUT_DURATION: Duration = Duration::from_secs(30);
+const CDX_PROBE_URL: &str =
+    "https://web.archive.org/cdx/search/cdx?url=example.com&limit=1&output=json";
+/// A capture that has been stable for years (also used in our integration tests).
+const CONTENT_PROBE_URL: &str =
+    "https://web.archive.org/web/20201103091610id_/https://twitter.com/travisbrown/status/1323554460765925376";
+const SAVE_PROBE_URL: &str = "https://web.archive.org/save";
+
+/// The result of probing a single service.
+#[derive(Debug)]
+pub struct Probe {
+    pub status: Option<StatusCode>,
+    pub latency: Duration,
+    pub error: Option<reqwest::Error>,
+}
+
+impl Probe {
+    /// A probe is healthy if a response was received and it was not a server
+    /// error or a rate-limit response.
+    pub fn is_healthy(&self) -> bool {
+        self.status.is_some_and(|status| {
+            !status.is_server_error() && status != StatusCode::TOO_MANY_REQUESTS
+        })
+    }
+}
+
+/// Probe results for the CDX server, content downloads, and Save Page Now.
+#[derive(Debug)]
+pub struct HealthReport {
+    pub cdx: Probe,
+    pub content: Probe,
+    pub save: Probe,
+}
+
+impl HealthReport {
+    pub fn is_healthy(&self) -> bool {
+        self.cdx.is_healthy() && self.content.is_healthy() && self.save.is_healthy()
+    }
+}
+
+/// Probe all services using a new client.
+pub async fn health_check() -> reqwest::Result<HealthReport> {
+    let client = Client::builder()
+        .timeout(PROBE_TIMEOUT_DURATION)
+        .redirect(reqwest::redirect::Policy::none())
+        .build()?;
+
+    Ok(health_check_with_client(&client).await)
+}
+
+/// Probe all services concurrently using the given client.
+pub async fn health_check_with_client(client: &Client) -> HealthReport {
+    let (cdx, content, save) = futures::join!(
+        probe(client, Method::GET, CDX_PROBE_URL),
+        probe(client, Method::HEAD, CONTENT_PROBE_URL),
+        probe(client, Method::HEAD, SAVE_PROBE_URL),
+    );
+
+    HealthReport { cdx, content, save }
+}
+
+async fn probe(client: &Client, method: Method, url: &str) -> Probe {
+    let start = Instant::now();
+    let result = client.request(method, url).send().await;
+    let latency = start.elapsed();
+
+    match result {
+        Ok(response) => Probe {
+            status: Some(response.status()),
+            latency,
+            error: None,
+        },
+        Err(error) => Probe {
+            status: None,
+            latency,
+            error: Some(error),
+        },
+    }
+}
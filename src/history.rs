@@ -0,0 +1,291 @@
+//! A high-level API for fetching every capture of a URL, and for following
+//! archived redirects as they were at a given time.
+//!
+//! This combines a CDX search, content downloads, and digest checks, for
+//! consumers that don't need to control each step.
+
+use super::{
+    cdx::{self, CdxQuery, Filter, IndexClient, Timestamp},
+    digest::compute_digest,
+    downloader::{self, ChainEnd, Downloader, RedirectChain, RedirectHop},
+    util::retry_future,
+    Item,
+};
+use bytes::{Buf, Bytes};
+use chrono::NaiveDateTime;
+use futures::{Stream, TryStreamExt};
+use reqwest::StatusCode;
+use std::collections::HashSet;
+use std::time::Duration;
+
+const DEFAULT_PARALLELISM: usize = 2;
+const DEFAULT_PACING: Duration = Duration::from_millis(500);
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("CDX error: {0:?}")]
+    IndexClient(#[from] cdx::Error),
+    #[error("Download error for {}: {error:?}", item.url)]
+    Download {
+        item: Box<Item>,
+        error: downloader::Error,
+    },
+    #[error("Digest mismatch for {}: expected {}, found {actual}", item.url, item.digest)]
+    DigestMismatch { item: Box<Item>, actual: String },
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HistoryOptions {
+    parallelism: usize,
+    pacing: Duration,
+    successful_only: bool,
+    unique_content: bool,
+    verify_digests: bool,
+    from: Option<Timestamp>,
+    to: Option<Timestamp>,
+}
+
+impl Default for HistoryOptions {
+    fn default() -> Self {
+        Self {
+            parallelism: DEFAULT_PARALLELISM,
+            pacing: DEFAULT_PACING,
+            successful_only: true,
+            unique_content: false,
+            verify_digests: true,
+            from: None,
+            to: None,
+        }
+    }
+}
+
+impl HistoryOptions {
+    /// Download at most this many captures at once.
+    pub fn with_parallelism(mut self, parallelism: usize) -> Self {
+        self.parallelism = parallelism.max(1);
+        self
+    }
+
+    /// Wait this long between starting downloads.
+    pub fn with_pacing(mut self, pacing: Duration) -> Self {
+        self.pacing = pacing;
+        self
+    }
+
+    /// Whether to include only captures with a 200 status (the default).
+    ///
+    /// Other captures (such as redirects) may not be downloadable.
+    pub fn with_successful_only(mut self, successful_only: bool) -> Self {
+        self.successful_only = successful_only;
+        self
+    }
+
+    /// Whether to download only the first capture with each digest.
+    pub fn with_unique_content(mut self, unique_content: bool) -> Self {
+        self.unique_content = unique_content;
+        self
+    }
+
+    /// Whether to check downloaded content against the CDX digest (the
+    /// default).
+    pub fn with_verify_digests(mut self, verify_digests: bool) -> Self {
+        self.verify_digests = verify_digests;
+        self
+    }
+
+    /// Only include captures between the two (possibly partial) timestamps.
+    pub fn with_range(mut self, from: Option<Timestamp>, to: Option<Timestamp>) -> Self {
+        self.from = from;
+        self.to = to;
+        self
+    }
+
+    fn query(&self, url: &str) -> CdxQuery {
+        let mut query = CdxQuery::new(url);
+
+        if self.successful_only {
+            query = query.with_filter(Filter::status(200));
+        }
+        if let Some(from) = &self.from {
+            query = query.with_from(from.clone());
+        }
+        if let Some(to) = &self.to {
+            query = query.with_to(to.clone());
+        }
+
+        query
+    }
+}
+
+/// Fetch every capture of a URL with the default clients.
+///
+/// Captures are returned in timestamp order. Failed downloads and digest
+/// mismatches are returned as errors without ending the stream.
+pub fn fetch_history(
+    url: &str,
+    options: HistoryOptions,
+) -> impl Stream<Item = Result<(Item, Bytes), Error>> {
+    fetch_history_with(IndexClient::default(), Downloader::default(), url, options)
+}
+
+/// Fetch every capture of a URL with the given clients.
+pub fn fetch_history_with(
+    index_client: IndexClient,
+    downloader: Downloader,
+    url: &str,
+    options: HistoryOptions,
+) -> impl Stream<Item = Result<(Item, Bytes), Error>> {
+    let query = options.query(url);
+    let pacing = options.pacing;
+    let verify_digests = options.verify_digests;
+    let mut seen = HashSet::new();
+
+    futures::stream::once(async move {
+        let items = retry_future(|| index_client.search(&query)).await?;
+        log::info!("Found {} captures for {}", items.len(), query.url());
+
+        Ok::<_, Error>(futures::stream::iter(items.into_iter().map(Ok)))
+    })
+    .try_flatten()
+    .try_filter(move |item| {
+        futures::future::ready(!options.unique_content || seen.insert(item.digest.clone()))
+    })
+    .and_then(move |item| async move {
+        async_std::task::sleep(pacing).await;
+        Ok(item)
+    })
+    .map_ok(move |item| {
+        let downloader = downloader.clone();
+
+        async move {
+            let content = match downloader.download_item(&item).await {
+                Ok(content) => content,
+                Err(error) => {
+                    return Err(Error::Download {
+                        item: Box::new(item),
+                        error,
+                    })
+                }
+            };
+
+            if verify_digests {
+                let actual = compute_digest(&mut content.clone().reader())
+                    .expect("Reading from memory cannot fail");
+
+                if actual != item.digest {
+                    return Err(Error::DigestMismatch {
+                        item: Box::new(item),
+                        actual,
+                    });
+                }
+            }
+
+            Ok((item, content))
+        }
+    })
+    .try_buffered(options.parallelism)
+}
+
+/// Follow archived redirects from a URL using only captures at or before the
+/// given time, so that a historical link chain isn't assembled from captures
+/// from different eras.
+///
+/// Each hop is the latest successful or redirect capture of its URL at that
+/// time. The chain ends at the first capture that is not a redirect, when a URL
+/// repeats, or when a URL has no capture before the time.
+pub async fn resolve_as_of(
+    index_client: &IndexClient,
+    downloader: &Downloader,
+    url: &str,
+    as_of: NaiveDateTime,
+    max_hops: usize,
+) -> Result<RedirectChain, Error> {
+    let mut current = url.to_string();
+    let mut seen = HashSet::new();
+    let mut hops = vec![];
+    seen.insert(current.clone());
+
+    while hops.len() < max_hops {
+        let query = CdxQuery::new(&current)
+            .with_to(as_of)
+            .with_filter(Filter::status_range(200..=399))
+            .with_last(1);
+        let item = match retry_future(|| index_client.search(&query)).await?.pop() {
+            Some(item) => item,
+            None => {
+                return Ok(RedirectChain {
+                    hops,
+                    end: ChainEnd::NotArchived,
+                })
+            }
+        };
+
+        let status = item
+            .status
+            .and_then(|status| StatusCode::from_u16(status).ok())
+            .unwrap_or(StatusCode::OK);
+        let location = if status.is_redirection() {
+            downloader
+                .resolve_redirect_chain(&item.url, &item.timestamp(), 1)
+                .await
+                .map_err(|error| Error::Download {
+                    item: Box::new(item.clone()),
+                    error,
+                })?
+                .hops
+                .pop()
+                .and_then(|hop| hop.location)
+        } else {
+            None
+        };
+        let next = location
+            .as_ref()
+            .and_then(|location| location.parse::<crate::item::UrlInfo>().ok())
+            .map(|info| info.url);
+
+        hops.push(RedirectHop {
+            url: item.url.clone(),
+            timestamp: item.timestamp(),
+            status,
+            location,
+        });
+
+        match next {
+            Some(next) if !seen.insert(next.clone()) => {
+                return Ok(RedirectChain {
+                    hops,
+                    end: ChainEnd::Loop,
+                })
+            }
+            Some(next) => current = next,
+            None => {
+                return Ok(RedirectChain {
+                    hops,
+                    end: ChainEnd::Complete,
+                })
+            }
+        }
+    }
+
+    Ok(RedirectChain {
+        hops,
+        end: ChainEnd::MaxHops,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::HistoryOptions;
+    use crate::cdx::Timestamp;
+
+    #[test]
+    fn history_query() {
+        let options =
+            HistoryOptions::default().with_range(Some("2020".parse::<Timestamp>().unwrap()), None);
+        let params = options.query("example.com").params();
+
+        assert!(params.contains(&("filter", "statuscode:200".to_string())));
+        assert!(params.contains(&("from", "2020".to_string())));
+        assert!(!params.iter().any(|(name, _)| *name == "to"));
+    }
+}
@@ -25,23 +25,91 @@ pub enum Error {
     InvalidStatus { value: String },
     #[error("Invalid Wayback Machine URL: {value}")]
     InvalidWaybackUrl { value: String },
+    #[error("Invalid CDXJ line: {value}")]
+    InvalidCdxj { value: String },
 }
 
-#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+lazy_static::lazy_static! {
+    static ref WWW_PREFIX_RE: regex::Regex = regex::Regex::new(r"^www\d*\.").unwrap();
+}
+
+/// The JSON block of a CDXJ line.
+#[derive(serde::Deserialize, serde::Serialize)]
+struct CdxjBlock {
+    url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mime: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    status: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    digest: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    length: Option<serde_json::Value>,
+}
+
+/// A Wayback Machine URL modifier, which selects how a capture is served.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Ord, PartialOrd)]
+pub enum Modifier {
+    /// The original content, without rewriting (`id_`).
+    Original,
+    /// Content rewritten for display in a frame, without the toolbar (`if_`).
+    Framed,
+    /// An image (`im_`).
+    Image,
+    /// JavaScript (`js_`).
+    Script,
+    /// CSS (`cs_`).
+    Stylesheet,
+}
+
+impl Modifier {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Modifier::Original => "id_",
+            Modifier::Framed => "if_",
+            Modifier::Image => "im_",
+            Modifier::Script => "js_",
+            Modifier::Stylesheet => "cs_",
+        }
+    }
+}
+
+impl FromStr for Modifier {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "id_" => Ok(Modifier::Original),
+            "if_" => Ok(Modifier::Framed),
+            "im_" => Ok(Modifier::Image),
+            "js_" => Ok(Modifier::Script),
+            "cs_" => Ok(Modifier::Stylesheet),
+            other => Err(format!("Unknown modifier: {}", other)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Ord, PartialOrd)]
 pub struct UrlInfo {
     pub url: String,
     pub timestamp: String,
+    /// The modifier in the Wayback Machine URL, if there was one.
+    pub modifier: Option<Modifier>,
 }
 
 impl UrlInfo {
     pub fn new(url: String, timestamp: String) -> UrlInfo {
-        UrlInfo { url, timestamp }
+        UrlInfo {
+            url,
+            timestamp,
+            modifier: None,
+        }
     }
 }
 
 lazy_static::lazy_static! {
     static ref WAYBACK_URL_RE: regex::Regex = regex::Regex::new(
-        r"^http(:?s)?://web.archive.org/web/(?P<timestamp>\d{14})(?:id_)?/(?P<url>.+)$",
+        r"^http(:?s)?://web.archive.org/web/(?P<timestamp>\d{14})(?P<modifier>(?:id|if|im|js|cs)_)?/(?P<url>.+)$",
     )
     .unwrap();
 }
@@ -54,10 +122,13 @@ impl FromStr for UrlInfo {
             value: s.to_string(),
         })?;
 
-        Ok(UrlInfo::new(
-            captures["url"].to_string(),
-            captures["timestamp"].to_string(),
-        ))
+        Ok(UrlInfo {
+            url: captures["url"].to_string(),
+            timestamp: captures["timestamp"].to_string(),
+            modifier: captures
+                .name("modifier")
+                .and_then(|modifier| modifier.as_str().parse().ok()),
+        })
     }
 }
 
@@ -100,6 +171,111 @@ impl Item {
         )
     }
 
+    fn parsed_url(&self) -> Option<url::Url> {
+        // CDX results occasionally include URLs without a scheme.
+        if self.url.contains("://") {
+            url::Url::parse(&self.url).ok()
+        } else {
+            url::Url::parse(&format!("http://{}", self.url)).ok()
+        }
+    }
+
+    /// The host of the URL, lower-cased and without any trailing dot.
+    pub fn host(&self) -> Option<String> {
+        self.parsed_url().and_then(|url| {
+            url.host_str()
+                .map(|host| host.trim_end_matches('.').to_lowercase())
+        })
+    }
+
+    /// The registrable domain of the URL's host (e.g. `example.co.uk` for
+    /// `www.example.co.uk`), according to the Public Suffix List.
+    #[cfg(feature = "psl")]
+    pub fn registrable_domain(&self) -> Option<String> {
+        self.host()
+            .and_then(|host| psl::domain_str(&host).map(str::to_string))
+    }
+
+    /// The path of the URL (without the query or fragment).
+    pub fn path(&self) -> Option<String> {
+        self.parsed_url().map(|url| url.path().to_string())
+    }
+
+    /// The SURT form of the URL used as the key in CDX indexes (e.g.
+    /// `com,example)/path?a=1&b=2`).
+    pub fn surt(&self) -> Option<String> {
+        let url = self.parsed_url()?;
+        let host = url.host_str()?.trim_end_matches('.').to_lowercase();
+        let host = WWW_PREFIX_RE.replace(&host, "");
+
+        let mut key = host.split('.').rev().collect::<Vec<_>>().join(",");
+
+        if let Some(port) = url.port() {
+            key.push_str(&format!(":{}", port));
+        }
+
+        key.push(')');
+        key.push_str(&url.path().to_lowercase());
+
+        if let Some(query) = url.query().filter(|query| !query.is_empty()) {
+            let mut params = query.split('&').collect::<Vec<_>>();
+            params.sort_unstable();
+            key.push('?');
+            key.push_str(&params.join("&").to_lowercase());
+        }
+
+        Some(key)
+    }
+
+    /// Encode the item as a CDXJ line (SURT key, timestamp, and JSON block).
+    pub fn to_cdxj(&self) -> String {
+        let block = CdxjBlock {
+            url: self.url.clone(),
+            mime: Some(self.mime_type.clone()),
+            status: self.status.map(|status| status.to_string()),
+            digest: Some(self.digest.clone()),
+            length: Some(serde_json::Value::String(self.length.to_string())),
+        };
+
+        format!(
+            "{} {} {}",
+            self.surt().unwrap_or_else(|| self.url.clone()),
+            self.timestamp(),
+            serde_json::to_string(&block).unwrap_or_default()
+        )
+    }
+
+    /// Decode a CDXJ line.
+    ///
+    /// Digests may have a `sha1:` prefix, and lengths may be strings or numbers.
+    pub fn from_cdxj(line: &str) -> Result<Item, Error> {
+        let invalid = || Error::InvalidCdxj {
+            value: line.to_string(),
+        };
+
+        let mut parts = line.trim().splitn(3, ' ');
+        let _key = parts.next().ok_or_else(invalid)?;
+        let timestamp = parts.next().ok_or_else(invalid)?;
+        let block = serde_json::from_str::<CdxjBlock>(parts.next().ok_or_else(invalid)?)
+            .map_err(|_| invalid())?;
+
+        let digest = block.digest.ok_or(Error::MissingDigest)?;
+        let length = match block.length.ok_or(Error::MissingLength)? {
+            serde_json::Value::String(value) => value,
+            serde_json::Value::Number(value) => value.to_string(),
+            _ => return Err(invalid()),
+        };
+
+        Self::parse(
+            &block.url,
+            timestamp,
+            digest.strip_prefix("sha1:").unwrap_or(&digest),
+            &block.mime.ok_or(Error::MissingMimeType)?,
+            &length,
+            block.status.as_deref().unwrap_or("-"),
+        )
+    }
+
     pub fn timestamp(&self) -> String {
         to_timestamp(&self.archived_at)
     }
@@ -192,3 +368,92 @@ impl Item {
         ]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Item, Modifier, UrlInfo};
+    use chrono::NaiveDate;
+
+    fn item(url: &str) -> Item {
+        Item::new(
+            url.to_string(),
+            NaiveDate::from_ymd_opt(2020, 1, 1)
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+                .unwrap(),
+            "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA".to_string(),
+            "text/html".to_string(),
+            0,
+            Some(200),
+        )
+    }
+
+    #[test]
+    fn host_and_path() {
+        let value = item("https://WWW.Example.co.uk./a/b?c=d");
+
+        assert_eq!(value.host(), Some("www.example.co.uk".to_string()));
+        assert_eq!(value.path(), Some("/a/b".to_string()));
+        assert_eq!(
+            item("twitter.com:80/foo").host(),
+            Some("twitter.com".to_string())
+        );
+    }
+
+    #[test]
+    fn url_info_modifiers() {
+        let info = "https://web.archive.org/web/20200101000000im_/https://example.com/a.png"
+            .parse::<UrlInfo>()
+            .unwrap();
+
+        assert_eq!(info.url, "https://example.com/a.png");
+        assert_eq!(info.timestamp, "20200101000000");
+        assert_eq!(info.modifier, Some(Modifier::Image));
+        assert_eq!(
+            "http://web.archive.org/web/20200101000000/https://example.com/"
+                .parse::<UrlInfo>()
+                .unwrap()
+                .modifier,
+            None
+        );
+        assert!(
+            "https://web.archive.org/web/20200101000000xx_/https://example.com/"
+                .parse::<UrlInfo>()
+                .is_err()
+        );
+
+        for modifier in ["id_", "if_", "im_", "js_", "cs_"] {
+            assert_eq!(modifier.parse::<Modifier>().unwrap().as_str(), modifier);
+        }
+    }
+
+    #[test]
+    fn cdxj_round_trip() {
+        let value = item("https://www.Example.com/A/b?z=1&a=2");
+        let line = value.to_cdxj();
+
+        assert_eq!(
+            line,
+            r#"com,example)/a/b?a=2&z=1 20200101000000 {"url":"https://www.Example.com/A/b?z=1&a=2","mime":"text/html","status":"200","digest":"AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA","length":"0"}"#
+        );
+        assert_eq!(Item::from_cdxj(&line).unwrap(), value);
+
+        let parsed = Item::from_cdxj(
+            r#"com,example)/ 20200101000000 {"url":"https://www.Example.com/A/b?z=1&a=2","mime":"text/html","status":"200","digest":"sha1:AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA","length":0}"#,
+        )
+        .unwrap();
+
+        assert_eq!(parsed, value);
+        assert!(Item::from_cdxj("com,example)/ 20200101000000").is_err());
+    }
+
+    #[cfg(feature = "psl")]
+    #[test]
+    fn registrable_domain() {
+        let value = item("https://www.example.co.uk/");
+
+        assert_eq!(
+            value.registrable_domain(),
+            Some("example.co.uk".to_string())
+        );
+    }
+}
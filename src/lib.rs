@@ -1,11 +1,23 @@
+pub mod analysis;
+pub mod availability;
 pub mod browser;
 pub mod cdx;
 pub mod digest;
 pub mod downloader;
+pub mod export;
+pub mod fixtures;
+pub mod health;
+pub mod history;
 pub mod item;
+pub mod metadata;
+pub mod sample;
+pub mod save;
 pub mod session;
 pub mod store;
 pub mod util;
+pub mod warc;
 
 pub use downloader::Downloader;
+pub use history::{fetch_history, HistoryOptions};
 pub use item::Item;
+pub use save::archive_now;
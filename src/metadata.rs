@@ -0,0 +1,144 @@
+//! A client for the Wayback Machine's capture metadata endpoints.
+//!
+//! These endpoints provide capture counts for a URL without listing the
+//! captures, which is much cheaper than a CDX search.
+
+use super::util::{retry_future, ErrorClass, Retryable};
+use reqwest::Client;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use tryhard::RetryPolicy;
+
+const DEFAULT_METADATA_BASE: &str = "https://web.archive.org/__wb";
+const DEFAULT_COLLECTION: &str = "web";
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("HTTP client error: {0}")]
+    HttpClientError(#[from] reqwest::Error),
+    #[error("JSON decoding error: {0}")]
+    JsonError(#[from] serde_json::Error),
+}
+
+impl Retryable for Error {
+    fn max_retries() -> u32 {
+        5
+    }
+
+    fn log_level() -> Option<log::Level> {
+        Some(log::Level::Warn)
+    }
+
+    fn default_initial_delay() -> Duration {
+        Duration::from_millis(250)
+    }
+
+    fn custom_retry_policy(&self) -> Option<RetryPolicy> {
+        match self {
+            Error::HttpClientError(error) => match ErrorClass::of(error) {
+                ErrorClass::Certificate => Some(RetryPolicy::Break),
+                _ => None,
+            },
+            Error::JsonError(_) => Some(RetryPolicy::Break),
+        }
+    }
+}
+
+/// Monthly capture counts for a URL.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub struct Sparkline {
+    /// Twelve monthly capture counts for each year.
+    pub years: BTreeMap<String, Vec<u64>>,
+    pub first_ts: Option<String>,
+    pub last_ts: Option<String>,
+    /// A status character for each month of each year.
+    #[serde(default)]
+    pub status: BTreeMap<String, String>,
+}
+
+impl Sparkline {
+    /// Total capture counts by year.
+    pub fn yearly(&self) -> BTreeMap<String, u64> {
+        self.years
+            .iter()
+            .map(|(year, months)| (year.clone(), months.iter().sum()))
+            .collect()
+    }
+
+    pub fn total(&self) -> u64 {
+        self.years.values().flatten().sum()
+    }
+}
+
+pub struct MetadataClient {
+    base: String,
+    underlying: Client,
+}
+
+impl MetadataClient {
+    pub fn new(base: String) -> Result<Self, Error> {
+        Ok(Self {
+            base,
+            underlying: Client::builder()
+                .user_agent(super::util::DEFAULT_USER_AGENT)
+                .build()?,
+        })
+    }
+
+    /// Get the capture counts for a URL in the main web collection.
+    pub async fn sparkline(&self, url: &str) -> Result<Sparkline, Error> {
+        self.sparkline_in_collection(url, DEFAULT_COLLECTION).await
+    }
+
+    /// Get the capture counts for a URL in the given collection.
+    pub async fn sparkline_in_collection(
+        &self,
+        url: &str,
+        collection: &str,
+    ) -> Result<Sparkline, Error> {
+        let query_url = format!(
+            "{}/sparkline?output=json&url={}&collection={}",
+            self.base,
+            url::form_urlencoded::byte_serialize(url.as_bytes()).collect::<String>(),
+            url::form_urlencoded::byte_serialize(collection.as_bytes()).collect::<String>()
+        );
+
+        retry_future(|| async {
+            let contents = self
+                .underlying
+                .get(&query_url)
+                .send()
+                .await?
+                .error_for_status()?
+                .text()
+                .await?;
+
+            Ok(serde_json::from_str(&contents)?)
+        })
+        .await
+    }
+}
+
+impl Default for MetadataClient {
+    fn default() -> Self {
+        Self::new(DEFAULT_METADATA_BASE.to_string()).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Sparkline;
+
+    #[test]
+    fn sparkline_yearly() {
+        let sparkline: Sparkline = serde_json::from_str(
+            r#"{"years":{"2019":[0,1,2,3,0,0,0,0,0,0,0,4],"2020":[5,0,0,0,0,0,0,0,0,0,0,0]},"first_ts":"20190201000000","last_ts":"20200105000000","status":{"2019":"422222222222"}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(sparkline.yearly()["2019"], 10);
+        assert_eq!(sparkline.yearly()["2020"], 5);
+        assert_eq!(sparkline.total(), 15);
+    }
+}
@@ -0,0 +1,171 @@
+//! Reservoir sampling over collections of items.
+//!
+//! Sampling is deterministic for a given seed, so that sampled subsets can be
+//! reproduced.
+
+use super::Item;
+use chrono::Datelike;
+use futures::{Stream, TryStreamExt};
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// A property used to group items for stratified sampling.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Stratum {
+    Host,
+    Year,
+    MimeType,
+}
+
+impl Stratum {
+    pub fn key(&self, item: &Item) -> String {
+        match self {
+            Stratum::Host => item.host().unwrap_or_default(),
+            Stratum::Year => item.archived_at.year().to_string(),
+            Stratum::MimeType => item.mime_type.clone(),
+        }
+    }
+}
+
+impl FromStr for Stratum {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "host" => Ok(Stratum::Host),
+            "year" => Ok(Stratum::Year),
+            "mime" => Ok(Stratum::MimeType),
+            other => Err(format!("Unknown stratum: {}", other)),
+        }
+    }
+}
+
+/// A fixed-size uniform sample of the values it has been given.
+pub struct Reservoir<T> {
+    capacity: usize,
+    seen: u64,
+    values: Vec<T>,
+    state: u64,
+}
+
+impl<T> Reservoir<T> {
+    pub fn new(capacity: usize, seed: u64) -> Self {
+        Self {
+            capacity,
+            seen: 0,
+            values: Vec::with_capacity(capacity),
+            state: seed,
+        }
+    }
+
+    // SplitMix64.
+    fn next_random(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn add(&mut self, value: T) {
+        self.seen += 1;
+
+        if self.values.len() < self.capacity {
+            self.values.push(value);
+        } else {
+            let index = self.next_random() % self.seen;
+
+            if index < self.capacity as u64 {
+                self.values[index as usize] = value;
+            }
+        }
+    }
+
+    /// The number of values that have been added.
+    pub fn seen(&self) -> u64 {
+        self.seen
+    }
+
+    pub fn into_values(self) -> Vec<T> {
+        self.values
+    }
+}
+
+/// Select a uniform sample of the given size.
+pub fn sample<I: IntoIterator<Item = Item>>(items: I, size: usize, seed: u64) -> Vec<Item> {
+    let mut reservoir = Reservoir::new(size, seed);
+    items.into_iter().for_each(|item| reservoir.add(item));
+    reservoir.into_values()
+}
+
+/// Select a uniform sample of the given size from each stratum.
+pub fn sample_stratified<I: IntoIterator<Item = Item>>(
+    items: I,
+    stratum: Stratum,
+    size: usize,
+    seed: u64,
+) -> BTreeMap<String, Vec<Item>> {
+    let mut reservoirs = BTreeMap::new();
+
+    for item in items {
+        let key = stratum.key(&item);
+        let count = reservoirs.len() as u64;
+        reservoirs
+            .entry(key)
+            .or_insert_with(|| Reservoir::new(size, seed.wrapping_add(count)))
+            .add(item);
+    }
+
+    reservoirs
+        .into_iter()
+        .map(|(key, reservoir)| (key, reservoir.into_values()))
+        .collect()
+}
+
+/// Select a uniform sample of the given size from a stream of items.
+pub async fn sample_stream<S, E>(items: S, size: usize, seed: u64) -> Result<Vec<Item>, E>
+where
+    S: Stream<Item = Result<Item, E>>,
+{
+    let reservoir = items
+        .try_fold(
+            Reservoir::new(size, seed),
+            |mut reservoir, item| async move {
+                reservoir.add(item);
+                Ok(reservoir)
+            },
+        )
+        .await?;
+
+    Ok(reservoir.into_values())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sample, sample_stratified, Stratum};
+
+    fn items() -> Vec<crate::Item> {
+        crate::fixtures::items(100)
+            .into_iter()
+            .map(|(item, _)| item)
+            .collect()
+    }
+
+    #[test]
+    fn sample_size_and_determinism() {
+        let first = sample(items(), 10, 1);
+        let second = sample(items(), 10, 1);
+
+        assert_eq!(first.len(), 10);
+        assert_eq!(first, second);
+        assert_eq!(sample(items(), 1000, 1).len(), 100);
+    }
+
+    #[test]
+    fn stratified() {
+        let result = sample_stratified(items(), Stratum::Year, 3, 1);
+
+        assert_eq!(result.len(), 1);
+        assert_eq!(result["2020"].len(), 3);
+    }
+}
@@ -0,0 +1,248 @@
+//! A client for the Wayback Machine's Save Page Now (SPN2) API.
+//!
+//! Saving is asynchronous: a request starts a capture job, and the job's
+//! status is polled until it finishes. The new capture is then looked up in
+//! the CDX index, which may take a little longer to include it.
+
+use super::{
+    cdx::{self, CdxQuery, Filter, IndexClient},
+    Item,
+};
+use reqwest::{header::ACCEPT, Client};
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+const DEFAULT_SAVE_URL: &str = "https://web.archive.org/save";
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+const DEFAULT_MAX_WAIT: Duration = Duration::from_secs(300);
+const CDX_ATTEMPTS: u32 = 6;
+const CDX_DELAY: Duration = Duration::from_secs(10);
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("HTTP client error: {0}")]
+    HttpClientError(#[from] reqwest::Error),
+    #[error("JSON decoding error: {0}")]
+    JsonError(#[from] serde_json::Error),
+    #[error("CDX error: {0}")]
+    IndexClient(#[from] cdx::Error),
+    #[error("Save failed ({status_ext:?}): {message:?}")]
+    Failed {
+        status_ext: Option<String>,
+        message: Option<String>,
+    },
+    #[error("Timed out waiting for save job: {0}")]
+    Timeout(String),
+    #[error("Capture not found in CDX index: {url} at {timestamp}")]
+    NotIndexed { url: String, timestamp: String },
+}
+
+/// Archive.org S3-style API keys.
+#[derive(Clone, Eq, PartialEq)]
+pub struct Credentials {
+    access_key: String,
+    secret_key: String,
+}
+
+impl Credentials {
+    pub fn new(access_key: &str, secret_key: &str) -> Self {
+        Self {
+            access_key: access_key.to_string(),
+            secret_key: secret_key.to_string(),
+        }
+    }
+
+    fn authorization(&self) -> String {
+        format!("LOW {}:{}", self.access_key, self.secret_key)
+    }
+}
+
+impl std::fmt::Debug for Credentials {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Credentials")
+            .field("access_key", &self.access_key)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Deserialize)]
+struct SaveResponse {
+    job_id: Option<String>,
+    status_ext: Option<String>,
+    message: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+struct JobStatus {
+    status: String,
+    timestamp: Option<String>,
+    original_url: Option<String>,
+    status_ext: Option<String>,
+    message: Option<String>,
+}
+
+pub struct SaveClient {
+    base: String,
+    credentials: Credentials,
+    poll_interval: Duration,
+    max_wait: Duration,
+    index_client: IndexClient,
+    underlying: Client,
+}
+
+impl SaveClient {
+    pub fn new(base: String, credentials: Credentials) -> Result<Self, Error> {
+        Ok(Self {
+            base,
+            credentials,
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            max_wait: DEFAULT_MAX_WAIT,
+            index_client: IndexClient::default(),
+            underlying: Client::builder()
+                .user_agent(super::util::DEFAULT_USER_AGENT)
+                .build()?,
+        })
+    }
+
+    /// Check the job status this often.
+    pub fn with_poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Give up on a job after this long.
+    pub fn with_max_wait(mut self, max_wait: Duration) -> Self {
+        self.max_wait = max_wait;
+        self
+    }
+
+    /// Verify captures with the given CDX client.
+    pub fn with_index_client(mut self, index_client: IndexClient) -> Self {
+        self.index_client = index_client;
+        self
+    }
+
+    /// Save a URL, returning the CDX item for the new capture.
+    pub async fn save(&self, url: &str) -> Result<Item, Error> {
+        let job_id = self.submit(url).await?;
+        log::info!("Save job for {}: {}", url, job_id);
+
+        let started = Instant::now();
+
+        loop {
+            async_std::task::sleep(self.poll_interval).await;
+
+            let status = self.status(&job_id).await?;
+
+            match status.status.as_str() {
+                "success" => {
+                    let original_url = status.original_url.unwrap_or_else(|| url.to_string());
+                    let timestamp = status.timestamp.ok_or(Error::Failed {
+                        status_ext: None,
+                        message: Some("Missing capture timestamp".to_string()),
+                    })?;
+
+                    return self.lookup(&original_url, &timestamp).await;
+                }
+                "pending" if started.elapsed() < self.max_wait => {}
+                "pending" => return Err(Error::Timeout(job_id)),
+                _ => {
+                    return Err(Error::Failed {
+                        status_ext: status.status_ext,
+                        message: status.message,
+                    })
+                }
+            }
+        }
+    }
+
+    async fn submit(&self, url: &str) -> Result<String, Error> {
+        let contents = self
+            .underlying
+            .post(&self.base)
+            .header(ACCEPT, "application/json")
+            .header("Authorization", self.credentials.authorization())
+            .form(&[("url", url)])
+            .send()
+            .await?
+            .text()
+            .await?;
+
+        let response = serde_json::from_str::<SaveResponse>(&contents)?;
+
+        response.job_id.ok_or(Error::Failed {
+            status_ext: response.status_ext,
+            message: response.message,
+        })
+    }
+
+    async fn status(&self, job_id: &str) -> Result<JobStatus, Error> {
+        let contents = self
+            .underlying
+            .get(format!("{}/status/{}", self.base, job_id))
+            .header(ACCEPT, "application/json")
+            .header("Authorization", self.credentials.authorization())
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    /// Find the new capture in the CDX index, waiting for it to be indexed.
+    async fn lookup(&self, url: &str, timestamp: &str) -> Result<Item, Error> {
+        let query = CdxQuery::new(url).with_filter(Filter::timestamp(timestamp));
+
+        for attempt in 0..CDX_ATTEMPTS {
+            if attempt > 0 {
+                async_std::task::sleep(CDX_DELAY).await;
+            }
+
+            if let Some(item) = self.index_client.search(&query).await?.pop() {
+                return Ok(item);
+            }
+        }
+
+        Err(Error::NotIndexed {
+            url: url.to_string(),
+            timestamp: timestamp.to_string(),
+        })
+    }
+}
+
+/// Save a URL with the default client, returning the CDX item for the new
+/// capture.
+pub async fn archive_now(url: &str, credentials: Credentials) -> Result<Item, Error> {
+    SaveClient::new(DEFAULT_SAVE_URL.to_string(), credentials)?
+        .save(url)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Credentials, JobStatus};
+
+    #[test]
+    fn decode_status() {
+        let status: JobStatus = serde_json::from_str(
+            r#"{"status":"success","job_id":"spn2-abc","original_url":"https://example.com/","timestamp":"20200101000000","duration_sec":6.2,"resources":[]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(status.status, "success");
+        assert_eq!(status.timestamp.as_deref(), Some("20200101000000"));
+
+        let status: JobStatus = serde_json::from_str(
+            r#"{"status":"error","status_ext":"error:too-many-daily-captures","message":"This URL has been already captured 10 times today."}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            status.status_ext.as_deref(),
+            Some("error:too-many-daily-captures")
+        );
+        assert!(!format!("{:?}", Credentials::new("access", "secret")).contains("secret"));
+    }
+}
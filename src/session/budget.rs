@@ -0,0 +1,137 @@
+use crate::util::CancellationToken;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Limits on the amount of work done in a single run.
+///
+/// Downloads that are in flight when a limit is reached are allowed to finish,
+/// so limits may be exceeded slightly.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Budget {
+    pub max_items: Option<usize>,
+    pub max_bytes: Option<u64>,
+    pub max_duration: Option<Duration>,
+}
+
+impl Budget {
+    pub fn is_unlimited(&self) -> bool {
+        self.max_items.is_none() && self.max_bytes.is_none() && self.max_duration.is_none()
+    }
+}
+
+/// Tracks usage against a budget during a run.
+pub(crate) struct BudgetTracker {
+    budget: Budget,
+    start: Instant,
+    items: AtomicUsize,
+    bytes: AtomicU64,
+    cancellation: Option<CancellationToken>,
+}
+
+impl BudgetTracker {
+    pub(crate) fn new(budget: Budget) -> Self {
+        Self {
+            budget,
+            start: Instant::now(),
+            items: AtomicUsize::new(0),
+            bytes: AtomicU64::new(0),
+            cancellation: None,
+        }
+    }
+
+    pub(crate) fn with_cancellation(mut self, token: Option<CancellationToken>) -> Self {
+        self.cancellation = token;
+        self
+    }
+
+    pub(crate) fn record(&self, bytes: u64) {
+        self.items.fetch_add(1, Ordering::Relaxed);
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn log_if_exhausted(&self) {
+        if self.is_exhausted() {
+            log::warn!(
+                "Budget exhausted after {} items and {} bytes; run again to resume",
+                self.items.load(Ordering::Relaxed),
+                self.bytes()
+            );
+        } else if self.is_cancelled() {
+            log::warn!(
+                "Cancelled after {} items and {} bytes; run again to resume",
+                self.items.load(Ordering::Relaxed),
+                self.bytes()
+            );
+        }
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancellation
+            .as_ref()
+            .is_some_and(|token| token.is_cancelled())
+    }
+
+    /// Whether new work should be started.
+    pub(crate) fn should_stop(&self) -> bool {
+        self.is_exhausted() || self.is_cancelled()
+    }
+
+    pub(crate) fn is_exhausted(&self) -> bool {
+        self.budget
+            .max_items
+            .is_some_and(|max| self.items.load(Ordering::Relaxed) >= max)
+            || self
+                .budget
+                .max_bytes
+                .is_some_and(|max| self.bytes.load(Ordering::Relaxed) >= max)
+            || self
+                .budget
+                .max_duration
+                .is_some_and(|max| self.start.elapsed() >= max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Budget, BudgetTracker};
+    use crate::util::CancellationToken;
+
+    #[test]
+    fn limits() {
+        let tracker = BudgetTracker::new(Budget {
+            max_items: Some(2),
+            max_bytes: Some(100),
+            ..Default::default()
+        });
+
+        tracker.record(10);
+        assert!(!tracker.is_exhausted());
+        tracker.record(10);
+        assert!(tracker.is_exhausted());
+
+        let tracker = BudgetTracker::new(Budget {
+            max_bytes: Some(100),
+            ..Default::default()
+        });
+
+        tracker.record(100);
+        assert!(tracker.is_exhausted());
+        assert!(!BudgetTracker::new(Budget::default()).is_exhausted());
+    }
+
+    #[test]
+    fn cancellation() {
+        let token = CancellationToken::new();
+        let tracker = BudgetTracker::new(Budget::default()).with_cancellation(Some(token.clone()));
+
+        assert!(!tracker.should_stop());
+        token.cancel();
+        assert!(tracker.should_stop());
+        assert!(tracker.is_cancelled());
+        assert!(!tracker.is_exhausted());
+    }
+}
@@ -0,0 +1,311 @@
+//! A streaming pipeline that connects CDX search, redirect resolution, and
+//! downloading.
+//!
+//! The stages are connected by bounded channels, so that slow downloads slow
+//! down CDX paging instead of accumulating items in memory.
+
+use super::output::{create_csv, finish_csv, ItemWriter};
+use super::{budget::BudgetTracker, Error, RetryTotals, Session, Stage, StageSummary};
+use crate::{cdx, Item};
+use futures::channel::mpsc::{channel, Receiver, Sender};
+use futures::{SinkExt, StreamExt};
+use std::collections::HashSet;
+use std::fs::{create_dir_all, File};
+use std::io::{BufRead, BufReader, Write};
+use std::time::Duration;
+
+/// Capacities and concurrency levels for the stages of a harvest.
+#[derive(Clone, Copy, Debug)]
+pub struct HarvestConfig {
+    /// The number of rows requested per CDX page.
+    pub page_size: usize,
+    /// The capacity of the channels between stages.
+    pub channel_capacity: usize,
+    pub resolve_parallelism: usize,
+    pub download_parallelism: usize,
+}
+
+impl Default for HarvestConfig {
+    fn default() -> Self {
+        Self {
+            page_size: 10000,
+            channel_capacity: 1000,
+            resolve_parallelism: 4,
+            download_parallelism: 6,
+        }
+    }
+}
+
+/// Counts for a completed harvest.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct HarvestSummary {
+    pub success: usize,
+    pub invalid: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub blocked_queries: Vec<String>,
+    /// Whether the run stopped early because its budget was used up.
+    pub budget_exhausted: bool,
+    /// Whether the run stopped early because it was cancelled.
+    pub cancelled: bool,
+    /// The number of download attempts that were retries.
+    pub retries: u32,
+    /// The total time spent waiting to retry downloads.
+    pub retry_delay: Duration,
+}
+
+impl Session {
+    /// Search for the given queries, resolve redirects, and download items in a
+    /// single pipeline.
+    pub async fn harvest(
+        &self,
+        queries: &[String],
+        config: HarvestConfig,
+    ) -> Result<HarvestSummary, Error> {
+        self.notify_started(queries.len()).await;
+        let result = self.run_harvest(queries, config).await;
+
+        if result
+            .as_ref()
+            .is_ok_and(|summary| summary.budget_exhausted)
+        {
+            self.notify_budget_exhausted().await;
+        }
+
+        self.notify_stage(Stage::Harvest, result, |summary| StageSummary {
+            success: summary.success,
+            invalid: summary.invalid,
+            skipped: summary.skipped,
+            failed: summary.failed,
+            blocked: summary.blocked_queries.len(),
+        })
+        .await
+    }
+
+    async fn run_harvest(
+        &self,
+        queries: &[String],
+        config: HarvestConfig,
+    ) -> Result<HarvestSummary, Error> {
+        create_dir_all(&self.base)?;
+        for dir in self.data_areas() {
+            create_dir_all(dir)?;
+        }
+        create_dir_all(self.base.join("invalid"))?;
+        create_dir_all(self.base.join("errors"))?;
+
+        let mut query_log = File::create(self.base.join("queries.txt"))?;
+        query_log.write_all(format!("{}\n", queries.join("\n")).as_bytes())?;
+
+        let (redirect_tx, redirect_rx) = channel(config.channel_capacity);
+        let (download_tx, download_rx) = channel(config.channel_capacity);
+        let tracker = self.budget_tracker();
+
+        let (blocked, _, mut summary) = futures::try_join!(
+            self.search_stage(queries, &config, &tracker, redirect_tx, download_tx.clone()),
+            self.resolve_stage(redirect_rx, &config, &tracker, download_tx),
+            self.download_stage(download_rx, &config, &tracker),
+        )?;
+
+        tracker.log_if_exhausted();
+        summary.blocked_queries = blocked;
+        summary.budget_exhausted = tracker.is_exhausted();
+        summary.cancelled = tracker.is_cancelled();
+
+        Ok(summary)
+    }
+
+    async fn search_stage(
+        &self,
+        queries: &[String],
+        config: &HarvestConfig,
+        tracker: &BudgetTracker,
+        mut redirect_tx: Sender<Item>,
+        mut download_tx: Sender<Item>,
+    ) -> Result<Vec<String>, Error> {
+        let (blocked, queries) = self.partition_blocked(queries);
+        let mut newly_blocked = vec![];
+
+        let mut originals_writer =
+            ItemWriter::create(&self.base, "originals", self.rotation, self.compression)?;
+        let mut redirects_writer =
+            ItemWriter::create(&self.base, "redirects", self.rotation, self.compression)?;
+        let mut poisoned_writer = self.poisoned_writer()?;
+
+        for query in queries {
+            if tracker.should_stop() {
+                break;
+            }
+
+            let query = self.cdx_query(query).with_limit(config.page_size);
+            let mut items = Box::pin(self.index_client.stream_search(&query));
+
+            while let Some(result) = items.next().await {
+                if tracker.should_stop() {
+                    break;
+                }
+
+                match result {
+                    Ok(item) if self.set_aside_poisoned(&item, &mut poisoned_writer)? => {}
+                    Ok(item) => {
+                        let (writer, tx) = if item.status == Some(302) {
+                            (&mut redirects_writer, &mut redirect_tx)
+                        } else {
+                            (&mut originals_writer, &mut download_tx)
+                        };
+
+                        writer.write(&item)?;
+
+                        // The receiving stage only fails if the pipeline is shutting down.
+                        if tx.send(item).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(cdx::Error::BlockedQuery(query)) => {
+                        newly_blocked.push(query);
+                        break;
+                    }
+                    Err(cdx::Error::Cancelled) => break,
+                    Err(error) => return Err(error.into()),
+                }
+            }
+        }
+
+        originals_writer.finish()?;
+        redirects_writer.finish()?;
+        if let Some(writer) = poisoned_writer {
+            writer.finish()?;
+        }
+
+        self.record_blocked(blocked, newly_blocked)
+    }
+
+    async fn resolve_stage(
+        &self,
+        redirect_rx: Receiver<Item>,
+        config: &HarvestConfig,
+        tracker: &BudgetTracker,
+        mut download_tx: Sender<Item>,
+    ) -> Result<(), Error> {
+        let known = self.known_digest_set()?;
+        let mut seen = HashSet::new();
+
+        let mut extras_writer =
+            ItemWriter::create(&self.base, "extras", self.rotation, self.compression)?;
+        let mut redirects_error_csv =
+            create_csv(self.base.join("errors"), "redirects", self.compression)?;
+
+        let mut results = redirect_rx
+            .take_while(|_| futures::future::ready(!tracker.should_stop()))
+            .filter(|item| {
+                futures::future::ready(
+                    !known.contains(&item.digest) && seen.insert(item.digest.clone()),
+                )
+            })
+            .map(|item| async move { self.resolve_item(&item).await.map_err(|_| item.clone()) })
+            .buffer_unordered(config.resolve_parallelism);
+
+        while let Some(result) = results.next().await {
+            match result {
+                Ok(item) => {
+                    extras_writer.write(&item)?;
+                    let _ = download_tx.send(item).await;
+                }
+                Err(item) => {
+                    redirects_error_csv.write_record(item.to_record())?;
+                }
+            }
+        }
+
+        extras_writer.finish()?;
+        finish_csv(redirects_error_csv)?;
+
+        Ok(())
+    }
+
+    async fn download_stage(
+        &self,
+        download_rx: Receiver<Item>,
+        config: &HarvestConfig,
+        tracker: &BudgetTracker,
+    ) -> Result<HarvestSummary, Error> {
+        let known = self.known_digest_set()?;
+        let mut seen = HashSet::new();
+        let mut summary = HarvestSummary::default();
+
+        let mut error_csv = create_csv(self.base.join("errors"), "items", self.compression)?;
+        let mut invalid_csv = create_csv(self.base.join("errors"), "invalid", self.compression)?;
+
+        let items = download_rx
+            .take_while(|_| futures::future::ready(!tracker.should_stop()))
+            .filter(|item| {
+                let new = seen.insert(item.digest.clone())
+                    && !known.contains(&item.digest)
+                    && !self.poison.contains(&item.digest)
+                    && !self.skips_content(item)
+                    && self.lookup_data(&item.digest).is_none();
+
+                if !new {
+                    summary.skipped += 1;
+                }
+
+                futures::future::ready(new)
+            });
+        let mut results = self
+            .client
+            .download_items(items, config.download_parallelism)
+            .map(|(item, result, stats)| self.save_result(item, result, stats, tracker))
+            .buffer_unordered(config.download_parallelism);
+
+        let mut success = 0;
+        let mut invalid = 0;
+        let mut failed = 0;
+        let mut retries = RetryTotals::default();
+
+        while let Some((result, stats)) = results.next().await {
+            retries.add(&stats);
+
+            match result {
+                Ok(None) => {
+                    success += 1;
+                }
+                Ok(Some((expected, computed))) => {
+                    invalid += 1;
+                    invalid_csv.write_record(vec![expected, computed])?;
+                }
+                Err(item) => {
+                    failed += 1;
+                    error_csv.write_record(item.to_record())?;
+                }
+            }
+        }
+
+        drop(results);
+
+        log::info!("Content bytes received: {}", tracker.bytes());
+        retries.log();
+
+        finish_csv(error_csv)?;
+        finish_csv(invalid_csv)?;
+
+        summary.success = success;
+        summary.invalid = invalid;
+        summary.failed = failed;
+        summary.retries = retries.retries;
+        summary.retry_delay = retries.delay;
+
+        Ok(summary)
+    }
+
+    fn known_digest_set(&self) -> Result<HashSet<String>, Error> {
+        let mut digests = HashSet::new();
+
+        if let Some(path) = &self.known_digests {
+            for line in BufReader::new(File::open(path)?).lines() {
+                digests.insert(line?.trim().to_string());
+            }
+        }
+
+        Ok(digests)
+    }
+}
@@ -0,0 +1,187 @@
+//! Mirroring the most recent capture of each URL in a list.
+
+use super::{Error, Session};
+use crate::{
+    cdx::{self, CdxQuery, Field, Filter},
+    digest::compute_digest,
+    util::retry_future,
+    Item,
+};
+use bytes::Buf;
+use futures::StreamExt;
+use std::fs::create_dir_all;
+use std::path::Path;
+
+/// The maximum number of redirect captures followed for a URL.
+const MAX_MIRROR_REDIRECTS: usize = 3;
+/// File names longer than this are truncated and disambiguated by digest.
+const MAX_FILE_NAME_LEN: usize = 200;
+
+/// The results of mirroring a list of URLs.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MirrorSummary {
+    pub saved: usize,
+    /// URLs with no valid captures.
+    pub missing: Vec<String>,
+    pub failed: Vec<String>,
+}
+
+enum Outcome {
+    Saved {
+        url: String,
+        file_name: String,
+        item: Item,
+    },
+    Missing(String),
+    Failed(String),
+}
+
+impl Session {
+    /// Download only the most recent valid capture of each URL.
+    ///
+    /// Redirect captures are followed to the capture of their target. Content
+    /// is written uncompressed to the session's `mirror` directory, with files
+    /// named by the URL's canonical (SURT) form, and `mirror/index.csv` lists
+    /// the requested URL, file name, and capture for each saved URL.
+    pub async fn mirror_latest<S: AsRef<str>>(&self, urls: &[S]) -> Result<MirrorSummary, Error> {
+        let dir = self.base.join("mirror");
+        create_dir_all(&dir)?;
+
+        let outcomes = futures::stream::iter(urls)
+            .map(|url| self.mirror_url(url.as_ref(), &dir))
+            .buffer_unordered(self.parallelism)
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut index = csv::Writer::from_path(dir.join("index.csv"))?;
+        let mut summary = MirrorSummary::default();
+
+        for outcome in outcomes {
+            match outcome {
+                Outcome::Saved {
+                    url,
+                    file_name,
+                    item,
+                } => {
+                    let mut record = vec![url, file_name];
+                    record.extend(item.to_record());
+                    index.write_record(record)?;
+                    summary.saved += 1;
+                }
+                Outcome::Missing(url) => summary.missing.push(url),
+                Outcome::Failed(url) => summary.failed.push(url),
+            }
+        }
+
+        index.flush()?;
+
+        Ok(summary)
+    }
+
+    async fn mirror_url(&self, url: &str, dir: &Path) -> Outcome {
+        let latest = match self.latest_capture(url).await {
+            Ok(Some(item)) => item,
+            Ok(None) => return Outcome::Missing(url.to_string()),
+            Err(error) => {
+                log::warn!("CDX search failed for {}: {:?}", url, error);
+                return Outcome::Failed(url.to_string());
+            }
+        };
+
+        let file_name = mirror_file_name(&latest);
+        let mut item = latest;
+
+        for _ in 0..MAX_MIRROR_REDIRECTS {
+            if item.status == Some(200) {
+                break;
+            }
+
+            match self.resolve_item(&item).await {
+                Ok(target) => item = target,
+                Err(_) => return Outcome::Failed(url.to_string()),
+            }
+        }
+
+        if item.status != Some(200) {
+            log::warn!("Too many redirects for {}", url);
+            return Outcome::Failed(url.to_string());
+        }
+
+        let content = match self.client.download_item(&item).await {
+            Ok(content) => content,
+            Err(error) => {
+                log::warn!("Download failed for {}: {:?}", item.url, error);
+                return Outcome::Failed(url.to_string());
+            }
+        };
+
+        let computed = compute_digest(&mut content.clone().reader()).unwrap();
+
+        if computed != item.digest {
+            log::warn!("Invalid digest for {}: {}", item.url, computed);
+            return Outcome::Failed(url.to_string());
+        }
+
+        match std::fs::write(dir.join(&file_name), &content) {
+            Ok(()) => Outcome::Saved {
+                url: url.to_string(),
+                file_name,
+                item,
+            },
+            Err(error) => {
+                log::warn!("Failed to write {}: {:?}", file_name, error);
+                Outcome::Failed(url.to_string())
+            }
+        }
+    }
+
+    /// The most recent successful or redirect capture of the exact URL.
+    async fn latest_capture(&self, url: &str) -> Result<Option<Item>, cdx::Error> {
+        let query = CdxQuery::new(url)
+            .with_filter(Filter::status_range(200..=399))
+            .with_collapse(Field::Digest);
+        let items = retry_future(|| self.index_client.search(&query)).await?;
+
+        Ok(items.into_iter().max_by_key(|item| item.archived_at))
+    }
+}
+
+/// A file name for the item's URL that is safe to use in a flat directory.
+fn mirror_file_name(item: &Item) -> String {
+    let key = item.surt().unwrap_or_else(|| item.url.clone());
+    let encoded = url::form_urlencoded::byte_serialize(key.as_bytes()).collect::<String>();
+
+    if encoded.len() <= MAX_FILE_NAME_LEN {
+        encoded
+    } else {
+        let digest = compute_digest(&mut key.as_bytes()).unwrap();
+        let mut end = MAX_FILE_NAME_LEN;
+        while !encoded.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        format!("{}-{}", &encoded[..end], digest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mirror_file_name, MAX_FILE_NAME_LEN};
+
+    #[test]
+    fn file_names() {
+        let mut item = crate::fixtures::items(1).remove(0).0;
+        item.url = "https://www.example.com/a/b?z=1&a=2".to_string();
+
+        assert_eq!(
+            mirror_file_name(&item),
+            "com%2Cexample%29%2Fa%2Fb%3Fa%3D2%26z%3D1"
+        );
+
+        item.url = format!("https://example.com/{}", "a".repeat(300));
+        let name = mirror_file_name(&item);
+
+        assert_eq!(name.len(), MAX_FILE_NAME_LEN + 33);
+        assert!(!name.contains('/'));
+    }
+}
@@ -0,0 +1,962 @@
+use super::{
+    cdx::{self, BlockedRegistry, CdxQuery, Filter, IndexClient, ResponseCache},
+    downloader::{self, Downloader, OriginalHeaders, ResolveOptions, VerifiedDownload},
+    store::headers::HeaderStore,
+    util::{CancellationToken, RetryStats},
+    Item,
+};
+use bytes::Bytes;
+use chrono::Utc;
+use futures::StreamExt;
+use std::collections::HashSet;
+use std::fs::{create_dir_all, File};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+mod budget;
+mod harvest;
+mod mirror;
+mod notify;
+mod output;
+mod poison;
+mod results;
+mod routing;
+mod selection;
+mod shard;
+mod writer;
+pub use budget::Budget;
+use budget::BudgetTracker;
+pub use harvest::{HarvestConfig, HarvestSummary};
+pub use mirror::MirrorSummary;
+pub use notify::{Milestone, Notifier, Slack, Stage, StageSummary, Webhook};
+pub(crate) use output::read_items;
+use output::{create_csv, finish_csv, ItemWriter};
+pub use output::{Manifest, OutputCompression, Part, Rotation};
+pub use poison::PoisonDigests;
+use results::QueryResults;
+pub use routing::{MimeClass, MimeRouting};
+pub use selection::{CaptureSelection, Period};
+pub use shard::{Lease, ShardDir, Work, WorkUnit};
+use writer::GzWriter;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("I/O error: {0:?}")]
+    Io(#[from] std::io::Error),
+    #[error("CDX error: {0:?}")]
+    IndexClient(#[from] cdx::Error),
+    #[error("HTTP client error: {0:?}")]
+    Client(#[from] reqwest::Error),
+    #[error("CSV writing error: {0:?}")]
+    Csv(#[from] csv::Error),
+    #[error("Item parsing error: {0:?}")]
+    Item(#[from] super::item::Error),
+    #[error("JSON error: {0:?}")]
+    Json(#[from] serde_json::Error),
+}
+
+pub struct Session {
+    base: PathBuf,
+    data_dirs: Vec<PathBuf>,
+    routing: MimeRouting,
+    skipped_classes: Vec<MimeClass>,
+    known_digests: Option<PathBuf>,
+    parallelism: usize,
+    rotation: Option<Rotation>,
+    compression: OutputCompression,
+    header_store: Option<HeaderStore>,
+    resolve_options: ResolveOptions,
+    writer: GzWriter,
+    selection: CaptureSelection,
+    poison: PoisonDigests,
+    budget: Budget,
+    notifiers: Vec<Box<dyn Notifier>>,
+    index_client: IndexClient,
+    client: Downloader,
+    cancellation: Option<CancellationToken>,
+}
+
+impl Session {
+    const TIMESTAMP_FMT: &'static str = "%Y%m%d%H%M%S";
+
+    pub fn new<P1: AsRef<Path>, P2: AsRef<Path>>(
+        base: P1,
+        known_digests: Option<P2>,
+        parallelism: usize,
+    ) -> Result<Session, Error> {
+        Ok(Session {
+            base: base.as_ref().to_path_buf(),
+            data_dirs: vec![base.as_ref().join("data")],
+            routing: MimeRouting::default(),
+            skipped_classes: vec![],
+            known_digests: known_digests.map(|path| path.as_ref().to_path_buf()),
+            parallelism,
+            rotation: None,
+            compression: OutputCompression::default(),
+            header_store: None,
+            resolve_options: ResolveOptions::default(),
+            writer: GzWriter::new(GzWriter::default_max_in_flight()),
+            selection: CaptureSelection::default(),
+            poison: PoisonDigests::default(),
+            budget: Budget::default(),
+            notifiers: vec![],
+            index_client: IndexClient::default(),
+            client: Downloader::default(),
+            cancellation: None,
+        })
+    }
+
+    pub fn new_timestamped<P: AsRef<Path>>(
+        known_digests: Option<P>,
+        parallelism: usize,
+    ) -> Result<Session, Error> {
+        Self::new(
+            Utc::now().format(Self::TIMESTAMP_FMT).to_string(),
+            known_digests,
+            parallelism,
+        )
+    }
+
+    /// Use the given directories for downloaded data instead of the default
+    /// `data` directory under the session base.
+    ///
+    /// Items are routed to a directory by digest prefix, and if that directory's
+    /// device is full, the remaining directories are tried in order.
+    pub fn with_data_dirs<P: AsRef<Path>>(mut self, dirs: &[P]) -> Self {
+        if !dirs.is_empty() {
+            self.data_dirs = dirs.iter().map(|dir| dir.as_ref().to_path_buf()).collect();
+        }
+        self
+    }
+
+    /// Place downloaded data in separate directories by MIME class.
+    ///
+    /// Lookups search every area, so data saved with different routing is
+    /// still found.
+    pub fn with_mime_routing(mut self, routing: MimeRouting) -> Self {
+        self.routing = routing;
+        self
+    }
+
+    /// Don't download content for items in the given MIME classes.
+    ///
+    /// These items are still listed in the session's item files, so their CDX
+    /// metadata is kept.
+    pub fn with_skipped_mime_classes(mut self, classes: &[MimeClass]) -> Self {
+        self.skipped_classes = classes.to_vec();
+        self
+    }
+
+    fn skips_content(&self, item: &Item) -> bool {
+        self.skipped_classes
+            .contains(&MimeClass::of(&item.mime_type))
+    }
+
+    /// All directories that may contain downloaded data.
+    fn data_areas(&self) -> Vec<PathBuf> {
+        self.routing.all_dirs(&self.data_dirs)
+    }
+
+    /// Rotate the item CSV files (originals, redirects, and extras) into
+    /// numbered parts with the given limits.
+    pub fn with_rotation(mut self, rotation: Rotation) -> Self {
+        self.rotation = Some(rotation);
+        self
+    }
+
+    /// Compress the session's CSV files.
+    ///
+    /// Existing files are read transparently whether or not they are compressed.
+    pub fn with_compression(mut self, compression: OutputCompression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Save the original response headers for downloaded items as JSON files
+    /// in the given directory.
+    pub fn with_header_store<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.header_store = Some(HeaderStore::new(path));
+        self
+    }
+
+    /// Limit the requests made while resolving redirects.
+    pub fn with_resolve_options(mut self, options: ResolveOptions) -> Self {
+        self.resolve_options = options;
+        self
+    }
+
+    /// Skip queries that are known to be blocked, and record newly blocked
+    /// queries in the registry.
+    pub fn with_blocked_registry(mut self, registry: BlockedRegistry) -> Self {
+        let registry = Arc::new(Mutex::new(registry));
+        self.index_client = self.index_client.with_blocked_registry(registry);
+        self
+    }
+
+    /// Cache CDX search results on disk, so that repeated runs don't repeat
+    /// identical searches.
+    pub fn with_cdx_cache(mut self, cache: ResponseCache) -> Self {
+        self.index_client = self.index_client.with_cache(cache);
+        self
+    }
+
+    /// Encrypt downloaded data files with the given key.
+    ///
+    /// The session's CSV files are not encrypted.
+    #[cfg(feature = "encryption")]
+    pub fn with_encryption_key(mut self, key: crate::store::crypto::Key) -> Self {
+        self.writer.set_key(key);
+        self
+    }
+
+    /// Compress data files with the given options.
+    pub fn with_gz_options(mut self, options: crate::store::gz::GzOptions) -> Self {
+        self.writer.set_options(options);
+        self
+    }
+
+    /// Compress at most this many data files at once.
+    ///
+    /// Compression runs on the blocking thread pool, and downloads wait for a
+    /// free slot before writing. Defaults to the available parallelism.
+    pub fn with_write_queue(mut self, max_in_flight: usize) -> Self {
+        self.writer.set_max_in_flight(max_in_flight);
+        self
+    }
+
+    /// Stop starting new downloads (and CDX searches in harvests) once the
+    /// budget is used up.
+    ///
+    /// Item files are written completely, so running the download again will
+    /// resume where the budgeted run stopped.
+    pub fn with_budget(mut self, budget: Budget) -> Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Stop starting new searches, redirect resolutions, and downloads once
+    /// the token is cancelled.
+    ///
+    /// Work in progress is completed and saved, so running the session again
+    /// will resume where the cancelled run stopped.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.index_client = self.index_client.with_cancellation(token.clone());
+        self.client = self.client.with_cancellation(token.clone());
+        self.cancellation = Some(token);
+        self
+    }
+
+    fn budget_tracker(&self) -> BudgetTracker {
+        BudgetTracker::new(self.budget).with_cancellation(self.cancellation.clone())
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancellation
+            .as_ref()
+            .is_some_and(|token| token.is_cancelled())
+    }
+
+    /// Only harvest the selected captures of each URL.
+    pub fn with_capture_selection(mut self, selection: CaptureSelection) -> Self {
+        self.selection = selection;
+        self
+    }
+
+    /// Notify the given notifier when runs start, when stages complete, when the
+    /// budget is used up, and when runs fail.
+    pub fn with_notifier<N: Notifier + 'static>(mut self, notifier: N) -> Self {
+        self.notifiers.push(Box::new(notifier));
+        self
+    }
+
+    fn session_name(&self) -> String {
+        self.base.display().to_string()
+    }
+
+    async fn notify(&self, milestone: Milestone) {
+        for notifier in &self.notifiers {
+            if let Err(error) = notifier.notify(&milestone).await {
+                log::warn!("Failed to send notification: {:?}", error);
+            }
+        }
+    }
+
+    async fn notify_started(&self, queries: usize) {
+        self.notify(Milestone::Started {
+            session: self.session_name(),
+            queries,
+        })
+        .await;
+    }
+
+    async fn notify_budget_exhausted(&self) {
+        self.notify(Milestone::BudgetExhausted {
+            session: self.session_name(),
+        })
+        .await;
+    }
+
+    /// Notify of the completion or failure of a stage.
+    async fn notify_stage<T, F: FnOnce(&T) -> StageSummary>(
+        &self,
+        stage: Stage,
+        result: Result<T, Error>,
+        summary: F,
+    ) -> Result<T, Error> {
+        let milestone = match &result {
+            Ok(value) => Milestone::StageCompleted {
+                session: self.session_name(),
+                stage,
+                summary: summary(value),
+            },
+            Err(error) => Milestone::Failed {
+                session: self.session_name(),
+                error: error.to_string(),
+            },
+        };
+        self.notify(milestone).await;
+
+        result
+    }
+
+    /// Set aside captures with the given digests.
+    ///
+    /// When search results are saved, these captures are written to the
+    /// `poisoned` item file instead of `originals` or `redirects`, and they are
+    /// never downloaded.
+    pub fn with_poison_digests(mut self, digests: PoisonDigests) -> Self {
+        self.poison = digests;
+        self
+    }
+
+    /// A writer for poisoned captures, if there are any poison digests.
+    fn poisoned_writer(&self) -> Result<Option<ItemWriter>, Error> {
+        if self.poison.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(ItemWriter::create(
+                &self.base,
+                "poisoned",
+                self.rotation,
+                self.compression,
+            )?))
+        }
+    }
+
+    /// Write the item to the poisoned writer if its digest is poisoned.
+    fn set_aside_poisoned(
+        &self,
+        item: &Item,
+        writer: &mut Option<ItemWriter>,
+    ) -> Result<bool, Error> {
+        match writer {
+            Some(writer) if self.poison.contains(&item.digest) => {
+                log::debug!(
+                    "Setting aside poisoned capture of {} ({})",
+                    item.url,
+                    self.poison.label(&item.digest).unwrap_or(&item.digest)
+                );
+                writer.write(item)?;
+                Ok(true)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// The CDX query for a search string, with the capture selection applied.
+    fn cdx_query(&self, query: &str) -> CdxQuery {
+        self.selection.apply(CdxQuery::new(query))
+    }
+
+    /// Read the items from one of the session's item files (`originals`,
+    /// `redirects`, `extras`, or `poisoned`).
+    pub fn items(&self, name: &str) -> Result<Vec<Item>, Error> {
+        read_items(&self.base, name)
+    }
+
+    /// Find the data file for the given digest in any of the data directories.
+    pub fn lookup_data(&self, digest: &str) -> Option<PathBuf> {
+        self.data_areas()
+            .iter()
+            .map(|dir| dir.join(format!("{}.gz", digest)))
+            .find(|path| path.is_file())
+    }
+
+    fn data_dir_index(digest: &str, len: usize) -> usize {
+        digest.bytes().next().map_or(0, |first| first as usize) % len
+    }
+
+    async fn write_data(&self, item: &Item, content: &Bytes) -> std::io::Result<PathBuf> {
+        let dirs = self
+            .routing
+            .dirs_for(MimeClass::of(&item.mime_type), &self.data_dirs);
+        let len = dirs.len();
+        let first = Self::data_dir_index(&item.digest, len);
+        let mut last_error = None;
+
+        for i in 0..len {
+            let path = dirs[(first + i) % len].join(format!("{}.gz", item.digest));
+
+            match self.write_gz(&path, item, content).await {
+                Ok(()) => return Ok(path),
+                Err(error) if error.kind() == ErrorKind::StorageFull => {
+                    log::warn!("Data directory full, spilling over: {:?}", path);
+                    let _ = std::fs::remove_file(&path);
+                    last_error = Some(error);
+                }
+                Err(error) => return Err(error),
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| ErrorKind::StorageFull.into()))
+    }
+
+    async fn write_gz(&self, path: &Path, item: &Item, content: &Bytes) -> std::io::Result<()> {
+        self.writer
+            .write(path.to_path_buf(), item.clone(), content.clone())
+            .await
+    }
+
+    /// Search for the given queries and save the results, returning any
+    /// queries that were blocked.
+    pub async fn save_cdx_results(&self, queries: &[String]) -> Result<Vec<String>, Error> {
+        self.notify_started(queries.len()).await;
+        let result = self.search_and_save(queries).await;
+
+        self.notify_stage(Stage::Search, result, |(count, blocked)| StageSummary {
+            success: *count,
+            blocked: blocked.len(),
+            ..StageSummary::default()
+        })
+        .await
+        .map(|(_, blocked)| blocked)
+    }
+
+    /// The saved search results for a query, if its search has completed.
+    pub fn query_items(&self, query: &str) -> Result<Option<Vec<Item>>, Error> {
+        QueryResults::new(&self.base).load(query)
+    }
+
+    /// Search and save results, returning the number of items found along with
+    /// any queries that were blocked.
+    ///
+    /// Each query's results are also saved separately, and queries with saved
+    /// results (from an interrupted run in the same directory) are not
+    /// searched again.
+    async fn search_and_save(&self, queries: &[String]) -> Result<(usize, Vec<String>), Error> {
+        create_dir_all(&self.base)?;
+        let mut query_log = File::create(self.base.join("queries.txt"))?;
+        query_log.write_all(format!("{}\n", queries.join("\n")).as_bytes())?;
+
+        let (blocked, queries) = self.partition_blocked(queries);
+        let results = QueryResults::new(&self.base);
+        let mut found: Vec<(String, Vec<Item>)> = vec![];
+        let mut pending = vec![];
+
+        for query in queries {
+            match results.load(query)? {
+                Some(items) => found.push((query.clone(), items)),
+                None => pending.push(query),
+            }
+        }
+
+        if !found.is_empty() {
+            log::info!("Using saved results for {} queries", found.len());
+        }
+
+        let mut searches = futures::stream::iter(pending)
+            .map(|query| async move {
+                (
+                    query,
+                    self.index_client.search(&self.cdx_query(query)).await,
+                )
+            })
+            .buffer_unordered(self.parallelism.max(1));
+        let mut newly_blocked: Vec<String> = vec![];
+        let mut cancelled = 0;
+
+        while let Some((query, result)) = searches.next().await {
+            match result {
+                Ok(items) => {
+                    results.save(query, &items)?;
+                    found.push((query.clone(), items));
+                }
+                Err(cdx::Error::BlockedQuery(query)) => newly_blocked.push(query),
+                // Unfinished queries are searched again on the next run.
+                Err(cdx::Error::Cancelled) => cancelled += 1,
+                Err(other) => return Err(other.into()),
+            }
+        }
+
+        if cancelled > 0 {
+            log::warn!("Search cancelled; {} queries were not completed", cancelled);
+        }
+
+        let blocked = self.record_blocked(blocked, newly_blocked)?;
+
+        found.sort_by(|(a, _), (b, _)| a.cmp(b));
+        results.write_index(
+            &found
+                .iter()
+                .map(|(query, items)| (query.clone(), items.len()))
+                .collect::<Vec<_>>(),
+        )?;
+
+        let mut items = found
+            .into_iter()
+            .flat_map(|(_, items)| items)
+            .collect::<Vec<_>>();
+
+        items.sort();
+        items.dedup();
+
+        let mut originals_writer =
+            ItemWriter::create(&self.base, "originals", self.rotation, self.compression)?;
+        let mut redirects_writer =
+            ItemWriter::create(&self.base, "redirects", self.rotation, self.compression)?;
+        let mut poisoned_writer = self.poisoned_writer()?;
+
+        for item in &items {
+            if self.set_aside_poisoned(item, &mut poisoned_writer)? {
+                continue;
+            }
+
+            if item.status == Some(302) {
+                redirects_writer.write(item)?;
+            } else {
+                originals_writer.write(item)?;
+            }
+        }
+
+        originals_writer.finish()?;
+        redirects_writer.finish()?;
+        if let Some(writer) = poisoned_writer {
+            writer.finish()?;
+        }
+
+        Ok((items.len(), blocked))
+    }
+
+    /// Split the queries into those known to be blocked and the rest.
+    ///
+    /// Known blocked queries are returned in the form used by the index client
+    /// (and its blocked query errors).
+    fn partition_blocked<'a>(&self, queries: &'a [String]) -> (Vec<String>, Vec<&'a String>) {
+        let mut known_blocked = vec![];
+        let mut unblocked = vec![];
+
+        for query in queries {
+            let cdx_query = self.cdx_query(query);
+
+            if self.index_client.is_blocked(&cdx_query) {
+                known_blocked.push(cdx_query.url().to_string());
+            } else {
+                unblocked.push(query);
+            }
+        }
+
+        if !known_blocked.is_empty() {
+            log::info!("Skipping {} known blocked queries", known_blocked.len());
+        }
+
+        (known_blocked, unblocked)
+    }
+
+    /// Log all blocked queries.
+    ///
+    /// Newly blocked queries have already been added to the registry by the
+    /// index client.
+    fn record_blocked(
+        &self,
+        mut blocked: Vec<String>,
+        newly_blocked: Vec<String>,
+    ) -> Result<Vec<String>, Error> {
+        blocked.extend(newly_blocked);
+
+        if !blocked.is_empty() {
+            let mut blocked_log = File::create(self.base.join("blocked.txt"))?;
+            blocked.sort();
+            blocked_log.write_all(format!("{}\n", blocked.join("\n")).as_bytes())?;
+        }
+
+        Ok(blocked)
+    }
+
+    pub async fn resolve_redirects(&self) -> Result<(), Error> {
+        let result = self.resolve_and_save().await;
+
+        self.notify_stage(Stage::Resolve, result, |summary| *summary)
+            .await
+            .map(|_| ())
+    }
+
+    async fn resolve_and_save(&self) -> Result<StageSummary, Error> {
+        let mut items = read_items(&self.base, "redirects")?;
+
+        items.sort();
+
+        for dir in self.data_areas() {
+            create_dir_all(dir)?;
+        }
+        create_dir_all(self.base.join("invalid"))?;
+
+        let mut digests = HashSet::new();
+
+        items.retain(|item| digests.insert(item.digest.clone()));
+
+        if let Some(path) = &self.known_digests {
+            let file = File::open(path)?;
+            for line in BufReader::new(file).lines() {
+                digests.remove(line?.trim());
+            }
+        }
+
+        items.retain(|item| digests.remove(&item.digest));
+
+        log::info!("Resolving {} items", items.len());
+
+        let results = futures::stream::iter(items.iter())
+            .take_while(|_| futures::future::ready(!self.is_cancelled()))
+            .map(|item| self.resolve_item(item))
+            .buffer_unordered(self.parallelism)
+            .collect::<Vec<_>>()
+            .await;
+
+        create_dir_all(self.base.join("errors"))?;
+
+        let mut redirects_error_csv =
+            create_csv(self.base.join("errors"), "redirects", self.compression)?;
+
+        let mut extras_writer =
+            ItemWriter::create(&self.base, "extras", self.rotation, self.compression)?;
+        let mut summary = StageSummary::default();
+
+        for result in results {
+            match result {
+                Ok(item) => {
+                    summary.success += 1;
+                    extras_writer.write(&item)?;
+                }
+                Err(item) => {
+                    summary.failed += 1;
+                    redirects_error_csv.write_record(item.to_record())?;
+                }
+            }
+        }
+
+        extras_writer.finish()?;
+        finish_csv(redirects_error_csv)?;
+
+        Ok(summary)
+    }
+
+    pub async fn download_items(&self) -> Result<DownloadSummary, Error> {
+        let result = self.download_and_save().await;
+
+        self.notify_stage(Stage::Download, result, |summary| StageSummary {
+            success: summary.success,
+            invalid: summary.invalid,
+            skipped: summary.skipped,
+            failed: summary.failed,
+            blocked: 0,
+        })
+        .await
+    }
+
+    async fn download_and_save(&self) -> Result<DownloadSummary, Error> {
+        let mut items = read_items(&self.base, "originals")?;
+        items.extend(read_items(&self.base, "extras")?);
+        items.sort();
+
+        let total_count = items.len();
+
+        let mut digests = HashSet::new();
+
+        items.retain(|item| digests.insert(item.digest.clone()));
+
+        if let Some(path) = &self.known_digests {
+            let file = File::open(path)?;
+            for line in BufReader::new(file).lines() {
+                digests.remove(line?.trim());
+            }
+        }
+
+        items.retain(|item| digests.remove(&item.digest));
+        items.retain(|item| !self.poison.contains(&item.digest));
+        items.retain(|item| self.lookup_data(&item.digest).is_none());
+
+        let before_skip = items.len();
+        items.retain(|item| !self.skips_content(item));
+        if items.len() < before_skip {
+            log::info!(
+                "Skipping content for {} items by MIME class",
+                before_skip - items.len()
+            );
+        }
+
+        log::info!("Downloading {} items", items.len());
+
+        let tracker = self.budget_tracker();
+
+        let items = futures::stream::iter(items)
+            .take_while(|_| futures::future::ready(!tracker.should_stop()));
+        let results = self
+            .client
+            .download_items(items, self.parallelism)
+            .map(|(item, result, stats)| self.save_result(item, result, stats, &tracker))
+            .buffer_unordered(self.parallelism)
+            .collect::<Vec<_>>()
+            .await;
+
+        let mut retries = RetryTotals::default();
+        for (_, stats) in &results {
+            retries.add(stats);
+        }
+
+        log::info!("Content bytes received: {}", tracker.bytes());
+        retries.log();
+        tracker.log_if_exhausted();
+
+        if tracker.is_exhausted() {
+            self.notify_budget_exhausted().await;
+        }
+
+        let mut error_csv = create_csv(self.base.join("errors"), "items", self.compression)?;
+        let mut invalid_csv = create_csv(self.base.join("errors"), "invalid", self.compression)?;
+
+        let mut success_count = 0;
+        let mut invalid_count = 0;
+        let mut error_count = 0;
+
+        for (result, _) in results {
+            match result {
+                Ok(None) => {
+                    success_count += 1;
+                }
+                Ok(Some((expected, computed))) => {
+                    invalid_count += 1;
+                    invalid_csv.write_record(vec![expected, computed])?;
+                }
+                Err(item) => {
+                    error_count += 1;
+                    error_csv.write_record(item.to_record())?;
+                }
+            }
+        }
+
+        finish_csv(error_csv)?;
+        finish_csv(invalid_csv)?;
+
+        Ok(DownloadSummary {
+            success: success_count,
+            invalid: invalid_count,
+            skipped: total_count - success_count - error_count - invalid_count,
+            failed: error_count,
+            budget_exhausted: tracker.is_exhausted(),
+            retries: retries.retries,
+            retry_delay: retries.delay,
+        })
+    }
+
+    /// Resolve a redirect item, returning the CDX item for its target.
+    async fn resolve_item<'a>(&self, item: &'a Item) -> Result<Item, &'a Item> {
+        log::info!("Resolving: {}", item.url);
+
+        let resolution = self
+            .client
+            .resolve_redirect_with_options(
+                &item.url,
+                &item.timestamp(),
+                &item.digest,
+                &self.resolve_options,
+            )
+            .await
+            .map_err(|_| item)?;
+
+        if resolution.valid_digest || !self.resolve_options.content {
+            let mut items = self
+                .index_client
+                .search(
+                    &CdxQuery::new(&resolution.url)
+                        .with_filter(Filter::timestamp(&resolution.timestamp)),
+                )
+                .await
+                .map_err(|_| item)?;
+
+            let actual_item = items.pop().ok_or(item)?;
+
+            if resolution.valid_digest {
+                self.write_data(item, &resolution.content)
+                    .await
+                    .map_err(|_| item)?;
+            }
+
+            Ok(actual_item)
+        } else {
+            Err(item)
+        }
+    }
+
+    /// Save the result of a download, returning the expected and computed
+    /// digests if they do not match, along with statistics about the
+    /// download's retries.
+    async fn save_result(
+        &self,
+        item: Item,
+        result: Result<(Bytes, OriginalHeaders), downloader::Error>,
+        stats: RetryStats,
+        tracker: &BudgetTracker,
+    ) -> (Result<Option<(String, String)>, Item>, RetryStats) {
+        if stats.retries() > 0 {
+            log::info!(
+                "Download of {} took {} attempts ({:?} waiting)",
+                item.url,
+                stats.attempts,
+                stats.total_delay
+            );
+        }
+
+        let result = match result {
+            Ok((content, headers)) => self.save_download(item, content, headers, tracker).await,
+            Err(_) => Err(item),
+        };
+
+        (result, stats)
+    }
+
+    async fn save_download(
+        &self,
+        item: Item,
+        content: Bytes,
+        headers: OriginalHeaders,
+        tracker: &BudgetTracker,
+    ) -> Result<Option<(String, String)>, Item> {
+        tracker.record(content.len() as u64);
+
+        match VerifiedDownload::check(&item, content) {
+            VerifiedDownload::Verified { bytes } => {
+                self.write_data(&item, &bytes)
+                    .await
+                    .map_err(|_| item.clone())?;
+
+                if let Some(store) = &self.header_store {
+                    if let Err(error) = store.save(&item.digest, &headers) {
+                        log::warn!("Failed to save headers for {}: {:?}", item.digest, error);
+                    }
+                }
+
+                Ok(None)
+            }
+            VerifiedDownload::Mismatch {
+                bytes,
+                actual_digest,
+            } => {
+                let path = self
+                    .base
+                    .join("invalid")
+                    .join(format!("{}.gz", actual_digest));
+                self.write_gz(&path, &item, &bytes)
+                    .await
+                    .map_err(|_| item.clone())?;
+
+                Ok(Some((item.digest, actual_digest)))
+            }
+        }
+    }
+}
+
+/// Counts for a completed download stage.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DownloadSummary {
+    pub success: usize,
+    pub invalid: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    /// Whether the run stopped early because its budget was used up.
+    pub budget_exhausted: bool,
+    /// The number of download attempts that were retries.
+    pub retries: u32,
+    /// The total time spent waiting to retry downloads.
+    pub retry_delay: Duration,
+}
+
+/// Running totals of download retries.
+#[derive(Default)]
+struct RetryTotals {
+    retries: u32,
+    delay: Duration,
+}
+
+impl RetryTotals {
+    fn add(&mut self, stats: &RetryStats) {
+        self.retries += stats.retries();
+        self.delay += stats.total_delay;
+    }
+
+    fn log(&self) {
+        log::info!(
+            "Download retries: {} ({:?} waiting)",
+            self.retries,
+            self.delay
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Budget, Session};
+    use crate::cdx::BlockedRegistry;
+    use std::time::Duration;
+
+    #[test]
+    fn partition_blocked() {
+        let mut registry = BlockedRegistry::new(crate::cdx::blocked::DEFAULT_EXPIRY);
+        registry.insert("example.com/private");
+
+        let session = Session::new("unused", None::<String>, 1)
+            .unwrap()
+            .with_blocked_registry(registry);
+        let queries = vec![
+            "https://www.example.com/private/page".to_string(),
+            "example.com/public".to_string(),
+        ];
+        let (blocked, unblocked) = session.partition_blocked(&queries);
+
+        assert_eq!(blocked, vec!["https://www.example.com/private/page"]);
+        assert_eq!(unblocked, vec!["example.com/public"]);
+    }
+
+    #[tokio::test]
+    async fn download_budget_exhausted() {
+        let base = crate::fixtures::temp_dir("session-budget").unwrap();
+        let items = crate::fixtures::items(3)
+            .into_iter()
+            .map(|(item, _)| item)
+            .collect::<Vec<_>>();
+
+        std::fs::write(
+            base.join("originals.csv"),
+            crate::fixtures::items_csv(&items),
+        )
+        .unwrap();
+        std::fs::write(base.join("extras.csv"), "").unwrap();
+        std::fs::create_dir_all(base.join("errors")).unwrap();
+
+        // A budget that is used up before the first download starts.
+        let session = Session::new(&base, None::<String>, 2)
+            .unwrap()
+            .with_budget(Budget {
+                max_duration: Some(Duration::ZERO),
+                ..Default::default()
+            });
+        let summary = session.download_items().await.unwrap();
+
+        assert!(summary.budget_exhausted);
+        assert_eq!(summary.success, 0);
+        assert_eq!(summary.skipped, 3);
+
+        std::fs::remove_dir_all(base).unwrap();
+    }
+}
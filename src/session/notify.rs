@@ -0,0 +1,199 @@
+//! Notifications of run milestones, so that long harvests can be monitored
+//! without watching a terminal.
+//!
+//! Notification failures are logged but never stop a run.
+
+use futures::future::BoxFuture;
+use reqwest::Client;
+use serde::Serialize;
+use url::Url;
+
+/// A stage of a session run.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    Search,
+    Resolve,
+    Download,
+    /// The streaming pipeline, which runs all of the other stages.
+    Harvest,
+}
+
+impl Stage {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Stage::Search => "search",
+            Stage::Resolve => "resolve",
+            Stage::Download => "download",
+            Stage::Harvest => "harvest",
+        }
+    }
+}
+
+/// Counts for a completed stage.
+///
+/// For the search stage, `success` is the number of items found.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct StageSummary {
+    pub success: usize,
+    pub invalid: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    pub blocked: usize,
+}
+
+/// A point in a run that notifiers are told about.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Milestone {
+    Started {
+        session: String,
+        queries: usize,
+    },
+    StageCompleted {
+        session: String,
+        stage: Stage,
+        summary: StageSummary,
+    },
+    BudgetExhausted {
+        session: String,
+    },
+    Failed {
+        session: String,
+        error: String,
+    },
+}
+
+impl Milestone {
+    /// A one-line human-readable description.
+    pub fn message(&self) -> String {
+        match self {
+            Milestone::Started { session, queries } => {
+                format!("Session {} started with {} queries", session, queries)
+            }
+            Milestone::StageCompleted {
+                session,
+                stage,
+                summary,
+            } => format!(
+                "Session {} completed {}: {} succeeded, {} invalid, {} skipped, {} failed, {} blocked",
+                session,
+                stage.as_str(),
+                summary.success,
+                summary.invalid,
+                summary.skipped,
+                summary.failed,
+                summary.blocked
+            ),
+            Milestone::BudgetExhausted { session } => {
+                format!("Session {} stopped after using up its budget", session)
+            }
+            Milestone::Failed { session, error } => {
+                format!("Session {} failed: {}", session, error)
+            }
+        }
+    }
+}
+
+/// Receives notifications of run milestones.
+pub trait Notifier: Send + Sync {
+    fn notify<'a>(&'a self, milestone: &'a Milestone) -> BoxFuture<'a, Result<(), reqwest::Error>>;
+}
+
+/// Posts each milestone as JSON to a URL.
+pub struct Webhook {
+    client: Client,
+    url: Url,
+}
+
+impl Webhook {
+    pub fn new(url: Url) -> Self {
+        Self {
+            client: Client::new(),
+            url,
+        }
+    }
+}
+
+impl Notifier for Webhook {
+    fn notify<'a>(&'a self, milestone: &'a Milestone) -> BoxFuture<'a, Result<(), reqwest::Error>> {
+        Box::pin(async move {
+            self.client
+                .post(self.url.clone())
+                .json(milestone)
+                .send()
+                .await?
+                .error_for_status()?;
+
+            Ok(())
+        })
+    }
+}
+
+/// Posts each milestone's message to a Slack incoming webhook.
+pub struct Slack {
+    client: Client,
+    url: Url,
+}
+
+impl Slack {
+    pub fn new(url: Url) -> Self {
+        Self {
+            client: Client::new(),
+            url,
+        }
+    }
+}
+
+impl Notifier for Slack {
+    fn notify<'a>(&'a self, milestone: &'a Milestone) -> BoxFuture<'a, Result<(), reqwest::Error>> {
+        Box::pin(async move {
+            self.client
+                .post(self.url.clone())
+                .json(&serde_json::json!({ "text": milestone.message() }))
+                .send()
+                .await?
+                .error_for_status()?;
+
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Milestone, Stage, StageSummary};
+
+    #[test]
+    fn milestone_payload() {
+        let milestone = Milestone::StageCompleted {
+            session: "20240101000000".to_string(),
+            stage: Stage::Download,
+            summary: StageSummary {
+                success: 10,
+                failed: 1,
+                ..StageSummary::default()
+            },
+        };
+
+        assert_eq!(
+            serde_json::to_value(&milestone).unwrap(),
+            serde_json::json!({
+                "event": "stage_completed",
+                "session": "20240101000000",
+                "stage": "download",
+                "summary": {
+                    "success": 10,
+                    "invalid": 0,
+                    "skipped": 0,
+                    "failed": 1,
+                    "blocked": 0
+                }
+            })
+        );
+        assert_eq!(
+            milestone.message(),
+            "Session 20240101000000 completed download: 10 succeeded, 0 invalid, 0 skipped, 1 failed, 0 blocked"
+        );
+    }
+}
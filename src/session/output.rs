@@ -0,0 +1,422 @@
+//! Item CSV output for sessions, with optional compression and rotation into
+//! numbered parts.
+
+use super::{Error, Item};
+use csv::{ReaderBuilder, Writer, WriterBuilder};
+use flate2::{read::MultiGzDecoder, write::GzEncoder};
+use serde::{Deserialize, Serialize};
+use std::fs::{remove_file, File};
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Compression for session CSV files.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum OutputCompression {
+    #[default]
+    None,
+    Gzip,
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl OutputCompression {
+    const ALL: &'static [OutputCompression] = &[
+        OutputCompression::None,
+        OutputCompression::Gzip,
+        #[cfg(feature = "zstd")]
+        OutputCompression::Zstd,
+    ];
+
+    fn extension(&self) -> &'static str {
+        match self {
+            OutputCompression::None => "",
+            OutputCompression::Gzip => ".gz",
+            #[cfg(feature = "zstd")]
+            OutputCompression::Zstd => ".zst",
+        }
+    }
+
+    fn create(&self, path: &Path) -> std::io::Result<Output> {
+        let file = File::create(path)?;
+
+        Ok(match self {
+            OutputCompression::None => Output::Plain(file),
+            OutputCompression::Gzip => {
+                Output::Gzip(GzEncoder::new(file, flate2::Compression::default()))
+            }
+            #[cfg(feature = "zstd")]
+            OutputCompression::Zstd => Output::Zstd(zstd::Encoder::new(file, 0)?),
+        })
+    }
+
+    fn open(&self, path: &Path) -> std::io::Result<Box<dyn Read>> {
+        let file = BufReader::new(File::open(path)?);
+
+        Ok(match self {
+            OutputCompression::None => Box::new(file),
+            OutputCompression::Gzip => Box::new(MultiGzDecoder::new(file)),
+            #[cfg(feature = "zstd")]
+            OutputCompression::Zstd => Box::new(zstd::Decoder::with_buffer(file)?),
+        })
+    }
+
+    fn for_file_name(name: &str) -> OutputCompression {
+        Self::ALL
+            .iter()
+            .rev()
+            .find(|compression| name.ends_with(compression.extension()))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl FromStr for OutputCompression {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(OutputCompression::None),
+            "gzip" => Ok(OutputCompression::Gzip),
+            #[cfg(feature = "zstd")]
+            "zstd" => Ok(OutputCompression::Zstd),
+            other => Err(format!("Unsupported compression: {}", other)),
+        }
+    }
+}
+
+/// A possibly-compressed output file.
+pub(crate) enum Output {
+    Plain(File),
+    Gzip(GzEncoder<File>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::Encoder<'static, File>),
+}
+
+impl Output {
+    fn finish(self) -> std::io::Result<()> {
+        match self {
+            Output::Plain(mut file) => file.flush(),
+            Output::Gzip(encoder) => encoder.finish().map(|_| ()),
+            #[cfg(feature = "zstd")]
+            Output::Zstd(encoder) => encoder.finish().map(|_| ()),
+        }
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Output::Plain(file) => file.write(buf),
+            Output::Gzip(encoder) => encoder.write(buf),
+            #[cfg(feature = "zstd")]
+            Output::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Output::Plain(file) => file.flush(),
+            Output::Gzip(encoder) => encoder.flush(),
+            #[cfg(feature = "zstd")]
+            Output::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// A CSV writer for session output that must be closed with [`finish_csv`].
+pub(crate) type CsvWriter = Writer<Output>;
+
+pub(crate) fn create_csv<P: AsRef<Path>>(
+    dir: P,
+    name: &str,
+    compression: OutputCompression,
+) -> Result<CsvWriter, Error> {
+    let path = dir
+        .as_ref()
+        .join(format!("{}.csv{}", name, compression.extension()));
+
+    Ok(WriterBuilder::new().from_writer(compression.create(&path)?))
+}
+
+pub(crate) fn finish_csv(writer: CsvWriter) -> Result<(), Error> {
+    writer
+        .into_inner()
+        .map_err(|error| error.into_error())?
+        .finish()?;
+
+    Ok(())
+}
+
+/// Limits for rotating item CSV files into numbered parts.
+///
+/// Sizes are measured before compression.
+///
+/// When a part reaches either limit, it is closed and a new part is started.
+/// The parts are listed in a `<name>.manifest.json` file.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Rotation {
+    pub max_rows: Option<u64>,
+    pub max_bytes: Option<u64>,
+}
+
+impl Rotation {
+    pub fn by_rows(max_rows: u64) -> Self {
+        Self {
+            max_rows: Some(max_rows),
+            max_bytes: None,
+        }
+    }
+
+    pub fn by_bytes(max_bytes: u64) -> Self {
+        Self {
+            max_rows: None,
+            max_bytes: Some(max_bytes),
+        }
+    }
+
+    fn is_full(&self, rows: u64, bytes: u64) -> bool {
+        self.max_rows.is_some_and(|max| rows >= max)
+            || self.max_bytes.is_some_and(|max| bytes >= max)
+    }
+}
+
+/// A list of the parts of a rotated item CSV file.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Manifest {
+    pub parts: Vec<Part>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct Part {
+    pub file: String,
+    pub rows: u64,
+}
+
+struct CountingWriter<W> {
+    underlying: W,
+    count: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.underlying.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.underlying.flush()
+    }
+}
+
+pub(crate) struct ItemWriter {
+    dir: PathBuf,
+    name: String,
+    rotation: Option<Rotation>,
+    compression: OutputCompression,
+    current: Option<Writer<CountingWriter<Output>>>,
+    rows: u64,
+    manifest: Manifest,
+}
+
+impl ItemWriter {
+    pub(crate) fn create<P: AsRef<Path>>(
+        dir: P,
+        name: &str,
+        rotation: Option<Rotation>,
+        compression: OutputCompression,
+    ) -> Result<Self, Error> {
+        let dir = dir.as_ref().to_path_buf();
+
+        // Remove any manifest left by an earlier run.
+        if let Err(error) = remove_file(manifest_path(&dir, name)) {
+            if error.kind() != ErrorKind::NotFound {
+                return Err(error.into());
+            }
+        }
+
+        Ok(Self {
+            dir,
+            name: name.to_string(),
+            rotation,
+            compression,
+            current: None,
+            rows: 0,
+            manifest: Manifest::default(),
+        })
+    }
+
+    fn part_file_name(&self) -> String {
+        match self.rotation {
+            Some(_) => format!(
+                "{}-{:05}.csv{}",
+                self.name,
+                self.manifest.parts.len(),
+                self.compression.extension()
+            ),
+            None => format!("{}.csv{}", self.name, self.compression.extension()),
+        }
+    }
+
+    fn open_part(&mut self) -> Result<(), Error> {
+        let output = self
+            .compression
+            .create(&self.dir.join(self.part_file_name()))?;
+        self.current = Some(WriterBuilder::new().from_writer(CountingWriter {
+            underlying: output,
+            count: 0,
+        }));
+        self.rows = 0;
+
+        Ok(())
+    }
+
+    fn close_part(&mut self) -> Result<(), Error> {
+        if let Some(writer) = self.current.take() {
+            writer
+                .into_inner()
+                .map_err(|error| error.into_error())?
+                .underlying
+                .finish()?;
+
+            if self.rotation.is_some() {
+                let file = self.part_file_name();
+                self.manifest.parts.push(Part {
+                    file,
+                    rows: self.rows,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn write(&mut self, item: &Item) -> Result<(), Error> {
+        if self.current.is_none() {
+            self.open_part()?;
+        }
+
+        if let Some(writer) = self.current.as_mut() {
+            writer.write_record(item.to_record())?;
+            self.rows += 1;
+
+            let bytes = writer.get_ref().count;
+
+            if self
+                .rotation
+                .is_some_and(|rotation| rotation.is_full(self.rows, bytes))
+            {
+                self.close_part()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn finish(mut self) -> Result<(), Error> {
+        // Make sure that an (empty) file exists even if nothing was written.
+        if self.current.is_none() && (self.rotation.is_none() || self.manifest.parts.is_empty()) {
+            self.open_part()?;
+        }
+
+        self.close_part()?;
+
+        if self.rotation.is_some() {
+            let file = File::create(manifest_path(&self.dir, &self.name))?;
+            serde_json::to_writer_pretty(file, &self.manifest)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn manifest_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.manifest.json", name))
+}
+
+/// Read all items for the given name, following the manifest if the output
+/// was rotated.
+pub(crate) fn read_items<P: AsRef<Path>>(dir: P, name: &str) -> Result<Vec<Item>, Error> {
+    let dir = dir.as_ref();
+
+    match File::open(manifest_path(dir, name)) {
+        Ok(file) => {
+            let manifest: Manifest = serde_json::from_reader(BufReader::new(file))?;
+            let mut items = vec![];
+
+            for part in manifest.parts {
+                let compression = OutputCompression::for_file_name(&part.file);
+                items.extend(read_csv(compression.open(&dir.join(part.file))?)?);
+            }
+
+            Ok(items)
+        }
+        Err(error) if error.kind() == ErrorKind::NotFound => {
+            for compression in OutputCompression::ALL {
+                let path = dir.join(format!("{}.csv{}", name, compression.extension()));
+
+                if path.is_file() {
+                    return read_csv(compression.open(&path)?);
+                }
+            }
+
+            Err(std::io::Error::new(
+                ErrorKind::NotFound,
+                format!("No {} CSV file in {:?}", name, dir),
+            )
+            .into())
+        }
+        Err(error) => Err(error.into()),
+    }
+}
+
+pub(crate) fn read_csv<R: Read>(reader: R) -> Result<Vec<Item>, Error> {
+    let mut csv_reader = ReaderBuilder::new().has_headers(false).from_reader(reader);
+
+    csv_reader
+        .records()
+        .map(|record| {
+            let row = record?;
+            Ok(Item::parse_optional_record(
+                row.get(0),
+                row.get(1),
+                row.get(2),
+                row.get(3),
+                row.get(4),
+                row.get(5),
+            )?)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{read_items, ItemWriter, OutputCompression, Rotation};
+
+    #[test]
+    fn rotation_round_trip() {
+        let dir = crate::fixtures::temp_dir("rotation").unwrap();
+        let items = crate::fixtures::items(25)
+            .into_iter()
+            .map(|(item, _)| item)
+            .collect::<Vec<_>>();
+
+        let mut writer = ItemWriter::create(
+            &dir,
+            "originals",
+            Some(Rotation::by_rows(10)),
+            OutputCompression::Gzip,
+        )
+        .unwrap();
+        for item in &items {
+            writer.write(item).unwrap();
+        }
+        writer.finish().unwrap();
+
+        assert!(dir.join("originals-00002.csv.gz").is_file());
+        assert_eq!(read_items(&dir, "originals").unwrap(), items);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,98 @@
+//! Digests of well-known junk captures, such as archived interstitials and
+//! error pages.
+//!
+//! Captures with these digests are set aside when search results are saved
+//! and are never downloaded.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+/// A list of digests to exclude, each with an optional label describing the
+/// content.
+#[derive(Clone, Debug, Default)]
+pub struct PoisonDigests {
+    labels: HashMap<String, String>,
+}
+
+impl PoisonDigests {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Load a list with one digest per line, optionally followed by whitespace
+    /// and a label.
+    ///
+    /// Blank lines and lines starting with `#` are ignored.
+    pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<Self> {
+        let mut digests = Self::new();
+
+        for line in BufReader::new(File::open(path)?).lines() {
+            let line = line?;
+            let line = line.trim();
+
+            if !line.is_empty() && !line.starts_with('#') {
+                let (digest, label) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+                digests.insert(digest, label.trim());
+            }
+        }
+
+        Ok(digests)
+    }
+
+    pub fn insert(&mut self, digest: &str, label: &str) {
+        self.labels.insert(digest.to_string(), label.to_string());
+    }
+
+    pub fn contains(&self, digest: &str) -> bool {
+        self.labels.contains_key(digest)
+    }
+
+    /// The label for a digest, if it is in the list and has one.
+    pub fn label(&self, digest: &str) -> Option<&str> {
+        self.labels
+            .get(digest)
+            .map(|label| label.as_str())
+            .filter(|label| !label.is_empty())
+    }
+
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PoisonDigests;
+    use std::io::Write;
+
+    #[test]
+    fn load() {
+        let dir = crate::fixtures::temp_dir("poison").unwrap();
+        let path = dir.join("poison.txt");
+        let mut file = std::fs::File::create(&path).unwrap();
+        writeln!(file, "# Known junk").unwrap();
+        writeln!(file, "3I42H3S6NNFQ2MSVX7XZKYAYSCX5QBYJ  Interstitial page").unwrap();
+        writeln!(file).unwrap();
+        writeln!(file, "  LYZIZEVLKE3RSRRGUVVDRYCUZKGWKVNP").unwrap();
+        drop(file);
+
+        let digests = PoisonDigests::load(&path).unwrap();
+
+        assert_eq!(digests.len(), 2);
+        assert_eq!(
+            digests.label("3I42H3S6NNFQ2MSVX7XZKYAYSCX5QBYJ"),
+            Some("Interstitial page")
+        );
+        assert!(digests.contains("LYZIZEVLKE3RSRRGUVVDRYCUZKGWKVNP"));
+        assert_eq!(digests.label("LYZIZEVLKE3RSRRGUVVDRYCUZKGWKVNP"), None);
+        assert!(!digests.contains("Known"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,106 @@
+//! Search results saved separately for each query.
+//!
+//! Each query's results are written to a file named with the SHA-1 digest of
+//! the query once its search is complete, so an interrupted search can be
+//! resumed without repeating the queries that finished. An index file maps
+//! queries to result files.
+
+use super::{output::read_csv, Error};
+use crate::{digest::compute_digest, Item};
+use csv::WriterBuilder;
+use std::fs::{create_dir_all, rename, File};
+use std::io::{BufReader, ErrorKind};
+use std::path::{Path, PathBuf};
+
+const DIR_NAME: &str = "query-results";
+const INDEX_FILE_NAME: &str = "index.csv";
+
+pub(crate) struct QueryResults {
+    dir: PathBuf,
+}
+
+impl QueryResults {
+    pub(crate) fn new<P: AsRef<Path>>(base: P) -> Self {
+        Self {
+            dir: base.as_ref().join(DIR_NAME),
+        }
+    }
+
+    fn file_name(query: &str) -> Result<String, Error> {
+        Ok(format!("{}.csv", compute_digest(&mut query.as_bytes())?))
+    }
+
+    /// The saved results for a query, if its search has completed.
+    pub(crate) fn load(&self, query: &str) -> Result<Option<Vec<Item>>, Error> {
+        match File::open(self.dir.join(Self::file_name(query)?)) {
+            Ok(file) => Ok(Some(read_csv(BufReader::new(file))?)),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    pub(crate) fn save(&self, query: &str, items: &[Item]) -> Result<(), Error> {
+        create_dir_all(&self.dir)?;
+
+        let path = self.dir.join(Self::file_name(query)?);
+        let temp = path.with_extension("csv.tmp");
+        let mut writer = WriterBuilder::new().has_headers(false).from_path(&temp)?;
+
+        for item in items {
+            writer.write_record(item.to_record())?;
+        }
+
+        writer.flush()?;
+        rename(temp, path)?;
+
+        Ok(())
+    }
+
+    /// Write the index of queries, result files, and item counts.
+    pub(crate) fn write_index(&self, counts: &[(String, usize)]) -> Result<(), Error> {
+        create_dir_all(&self.dir)?;
+
+        let mut writer = WriterBuilder::new().from_path(self.dir.join(INDEX_FILE_NAME))?;
+        writer.write_record(["query", "file", "items"])?;
+
+        for (query, count) in counts {
+            writer.write_record([query.clone(), Self::file_name(query)?, count.to_string()])?;
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::QueryResults;
+
+    #[test]
+    fn save_and_load() {
+        let base = crate::fixtures::temp_dir("query-results").unwrap();
+        let results = QueryResults::new(&base);
+        let items = crate::fixtures::items(3)
+            .into_iter()
+            .map(|(item, _)| item)
+            .collect::<Vec<_>>();
+
+        assert!(results.load("example.com/*").unwrap().is_none());
+
+        results.save("example.com/*", &items).unwrap();
+        results.save("example.org", &[]).unwrap();
+
+        assert_eq!(results.load("example.com/*").unwrap(), Some(items));
+        assert_eq!(results.load("example.org").unwrap(), Some(vec![]));
+
+        results
+            .write_index(&[("example.com/*".to_string(), 3)])
+            .unwrap();
+        let index = std::fs::read_to_string(base.join("query-results/index.csv")).unwrap();
+        assert!(index.starts_with("query,file,items\nexample.com/*,"));
+        assert!(index.ends_with(".csv,3\n"));
+
+        std::fs::remove_dir_all(base).unwrap();
+    }
+}
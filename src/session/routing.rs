@@ -0,0 +1,154 @@
+//! Routing downloaded data into separate areas by MIME type.
+//!
+//! This allows (for example) text analysis to operate on an HTML-only tree
+//! without scanning large amounts of media.
+
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// A broad class of MIME types.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum MimeClass {
+    Html,
+    Image,
+    /// Audio and video.
+    Media,
+    Other,
+}
+
+impl MimeClass {
+    pub const ALL: [MimeClass; 4] = [
+        MimeClass::Html,
+        MimeClass::Image,
+        MimeClass::Media,
+        MimeClass::Other,
+    ];
+
+    /// Classify a MIME type (ignoring any parameters).
+    pub fn of(mime_type: &str) -> Self {
+        let essence = mime_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+
+        match essence.as_str() {
+            "text/html" | "application/xhtml+xml" => MimeClass::Html,
+            other if other.starts_with("image/") => MimeClass::Image,
+            other if other.starts_with("audio/") || other.starts_with("video/") => MimeClass::Media,
+            _ => MimeClass::Other,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MimeClass::Html => "html",
+            MimeClass::Image => "images",
+            MimeClass::Media => "media",
+            MimeClass::Other => "other",
+        }
+    }
+}
+
+impl FromStr for MimeClass {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        MimeClass::ALL
+            .into_iter()
+            .find(|class| class.as_str() == s)
+            .ok_or_else(|| format!("Unsupported MIME class: {}", s))
+    }
+}
+
+/// Rules for placing downloaded data by MIME class.
+///
+/// By default all data is placed directly in the session's data directories.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MimeRouting {
+    subdirectories: bool,
+    roots: Vec<(MimeClass, PathBuf)>,
+}
+
+impl MimeRouting {
+    /// Place each class in a subdirectory (named by the class) of the data
+    /// directories.
+    pub fn subdirectories() -> Self {
+        Self {
+            subdirectories: true,
+            roots: vec![],
+        }
+    }
+
+    /// Place the given class in a separate root directory instead of the data
+    /// directories.
+    pub fn with_root<P: AsRef<Path>>(mut self, class: MimeClass, root: P) -> Self {
+        self.roots.retain(|(existing, _)| *existing != class);
+        self.roots.push((class, root.as_ref().to_path_buf()));
+        self
+    }
+
+    /// The directories for new data of the given class.
+    pub(crate) fn dirs_for(&self, class: MimeClass, data_dirs: &[PathBuf]) -> Vec<PathBuf> {
+        match self
+            .roots
+            .iter()
+            .find(|(root_class, _)| *root_class == class)
+        {
+            Some((_, root)) => vec![root.clone()],
+            None if self.subdirectories => data_dirs
+                .iter()
+                .map(|dir| dir.join(class.as_str()))
+                .collect(),
+            None => data_dirs.to_vec(),
+        }
+    }
+
+    /// All directories that may contain data, including the data directories
+    /// themselves (for data saved before routing was enabled).
+    pub(crate) fn all_dirs(&self, data_dirs: &[PathBuf]) -> Vec<PathBuf> {
+        let mut dirs = data_dirs.to_vec();
+
+        for class in MimeClass::ALL {
+            for dir in self.dirs_for(class, data_dirs) {
+                if !dirs.contains(&dir) {
+                    dirs.push(dir);
+                }
+            }
+        }
+
+        dirs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MimeClass, MimeRouting};
+    use std::path::PathBuf;
+
+    #[test]
+    fn routing() {
+        assert_eq!(MimeClass::of("text/html; charset=utf-8"), MimeClass::Html);
+        assert_eq!(MimeClass::of("image/png"), MimeClass::Image);
+        assert_eq!(MimeClass::of("video/mp4"), MimeClass::Media);
+        assert_eq!(MimeClass::of("warc/revisit"), MimeClass::Other);
+
+        let data_dirs = vec![PathBuf::from("a"), PathBuf::from("b")];
+        let routing = MimeRouting::subdirectories().with_root(MimeClass::Media, "media");
+
+        assert_eq!(
+            routing.dirs_for(MimeClass::Html, &data_dirs),
+            vec![PathBuf::from("a/html"), PathBuf::from("b/html")]
+        );
+        assert_eq!(
+            routing.dirs_for(MimeClass::Media, &data_dirs),
+            vec![PathBuf::from("media")]
+        );
+        assert_eq!(routing.all_dirs(&data_dirs).len(), 9);
+        assert_eq!(
+            MimeRouting::default().dirs_for(MimeClass::Image, &data_dirs),
+            data_dirs
+        );
+    }
+}
@@ -0,0 +1,93 @@
+//! Selecting a subset of the captures found by CDX searches.
+//!
+//! Selection is done by the CDX server (with `collapse`), so unselected
+//! captures are never transferred.
+
+use crate::cdx::{CdxQuery, Field, Filter};
+use std::str::FromStr;
+
+/// Which captures of each URL to harvest.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum CaptureSelection {
+    #[default]
+    All,
+    /// Only the earliest successful or redirect capture of each URL.
+    First,
+    /// The earliest successful or redirect capture of each URL in each period.
+    ///
+    /// Collapsing only compares adjacent rows, so when one URL's last capture
+    /// and the next URL's first capture fall in the same period, the latter is
+    /// skipped.
+    PerPeriod(Period),
+}
+
+/// A calendar period for sampling captures.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Period {
+    Year,
+    Month,
+}
+
+impl Period {
+    /// The number of timestamp digits identifying the period.
+    fn prefix_length(&self) -> usize {
+        match self {
+            Period::Year => 4,
+            Period::Month => 6,
+        }
+    }
+}
+
+impl CaptureSelection {
+    pub(crate) fn apply(&self, query: CdxQuery) -> CdxQuery {
+        match self {
+            CaptureSelection::All => query,
+            // Results are sorted by URL key and then timestamp, and filters are
+            // applied before collapsing.
+            CaptureSelection::First => query
+                .with_filter(Filter::status_range(200..=399))
+                .with_collapse(Field::UrlKey),
+            CaptureSelection::PerPeriod(period) => query
+                .with_filter(Filter::status_range(200..=399))
+                .with_collapse_prefix(Field::Timestamp, period.prefix_length()),
+        }
+    }
+}
+
+impl FromStr for CaptureSelection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(CaptureSelection::All),
+            "first" => Ok(CaptureSelection::First),
+            "year" => Ok(CaptureSelection::PerPeriod(Period::Year)),
+            "month" => Ok(CaptureSelection::PerPeriod(Period::Month)),
+            other => Err(format!("Unsupported capture selection: {}", other)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CaptureSelection;
+    use crate::cdx::CdxQuery;
+
+    #[test]
+    fn first_capture() {
+        let selection = "first".parse::<CaptureSelection>().unwrap();
+        let params = selection.apply(CdxQuery::new("example.com/*")).params();
+
+        assert!(params.contains(&("collapse", "urlkey".to_string())));
+        assert!(params.contains(&("filter", "statuscode:[2-3][0-9][0-9]".to_string())));
+    }
+
+    #[test]
+    fn per_month() {
+        let selection = "month".parse::<CaptureSelection>().unwrap();
+        let params = selection.apply(CdxQuery::new("example.com/*")).params();
+
+        assert!(params.contains(&("collapse", "timestamp:6".to_string())));
+        assert!(!params.contains(&("collapse", "urlkey".to_string())));
+    }
+}
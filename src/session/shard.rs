@@ -0,0 +1,352 @@
+//! Splitting a large harvest into work units that several machines can claim
+//! from a shared directory.
+//!
+//! Units are claimed by creating a lease file, which other workers respect
+//! until it expires. Completed units are marked in the `done` directory, so
+//! an interrupted worker's unit is picked up by another worker once its lease
+//! runs out. Taking over an expired lease is best-effort: two workers racing
+//! for the same expired lease will usually (but not always) be resolved by
+//! the check after the takeover.
+
+use super::Error;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::fs::{create_dir_all, read_dir, remove_file, rename, File, OpenOptions};
+use std::io::{BufReader, BufWriter, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The characters that Base32 digests start with.
+const DIGEST_ALPHABET: &str = "ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// The work in a unit.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Work {
+    /// CDX queries to search for.
+    Queries { queries: Vec<String> },
+    /// Digests starting with any of these prefixes.
+    DigestPrefixes { prefixes: Vec<String> },
+}
+
+impl Work {
+    /// Whether a digest falls in this unit (always false for queries).
+    pub fn matches_digest(&self, digest: &str) -> bool {
+        match self {
+            Work::Queries { .. } => false,
+            Work::DigestPrefixes { prefixes } => {
+                prefixes.iter().any(|prefix| digest.starts_with(prefix))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct WorkUnit {
+    pub id: String,
+    pub work: Work,
+}
+
+#[derive(Deserialize, Serialize)]
+struct LeaseRecord {
+    worker: String,
+    /// Unix timestamp in seconds.
+    expires: i64,
+}
+
+impl LeaseRecord {
+    fn new(worker: &str, duration: Duration) -> Self {
+        Self {
+            worker: worker.to_string(),
+            expires: Utc::now().timestamp() + duration.as_secs() as i64,
+        }
+    }
+
+    fn is_expired(&self) -> bool {
+        Utc::now().timestamp() >= self.expires
+    }
+}
+
+/// A shared directory of work units.
+pub struct ShardDir {
+    dir: PathBuf,
+}
+
+impl ShardDir {
+    pub fn open<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Create units of up to `per_unit` queries each.
+    pub fn create_for_queries<P: AsRef<Path>>(
+        dir: P,
+        queries: &[String],
+        per_unit: usize,
+    ) -> Result<Self, Error> {
+        let shards = Self::open(dir);
+        let works = queries.chunks(per_unit.max(1)).map(|chunk| Work::Queries {
+            queries: chunk.to_vec(),
+        });
+
+        shards.write_units(works)?;
+
+        Ok(shards)
+    }
+
+    /// Create the given number of units (at most 32) by splitting digests on
+    /// their first character.
+    pub fn create_for_digest_prefixes<P: AsRef<Path>>(dir: P, units: usize) -> Result<Self, Error> {
+        let shards = Self::open(dir);
+        let prefixes = DIGEST_ALPHABET
+            .chars()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>();
+        let per_unit = prefixes.len().div_ceil(units.clamp(1, prefixes.len()));
+        let works = prefixes.chunks(per_unit).map(|chunk| Work::DigestPrefixes {
+            prefixes: chunk.to_vec(),
+        });
+
+        shards.write_units(works)?;
+
+        Ok(shards)
+    }
+
+    fn write_units<I: Iterator<Item = Work>>(&self, works: I) -> Result<(), Error> {
+        for dir in ["units", "leases", "done"] {
+            create_dir_all(self.dir.join(dir))?;
+        }
+
+        for (i, work) in works.enumerate() {
+            let unit = WorkUnit {
+                id: format!("{:05}", i),
+                work,
+            };
+            let file = File::create(self.unit_path(&unit.id))?;
+            serde_json::to_writer_pretty(BufWriter::new(file), &unit)?;
+        }
+
+        Ok(())
+    }
+
+    fn unit_path(&self, id: &str) -> PathBuf {
+        self.dir.join("units").join(format!("{}.json", id))
+    }
+
+    fn lease_path(&self, id: &str) -> PathBuf {
+        self.dir.join("leases").join(format!("{}.json", id))
+    }
+
+    fn done_path(&self, id: &str) -> PathBuf {
+        self.dir.join("done").join(id)
+    }
+
+    /// All units, in order.
+    pub fn units(&self) -> Result<Vec<WorkUnit>, Error> {
+        let mut paths = read_dir(self.dir.join("units"))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        paths.sort();
+
+        paths
+            .iter()
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "json")
+            })
+            .map(|path| Ok(serde_json::from_reader(BufReader::new(File::open(path)?))?))
+            .collect()
+    }
+
+    pub fn is_done(&self, id: &str) -> bool {
+        self.done_path(id).exists()
+    }
+
+    /// The number of units that have not been completed.
+    pub fn remaining(&self) -> Result<usize, Error> {
+        Ok(self
+            .units()?
+            .iter()
+            .filter(|unit| !self.is_done(&unit.id))
+            .count())
+    }
+
+    /// Claim the first unit that is neither done nor leased to another worker.
+    pub fn claim(&self, worker: &str, duration: Duration) -> Result<Option<Lease>, Error> {
+        for unit in self.units()? {
+            if self.is_done(&unit.id) {
+                continue;
+            }
+
+            let lease_path = self.lease_path(&unit.id);
+            let claimed = match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&lease_path)
+            {
+                Ok(file) => {
+                    serde_json::to_writer(file, &LeaseRecord::new(worker, duration))?;
+                    true
+                }
+                Err(error) if error.kind() == ErrorKind::AlreadyExists => {
+                    self.take_over_expired(&unit.id, worker, duration)?
+                }
+                Err(error) => return Err(error.into()),
+            };
+
+            if claimed {
+                return Ok(Some(Lease {
+                    shards: self.dir.clone(),
+                    unit,
+                    worker: worker.to_string(),
+                    duration,
+                }));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn take_over_expired(&self, id: &str, worker: &str, duration: Duration) -> Result<bool, Error> {
+        let lease_path = self.lease_path(id);
+
+        match read_lease(&lease_path)? {
+            Some(record) if !record.is_expired() => Ok(false),
+            _ => {
+                log::info!("Taking over expired lease for unit {}", id);
+                write_lease(&lease_path, &LeaseRecord::new(worker, duration))?;
+
+                Ok(read_lease(&lease_path)?.is_some_and(|record| record.worker == worker))
+            }
+        }
+    }
+}
+
+/// A claimed work unit.
+pub struct Lease {
+    shards: PathBuf,
+    pub unit: WorkUnit,
+    worker: String,
+    duration: Duration,
+}
+
+impl Lease {
+    fn shard_dir(&self) -> ShardDir {
+        ShardDir::open(&self.shards)
+    }
+
+    /// Extend the lease, returning false if another worker has taken it over.
+    pub fn renew(&self) -> Result<bool, Error> {
+        let shards = self.shard_dir();
+        let lease_path = shards.lease_path(&self.unit.id);
+
+        if read_lease(&lease_path)?.is_some_and(|record| record.worker == self.worker) {
+            write_lease(&lease_path, &LeaseRecord::new(&self.worker, self.duration))?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Mark the unit as done and release the lease.
+    pub fn complete(self) -> Result<(), Error> {
+        let shards = self.shard_dir();
+        File::create(shards.done_path(&self.unit.id))?;
+
+        self.release()
+    }
+
+    /// Release the lease without completing the unit.
+    pub fn release(self) -> Result<(), Error> {
+        match remove_file(self.shard_dir().lease_path(&self.unit.id)) {
+            Err(error) if error.kind() != ErrorKind::NotFound => Err(error.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn read_lease(path: &Path) -> Result<Option<LeaseRecord>, Error> {
+    match File::open(path) {
+        Ok(file) => Ok(serde_json::from_reader(BufReader::new(file)).ok()),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Replace a lease file atomically.
+fn write_lease(path: &Path, record: &LeaseRecord) -> Result<(), Error> {
+    let temp = path.with_extension(format!("{}.tmp", std::process::id()));
+    serde_json::to_writer(File::create(&temp)?, record)?;
+    rename(temp, path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ShardDir, Work};
+    use std::time::Duration;
+
+    #[test]
+    fn claim_and_complete() {
+        let dir = crate::fixtures::temp_dir("shards").unwrap();
+        let queries = (0..5)
+            .map(|i| format!("example.com/{}/*", i))
+            .collect::<Vec<_>>();
+        let shards = ShardDir::create_for_queries(&dir, &queries, 2).unwrap();
+        let hour = Duration::from_secs(3600);
+
+        assert_eq!(shards.units().unwrap().len(), 3);
+
+        let first = shards.claim("a", hour).unwrap().unwrap();
+        let second = shards.claim("b", Duration::ZERO).unwrap().unwrap();
+        assert_eq!(first.unit.id, "00000");
+        assert_eq!(second.unit.id, "00001");
+        assert_eq!(
+            second.unit.work,
+            Work::Queries {
+                queries: queries[2..4].to_vec()
+            }
+        );
+
+        first.complete().unwrap();
+        assert_eq!(shards.remaining().unwrap(), 2);
+
+        // The second lease has expired, so it can be taken over.
+        let taken = shards.claim("c", hour).unwrap().unwrap();
+        assert_eq!(taken.unit.id, "00001");
+        assert!(!second.renew().unwrap());
+        assert!(taken.renew().unwrap());
+
+        let third = shards.claim("a", hour).unwrap().unwrap();
+        assert_eq!(third.unit.id, "00002");
+        assert!(shards.claim("d", hour).unwrap().is_none());
+
+        third.release().unwrap();
+        assert_eq!(shards.claim("d", hour).unwrap().unwrap().unit.id, "00002");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn digest_prefixes() {
+        let dir = crate::fixtures::temp_dir("digest-shards").unwrap();
+        let shards = ShardDir::create_for_digest_prefixes(&dir, 5).unwrap();
+        let units = shards.units().unwrap();
+
+        assert_eq!(units.len(), 5);
+        assert!(!units[0]
+            .work
+            .matches_digest("ZHYT52YPEOCHJD5FZINSDYXGQZI22WJ4"));
+        assert_eq!(
+            units
+                .iter()
+                .filter(|unit| unit.work.matches_digest("ZHYT52YPEOCHJD5FZINSDYXGQZI22WJ4"))
+                .count(),
+            1
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
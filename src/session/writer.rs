@@ -0,0 +1,128 @@
+//! Compression of data files on the blocking thread pool.
+
+use crate::{store::gz::GzOptions, Item};
+use bytes::Bytes;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use tokio::sync::Semaphore;
+
+/// Writes gzipped data files without blocking the async executor.
+///
+/// At most `max_in_flight` files are compressed at once, so that a fast
+/// connection cannot queue up an unbounded amount of downloaded content.
+pub(crate) struct GzWriter {
+    permits: Semaphore,
+    options: GzOptions,
+    #[cfg(feature = "encryption")]
+    key: Option<crate::store::crypto::Key>,
+}
+
+impl GzWriter {
+    pub(crate) fn new(max_in_flight: usize) -> Self {
+        Self {
+            permits: Semaphore::new(max_in_flight.max(1)),
+            options: GzOptions::default(),
+            #[cfg(feature = "encryption")]
+            key: None,
+        }
+    }
+
+    pub(crate) fn default_max_in_flight() -> usize {
+        std::thread::available_parallelism().map_or(4, |count| count.get())
+    }
+
+    pub(crate) fn set_max_in_flight(&mut self, max_in_flight: usize) {
+        self.permits = Semaphore::new(max_in_flight.max(1));
+    }
+
+    pub(crate) fn set_options(&mut self, options: GzOptions) {
+        self.options = options;
+    }
+
+    #[cfg(feature = "encryption")]
+    pub(crate) fn set_key(&mut self, key: crate::store::crypto::Key) {
+        self.key = Some(key);
+    }
+
+    pub(crate) async fn write(
+        &self,
+        path: PathBuf,
+        item: Item,
+        content: Bytes,
+    ) -> std::io::Result<()> {
+        let _permit = self
+            .permits
+            .acquire()
+            .await
+            .map_err(std::io::Error::other)?;
+
+        let options = self.options;
+        #[cfg(feature = "encryption")]
+        let key = self.key.clone();
+
+        tokio::task::spawn_blocking(move || {
+            #[cfg(feature = "encryption")]
+            if let Some(key) = key {
+                return write_encrypted(&path, &item, &content, &options, &key);
+            }
+
+            write_gz(&path, &item, &content, &options)
+        })
+        .await
+        .map_err(std::io::Error::other)?
+    }
+}
+
+fn write_gz(path: &Path, item: &Item, content: &[u8], options: &GzOptions) -> std::io::Result<()> {
+    options.write(item, content, File::create(path)?)?;
+
+    Ok(())
+}
+
+#[cfg(feature = "encryption")]
+fn write_encrypted(
+    path: &Path,
+    item: &Item,
+    content: &[u8],
+    options: &GzOptions,
+    key: &crate::store::crypto::Key,
+) -> std::io::Result<()> {
+    let encrypted = crate::store::crypto::encrypt(key, &options.compress(item, content)?)
+        .map_err(std::io::Error::other)?;
+
+    std::fs::write(path, encrypted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GzWriter;
+    use bytes::Bytes;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    #[tokio::test]
+    async fn write_concurrently() {
+        let dir = crate::fixtures::temp_dir("gz-writer").unwrap();
+        let writer = GzWriter::new(2);
+
+        let items = crate::fixtures::items(8);
+        let writes = items.iter().enumerate().map(|(i, (item, _))| {
+            writer.write(
+                dir.join(format!("{}.gz", i)),
+                item.clone(),
+                Bytes::from(format!("content {}", i)),
+            )
+        });
+
+        for result in futures::future::join_all(writes).await {
+            result.unwrap();
+        }
+
+        let mut content = String::new();
+        GzDecoder::new(std::fs::File::open(dir.join("5.gz")).unwrap())
+            .read_to_string(&mut content)
+            .unwrap();
+
+        assert_eq!(content, "content 5");
+    }
+}
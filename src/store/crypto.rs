@@ -0,0 +1,142 @@
+//! Optional encryption at rest for stored items.
+//!
+//! Encrypted files start with a short magic header followed by a random
+//! XChaCha20-Poly1305 nonce and the ciphertext of the (gzipped) item.
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use std::io::{Cursor, Read};
+use std::path::Path;
+use std::str::FromStr;
+
+const MAGIC: &[u8] = b"WBRSENC1";
+const NONCE_LEN: usize = 24;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Invalid key")]
+    InvalidKey,
+    #[error("Encryption failed")]
+    Encryption,
+    #[error("Decryption failed")]
+    Decryption,
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+}
+
+/// A 256-bit key, written as 64 hexadecimal characters.
+#[derive(Clone)]
+pub struct Key([u8; 32]);
+
+impl Key {
+    pub fn new(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+
+    /// Generate a new random key.
+    pub fn generate() -> Self {
+        Self(XChaCha20Poly1305::generate_key(&mut OsRng).into())
+    }
+
+    /// Read a hex-encoded key from a file (surrounding whitespace is ignored).
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        std::fs::read_to_string(path)?.trim().parse()
+    }
+
+    pub fn to_hex(&self) -> String {
+        self.0.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(&self.0.into())
+    }
+}
+
+impl std::fmt::Debug for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Key(..)")
+    }
+}
+
+impl FromStr for Key {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.len() != 64 || !s.is_ascii() {
+            return Err(Error::InvalidKey);
+        }
+
+        let mut bytes = [0; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).map_err(|_| Error::InvalidKey)?;
+        }
+
+        Ok(Self(bytes))
+    }
+}
+
+/// Check whether the data starts with the encryption header.
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+pub fn encrypt(key: &Key, plaintext: &[u8]) -> Result<Vec<u8>, Error> {
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = key
+        .cipher()
+        .encrypt(&nonce, plaintext)
+        .map_err(|_| Error::Encryption)?;
+
+    let mut result = Vec::with_capacity(MAGIC.len() + NONCE_LEN + ciphertext.len());
+    result.extend_from_slice(MAGIC);
+    result.extend_from_slice(&nonce);
+    result.extend_from_slice(&ciphertext);
+
+    Ok(result)
+}
+
+pub fn decrypt(key: &Key, data: &[u8]) -> Result<Vec<u8>, Error> {
+    let rest = data.strip_prefix(MAGIC).ok_or(Error::Decryption)?;
+
+    if rest.len() < NONCE_LEN {
+        return Err(Error::Decryption);
+    }
+
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+    key.cipher()
+        .decrypt(XNonce::from_slice(nonce), ciphertext)
+        .map_err(|_| Error::Decryption)
+}
+
+/// Open a stored file, decrypting it if it is encrypted.
+///
+/// Unencrypted files are read as-is, so stores can be encrypted incrementally.
+pub fn open<P: AsRef<Path>>(path: P, key: &Key) -> std::io::Result<Box<dyn Read + Send>> {
+    let data = std::fs::read(path)?;
+
+    if is_encrypted(&data) {
+        let plaintext = decrypt(key, &data)
+            .map_err(|error| std::io::Error::new(std::io::ErrorKind::InvalidData, error))?;
+
+        Ok(Box::new(Cursor::new(plaintext)))
+    } else {
+        Ok(Box::new(Cursor::new(data)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decrypt, encrypt, is_encrypted, Key};
+
+    #[test]
+    fn round_trip() {
+        let key = Key::generate();
+        let encrypted = encrypt(&key, b"hello").unwrap();
+
+        assert!(is_encrypted(&encrypted));
+        assert_eq!(decrypt(&key, &encrypted).unwrap(), b"hello");
+        assert!(decrypt(&Key::generate(), &encrypted).is_err());
+        assert_eq!(key.to_hex().parse::<Key>().unwrap().to_hex(), key.to_hex());
+    }
+}
@@ -1,4 +1,7 @@
-use crate::digest::compute_digest_gz;
+use super::gz::GzOptions;
+use super::links::{rewrite_links, LinkTarget};
+use crate::digest::{compute_digest_gz, DigestReader};
+use crate::Item;
 use flate2::read::GzDecoder;
 use futures::{FutureExt, Stream, TryStreamExt};
 use lazy_static::lazy_static;
@@ -20,6 +23,12 @@ pub enum Error {
     ItemIOError { digest: String, error: io::Error },
     #[error("Unexpected error while computing digests")]
     DigestComputationError,
+    #[error("Digest mismatch: expected {expected}, found {actual}")]
+    DigestMismatch { expected: String, actual: String },
+    #[error("Invalid snapshot label: {0}")]
+    InvalidSnapshotLabel(String),
+    #[error("Snapshot already exists: {0:?}")]
+    SnapshotExists(PathBuf),
 }
 
 lazy_static! {
@@ -35,28 +44,125 @@ fn is_valid_char(c: char) -> bool {
     ('2'..='7').contains(&c) || c.is_ascii_uppercase()
 }
 
+/// A reader for the decompressed contents of a stored item.
+pub type ItemReader = BufReader<GzDecoder<Box<dyn Read + Send>>>;
+
 /// A content-addressable store for compressed Wayback Machine pages.
 pub struct Store {
     base: Box<Path>,
+    opener: Opener,
+    gz_options: GzOptions,
+}
+
+/// Opens item files, decrypting them if a key has been provided.
+#[derive(Clone, Default)]
+pub(super) struct Opener {
+    #[cfg(feature = "encryption")]
+    key: Option<super::crypto::Key>,
+}
+
+impl Opener {
+    fn open<P: AsRef<Path>>(&self, path: P) -> io::Result<Box<dyn Read + Send>> {
+        #[cfg(feature = "encryption")]
+        if let Some(key) = &self.key {
+            return super::crypto::open(path, key);
+        }
+
+        Ok(Box::new(File::open(path)?))
+    }
+
+    pub(super) fn open_item<P: AsRef<Path>>(&self, path: P) -> io::Result<ItemReader> {
+        Ok(BufReader::new(GzDecoder::new(self.open(path)?)))
+    }
 }
 
 impl Store {
     pub fn new<P: AsRef<Path>>(path: P) -> Self {
         Store {
             base: path.as_ref().to_path_buf().into_boxed_path(),
+            opener: Opener::default(),
+            gz_options: GzOptions::default(),
         }
     }
 
     pub fn create<P: AsRef<Path>>(base: P) -> Result<Self, std::io::Error> {
-        let path = base.as_ref();
+        let store = Self::new(base);
+        store.ensure_layout()?;
 
+        Ok(store)
+    }
+
+    pub fn base(&self) -> &Path {
+        &self.base
+    }
+
+    pub(super) fn opener(&self) -> &Opener {
+        &self.opener
+    }
+
+    /// Open items in the same way as another store (with its key, if any).
+    pub(super) fn with_opener(mut self, opener: Opener) -> Self {
+        self.opener = opener;
+        self
+    }
+
+    /// Create any missing prefix directories.
+    pub fn ensure_layout(&self) -> Result<(), std::io::Error> {
         for name in NAMES.iter() {
-            std::fs::create_dir_all(path.join(name))?;
+            std::fs::create_dir_all(self.base.join(name))?;
         }
 
-        Ok(Store {
-            base: path.to_path_buf().into_boxed_path(),
-        })
+        Ok(())
+    }
+
+    /// Compress and save the content for an item, creating its prefix directory
+    /// if necessary.
+    ///
+    /// Any existing file is replaced rather than modified in place, since it
+    /// may be hard-linked into a snapshot. The content is not checked against
+    /// the item's digest.
+    pub fn save(&self, item: &Item, content: &[u8]) -> Result<Box<Path>, Error> {
+        let location = self
+            .location(&item.digest)
+            .ok_or_else(|| Error::InvalidDigest(item.digest.clone()))?;
+
+        if let Some(parent) = location.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let compressed = self.gz_options.compress(item, content)?;
+
+        #[cfg(feature = "encryption")]
+        let compressed = match &self.opener.key {
+            Some(key) => super::crypto::encrypt(key, &compressed).map_err(io::Error::other)?,
+            None => compressed,
+        };
+
+        let temp = location.with_extension("gz.tmp");
+        std::fs::write(&temp, compressed)?;
+        std::fs::rename(&temp, &location)?;
+
+        Ok(location)
+    }
+
+    /// The options used to compress saved items.
+    pub fn gz_options(&self) -> &GzOptions {
+        &self.gz_options
+    }
+
+    /// Compress saved items with the given options.
+    pub fn with_gz_options(mut self, options: GzOptions) -> Self {
+        self.gz_options = options;
+        self
+    }
+
+    /// Decrypt items with the given key when reading them.
+    ///
+    /// Items that are not encrypted can still be read.
+    #[cfg(feature = "encryption")]
+    pub fn with_key(mut self, key: super::crypto::Key) -> Self {
+        self.opener.key = Some(key);
+        self
     }
 
     pub fn compute_digests(
@@ -64,10 +170,13 @@ impl Store {
         prefix: Option<&str>,
         n: usize,
     ) -> impl Stream<Item = Result<(String, String), Error>> {
+        let opener = self.opener.clone();
+
         futures::stream::iter(self.paths_for_prefix(prefix.unwrap_or("")))
-            .map_ok(|(expected, path)| {
-                tokio::spawn(async {
-                    let mut file = File::open(path)?;
+            .map_ok(move |(expected, path)| {
+                let opener = opener.clone();
+                tokio::spawn(async move {
+                    let mut file = opener.open(path)?;
                     match compute_digest_gz(&mut file) {
                         Ok(actual) => Ok((expected, actual)),
                         Err(error) => Err(Error::ItemIOError {
@@ -122,6 +231,10 @@ impl Store {
                 if Self::is_valid_prefix(prefix) {
                     let first = first_char.to_string();
                     match read_dir(self.base.join(&first)) {
+                        // Prefix directories are created lazily, so a missing one is empty.
+                        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                            Box::new(std::iter::empty())
+                        }
                         Err(error) => Self::emit_error(error),
                         Ok(files) => {
                             let p = prefix.to_string();
@@ -146,6 +259,7 @@ impl Store {
         }
     }
 
+    #[allow(clippy::type_complexity)]
     pub fn check_file_location<P: AsRef<Path>>(
         &self,
         candidate: P,
@@ -208,20 +322,13 @@ impl Store {
         self.location(digest).filter(|path| path.is_file())
     }
 
-    pub fn extract_reader(
-        &self,
-        digest: &str,
-    ) -> Option<Result<BufReader<GzDecoder<File>>, std::io::Error>> {
-        self.lookup(digest).map(|path| {
-            let file = File::open(path)?;
-
-            Ok(BufReader::new(GzDecoder::new(file)))
-        })
+    pub fn extract_reader(&self, digest: &str) -> Option<Result<ItemReader, std::io::Error>> {
+        self.lookup(digest).map(|path| self.opener.open_item(path))
     }
 
     pub fn extract(&self, digest: &str) -> Option<Result<String, std::io::Error>> {
         self.lookup(digest).map(|path| {
-            let file = File::open(path)?;
+            let file = self.opener.open(path)?;
             let mut buffer = String::new();
 
             GzDecoder::new(file).read_to_string(&mut buffer)?;
@@ -230,9 +337,19 @@ impl Store {
         })
     }
 
+    /// Extract the item with archive links rewritten to point to the given target.
+    pub fn extract_rewritten(
+        &self,
+        digest: &str,
+        target: &LinkTarget,
+    ) -> Option<Result<String, std::io::Error>> {
+        self.extract(digest)
+            .map(|result| result.map(|html| rewrite_links(&html, target).into_owned()))
+    }
+
     pub fn extract_bytes(&self, digest: &str) -> Option<Result<Vec<u8>, std::io::Error>> {
         self.lookup(digest).map(|path| {
-            let file = File::open(path)?;
+            let file = self.opener.open(path)?;
             let mut buffer = Vec::new();
 
             GzDecoder::new(file).read_to_end(&mut buffer)?;
@@ -241,6 +358,28 @@ impl Store {
         })
     }
 
+    /// Extract the item, checking that its contents match its digest.
+    pub fn extract_verified(&self, digest: &str) -> Option<Result<Vec<u8>, Error>> {
+        self.lookup(digest).map(|path| {
+            let file = self.opener.open(path)?;
+            let mut reader = DigestReader::new(GzDecoder::new(file));
+            let mut buffer = Vec::new();
+
+            reader.read_to_end(&mut buffer)?;
+
+            let actual = reader.digest();
+
+            if actual == digest {
+                Ok(buffer)
+            } else {
+                Err(Error::DigestMismatch {
+                    expected: digest.to_string(),
+                    actual,
+                })
+            }
+        })
+    }
+
     fn is_valid_digest(candidate: &str) -> bool {
         candidate.len() == 32 && candidate.chars().all(is_valid_char)
     }
@@ -339,6 +478,62 @@ mod tests {
         );
     }
 
+    #[tokio::test]
+    async fn compute_digests_fixture() {
+        let base = crate::fixtures::temp_dir("store").unwrap();
+        let contents = (0..20).map(crate::fixtures::content).collect::<Vec<_>>();
+        let mut expected = crate::fixtures::store_tree(&base, &contents).unwrap();
+        expected.sort();
+
+        let store = Store::new(&base);
+        let mut result = store
+            .compute_digests(None, 4)
+            .try_collect::<Vec<_>>()
+            .await
+            .unwrap();
+        result.sort();
+
+        assert_eq!(
+            result,
+            expected
+                .into_iter()
+                .map(|digest| (digest.clone(), digest))
+                .collect::<Vec<_>>()
+        );
+
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[test]
+    fn extract_verified() {
+        let store = Store::new("examples/wayback/store/items/");
+
+        assert!(store
+            .extract_verified("2G3EOT7X6IEQZXKSM3OJJDW6RBCHB7YE")
+            .unwrap()
+            .is_ok());
+        assert!(matches!(
+            store.extract_verified("5DECQVIU7Y3F276SIBAKKCRGDMVXJYFV"),
+            Some(Err(super::Error::DigestMismatch { .. }))
+        ));
+    }
+
+    #[test]
+    fn save_without_layout() {
+        let base = crate::fixtures::temp_dir("store-save").unwrap();
+        let store = Store::new(&base);
+        let (item, content) = crate::fixtures::items(1).remove(0);
+
+        store.save(&item, &content).unwrap();
+
+        assert_eq!(
+            store.extract_verified(&item.digest).unwrap().unwrap(),
+            content
+        );
+
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
     #[test]
     fn paths() {
         let store = Store::new("examples/wayback/store/items/");
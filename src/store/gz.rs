@@ -0,0 +1,212 @@
+//! Options for the gzip files that item content is stored in.
+
+use crate::Item;
+use flate2::{write::GzEncoder, Compression, GzBuilder};
+use std::io::{self, Write};
+
+/// The "unknown" operating system value, which is written in every header so
+/// that output doesn't depend on the platform.
+const UNKNOWN_OS: u8 = 255;
+
+/// MIME types whose content is already compressed.
+const COMPRESSED_MIME_TYPES: [&str; 8] = [
+    "application/gzip",
+    "application/x-gzip",
+    "application/zip",
+    "application/x-7z-compressed",
+    "application/x-bzip2",
+    "application/x-xz",
+    "application/zstd",
+    "application/pdf",
+];
+
+/// How item content is compressed when it is saved.
+///
+/// The defaults match the original behavior: the default compression level,
+/// the item's file name in the gzip header, and no modification time.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct GzOptions {
+    level: u32,
+    store_compressed_types: bool,
+    filename: bool,
+    mtime: bool,
+}
+
+impl Default for GzOptions {
+    fn default() -> Self {
+        Self {
+            level: Compression::default().level(),
+            store_compressed_types: false,
+            filename: true,
+            mtime: false,
+        }
+    }
+}
+
+impl GzOptions {
+    /// Options that produce byte-identical output for identical content.
+    ///
+    /// No file name (which depends on the MIME type) or modification time is
+    /// written, so two stores that contain the same content can be compared at
+    /// the level of the compressed files.
+    pub fn deterministic() -> Self {
+        Self::default().with_filename(false).with_mtime(false)
+    }
+
+    /// Use the given compression level (from 0 to 9).
+    pub fn with_level(mut self, level: u32) -> Self {
+        self.level = level.min(9);
+        self
+    }
+
+    /// Store content with already-compressed MIME types (images, audio, video,
+    /// and archives) without compressing it again.
+    pub fn with_store_compressed_types(mut self, store_compressed_types: bool) -> Self {
+        self.store_compressed_types = store_compressed_types;
+        self
+    }
+
+    /// Whether to include the item's file name in the gzip header.
+    pub fn with_filename(mut self, filename: bool) -> Self {
+        self.filename = filename;
+        self
+    }
+
+    /// Whether to use the capture time as the gzip modification time.
+    pub fn with_mtime(mut self, mtime: bool) -> Self {
+        self.mtime = mtime;
+        self
+    }
+
+    /// The compression level used for the given item.
+    pub fn compression_for(&self, item: &Item) -> Compression {
+        if self.store_compressed_types && is_compressed_type(&item.mime_type) {
+            Compression::none()
+        } else {
+            Compression::new(self.level)
+        }
+    }
+
+    /// Compress the content for an item.
+    pub fn compress(&self, item: &Item, content: &[u8]) -> io::Result<Vec<u8>> {
+        self.write(item, content, vec![])
+    }
+
+    /// Compress the content for an item into the given output.
+    pub fn write<W: Write>(&self, item: &Item, content: &[u8], output: W) -> io::Result<W> {
+        let mut gz = self.encoder(item, output);
+        gz.write_all(content)?;
+        gz.finish()
+    }
+
+    /// An encoder for writing an item's content to the given output as it
+    /// arrives.
+    pub fn encoder<W: Write>(&self, item: &Item, output: W) -> GzEncoder<W> {
+        let mut builder = GzBuilder::new().operating_system(UNKNOWN_OS);
+
+        if self.filename {
+            builder = builder.filename(item.make_filename());
+        }
+
+        if self.mtime {
+            let seconds = item.archived_at.and_utc().timestamp();
+            builder = builder.mtime(seconds.clamp(0, u32::MAX as i64) as u32);
+        }
+
+        builder.write(output, self.compression_for(item))
+    }
+}
+
+fn is_compressed_type(mime_type: &str) -> bool {
+    let mime_type = mime_type.trim().to_lowercase();
+
+    match mime_type.split_once('/') {
+        Some(("image", subtype)) => !matches!(subtype, "svg+xml" | "bmp" | "x-icon"),
+        Some(("audio" | "video", _)) => true,
+        _ => COMPRESSED_MIME_TYPES.contains(&mime_type.as_str()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GzOptions;
+    use crate::Item;
+    use chrono::NaiveDate;
+    use flate2::{read::GzDecoder, Compression};
+    use std::io::Read;
+
+    fn item(mime_type: &str) -> Item {
+        Item::new(
+            "https://example.com/a".to_string(),
+            NaiveDate::from_ymd_opt(2020, 1, 1)
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+                .unwrap(),
+            "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA".to_string(),
+            mime_type.to_string(),
+            0,
+            Some(200),
+        )
+    }
+
+    #[test]
+    fn compress() {
+        let options = GzOptions::default()
+            .with_level(9)
+            .with_store_compressed_types(true)
+            .with_filename(false)
+            .with_mtime(true);
+
+        assert_eq!(
+            options.compression_for(&item("image/png")),
+            Compression::none()
+        );
+        assert_eq!(options.compression_for(&item("image/svg+xml")).level(), 9);
+        assert_eq!(options.compression_for(&item("text/html")).level(), 9);
+
+        let compressed = options.compress(&item("text/html"), b"hello").unwrap();
+        let mut decoder = GzDecoder::new(&compressed[..]);
+        let mut content = String::new();
+        decoder.read_to_string(&mut content).unwrap();
+
+        assert_eq!(content, "hello");
+        assert_eq!(decoder.header().and_then(|header| header.filename()), None);
+        assert_eq!(
+            decoder.header().map(|header| header.mtime()),
+            Some(1577836800)
+        );
+    }
+
+    #[test]
+    fn deterministic() {
+        let options = GzOptions::deterministic();
+        let other = item("text/plain");
+
+        assert_eq!(
+            options.compress(&item("text/html"), b"hello").unwrap(),
+            options.compress(&other, b"hello").unwrap()
+        );
+        assert_ne!(
+            GzOptions::default()
+                .compress(&item("text/html"), b"hello")
+                .unwrap(),
+            GzOptions::default().compress(&other, b"hello").unwrap()
+        );
+    }
+
+    #[test]
+    fn streaming_encoder() {
+        use std::io::Write;
+
+        let options = GzOptions::default().with_level(1).with_mtime(true);
+        let mut encoder = options.encoder(&item("text/html"), vec![]);
+
+        for chunk in [&b"hel"[..], b"lo"] {
+            encoder.write_all(chunk).unwrap();
+        }
+
+        assert_eq!(
+            encoder.finish().unwrap(),
+            options.compress(&item("text/html"), b"hello").unwrap()
+        );
+    }
+}
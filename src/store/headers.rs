@@ -0,0 +1,61 @@
+//! A sidecar store for the original response headers of downloaded items.
+//!
+//! Headers are saved as JSON files named by digest, using the same prefix
+//! directory layout as the data store.
+
+use crate::downloader::OriginalHeaders;
+use std::fs::{create_dir_all, File};
+use std::io::{BufReader, BufWriter, ErrorKind};
+use std::path::{Path, PathBuf};
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("Invalid digest: {0}")]
+    InvalidDigest(String),
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("JSON error")]
+    Json(#[from] serde_json::Error),
+}
+
+pub struct HeaderStore {
+    base: PathBuf,
+}
+
+impl HeaderStore {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            base: path.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn location(&self, digest: &str) -> Result<PathBuf, Error> {
+        match digest.chars().next() {
+            Some(first) if first.is_ascii_alphanumeric() => Ok(self
+                .base
+                .join(first.to_string())
+                .join(format!("{}.json", digest))),
+            _ => Err(Error::InvalidDigest(digest.to_string())),
+        }
+    }
+
+    pub fn save(&self, digest: &str, headers: &OriginalHeaders) -> Result<(), Error> {
+        let path = self.location(digest)?;
+
+        if let Some(parent) = path.parent() {
+            create_dir_all(parent)?;
+        }
+
+        serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), headers)?;
+
+        Ok(())
+    }
+
+    pub fn load(&self, digest: &str) -> Result<Option<OriginalHeaders>, Error> {
+        match File::open(self.location(digest)?) {
+            Ok(file) => Ok(Some(serde_json::from_reader(BufReader::new(file))?)),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! Rewriting of Wayback Machine links in extracted HTML.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+lazy_static::lazy_static! {
+    static ref ARCHIVE_LINK_RE: regex::Regex = regex::Regex::new(
+        r#"(?:(?:https?:)?//web\.archive\.org)?/web/(\d{1,14})(?:[a-z]{2}_)?/(https?://[^"'\s<>()]+)"#,
+    )
+    .unwrap();
+}
+
+/// What archive links should point to after rewriting.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum LinkTarget {
+    /// The original live URL.
+    Live,
+    /// A replay server with the given base URL (links become `{base}/web/{timestamp}/{url}`).
+    Replay(String),
+    /// Relative paths to data files in the store, given a map from original
+    /// URLs to digests. Links to URLs that are not in the map point to the live
+    /// URL.
+    Store(HashMap<String, String>),
+}
+
+impl LinkTarget {
+    fn target(&self, timestamp: &str, url: &str) -> String {
+        match self {
+            LinkTarget::Live => url.to_string(),
+            LinkTarget::Replay(base) => {
+                format!("{}/web/{}/{}", base.trim_end_matches('/'), timestamp, url)
+            }
+            LinkTarget::Store(digests) => match digests
+                .get(url)
+                .and_then(|digest| digest.chars().next().map(|first| (first, digest)))
+            {
+                // Extracted items live one directory down, in their prefix directory.
+                Some((first, digest)) => format!("../{}/{}.gz", first, digest),
+                None => url.to_string(),
+            },
+        }
+    }
+}
+
+/// Rewrite all archive links in the given HTML.
+pub fn rewrite_links<'a>(html: &'a str, target: &LinkTarget) -> Cow<'a, str> {
+    ARCHIVE_LINK_RE.replace_all(html, |captures: &regex::Captures| {
+        target.target(&captures[1], &captures[2])
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{rewrite_links, LinkTarget};
+    use std::collections::HashMap;
+
+    const HTML: &str = r#"<a href="https://web.archive.org/web/20200101000000/https://example.com/a">a</a><img src="/web/20200102000000im_/http://example.com/b.png">"#;
+
+    #[test]
+    fn live() {
+        assert_eq!(
+            rewrite_links(HTML, &LinkTarget::Live),
+            r#"<a href="https://example.com/a">a</a><img src="http://example.com/b.png">"#
+        );
+    }
+
+    #[test]
+    fn replay_and_store() {
+        assert_eq!(
+            rewrite_links(
+                HTML,
+                &LinkTarget::Replay("http://localhost:8080/".to_string())
+            ),
+            r#"<a href="http://localhost:8080/web/20200101000000/https://example.com/a">a</a><img src="http://localhost:8080/web/20200102000000/http://example.com/b.png">"#
+        );
+
+        let digests = HashMap::from([(
+            "https://example.com/a".to_string(),
+            "2G3EOT7X6IEQZXKSM3OJJDW6RBCHB7YE".to_string(),
+        )]);
+
+        assert_eq!(
+            rewrite_links(HTML, &LinkTarget::Store(digests)),
+            r#"<a href="../2/2G3EOT7X6IEQZXKSM3OJJDW6RBCHB7YE.gz">a</a><img src="http://example.com/b.png">"#
+        );
+    }
+}
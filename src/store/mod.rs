@@ -1 +1,8 @@
+#[cfg(feature = "encryption")]
+pub mod crypto;
 pub mod data;
+pub mod gz;
+pub mod headers;
+pub mod links;
+pub mod reader;
+pub mod snapshot;
@@ -0,0 +1,132 @@
+//! Asynchronous reading of stored items.
+//!
+//! Items are decompressed (and decrypted, if the store has a key) on a
+//! blocking thread, and the content is passed to the reader in chunks, so
+//! async tasks never wait on file reads or decompression.
+
+use super::data::Store;
+use bytes::Bytes;
+use std::io::{self, Read};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, ReadBuf};
+use tokio::sync::mpsc::{channel, Receiver};
+
+const CHUNK_SIZE: usize = 64 * 1024;
+/// The number of decompressed chunks that can be waiting to be read.
+const CHANNEL_CAPACITY: usize = 4;
+
+/// An asynchronous reader for the decompressed contents of a stored item.
+pub struct AsyncItemReader {
+    chunks: Receiver<io::Result<Bytes>>,
+    current: Bytes,
+}
+
+impl AsyncRead for AsyncItemReader {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        while self.current.is_empty() {
+            match ready!(self.chunks.poll_recv(cx)) {
+                Some(Ok(chunk)) => self.current = chunk,
+                Some(Err(error)) => return Poll::Ready(Err(error)),
+                // The sender is dropped once the item has been read.
+                None => return Poll::Ready(Ok(())),
+            }
+        }
+
+        let len = self.current.len().min(buf.remaining());
+        buf.put_slice(&self.current.split_to(len));
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Store {
+    /// Open an item for asynchronous reading, if it is in the store.
+    ///
+    /// Errors opening or decompressing the item are returned by the reader.
+    /// This must be called from within a Tokio runtime.
+    pub fn extract_async_reader(&self, digest: &str) -> Option<AsyncItemReader> {
+        let path = self.lookup(digest)?;
+        let opener = self.opener().clone();
+        let (tx, rx) = channel(CHANNEL_CAPACITY);
+
+        tokio::task::spawn_blocking(move || {
+            let mut reader = match opener.open_item(path) {
+                Ok(reader) => reader,
+                Err(error) => {
+                    let _ = tx.blocking_send(Err(error));
+                    return;
+                }
+            };
+
+            loop {
+                let mut buffer = vec![0; CHUNK_SIZE];
+
+                let chunk = match reader.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(len) => {
+                        buffer.truncate(len);
+                        Ok(Bytes::from(buffer))
+                    }
+                    Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(error) => Err(error),
+                };
+                let failed = chunk.is_err();
+
+                // Stop if the reader has been dropped.
+                if tx.blocking_send(chunk).is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        Some(AsyncItemReader {
+            chunks: rx,
+            current: Bytes::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::data::Store;
+    use std::pin::Pin;
+    use tokio::io::{AsyncRead, ReadBuf};
+
+    async fn read_to_end<R: AsyncRead + Unpin>(mut reader: R) -> std::io::Result<Vec<u8>> {
+        let mut content = vec![];
+        let mut buffer = [0; 1000];
+
+        loop {
+            let mut buf = ReadBuf::new(&mut buffer);
+            std::future::poll_fn(|cx| Pin::new(&mut reader).poll_read(cx, &mut buf)).await?;
+
+            if buf.filled().is_empty() {
+                return Ok(content);
+            }
+            content.extend_from_slice(buf.filled());
+        }
+    }
+
+    #[tokio::test]
+    async fn extract_async_reader() {
+        let base = crate::fixtures::temp_dir("async-reader").unwrap();
+        let store = Store::create(&base).unwrap();
+        let (item, _) = &crate::fixtures::items(1)[0];
+        let content = (0..200_000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+
+        store.save(item, &content).unwrap();
+
+        let reader = store.extract_async_reader(&item.digest).unwrap();
+        assert_eq!(read_to_end(reader).await.unwrap(), content);
+        assert!(store
+            .extract_async_reader("2222222222222222222222222222222A")
+            .is_none());
+
+        std::fs::remove_dir_all(base).unwrap();
+    }
+}
@@ -0,0 +1,314 @@
+//! Point-in-time copies of a store.
+//!
+//! Snapshots are kept in a directory next to the store (`items.snapshots` for
+//! a store at `items`), each named with its creation time and label. Files are
+//! reflinked where the filesystem supports it and hard-linked otherwise, so a
+//! snapshot takes almost no space. Hard links are safe because the store
+//! always replaces files instead of modifying them in place.
+
+use super::data::{Error, Opener, Store};
+use crate::util::{parse_timestamp, to_timestamp};
+use chrono::{NaiveDateTime, Utc};
+use std::fs::{create_dir_all, read_dir, remove_dir_all, rename};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A snapshot of a store.
+#[derive(Clone)]
+pub struct Snapshot {
+    pub label: String,
+    pub created: NaiveDateTime,
+    pub path: PathBuf,
+    /// Opens items with the key of the store the snapshot was taken from.
+    opener: Opener,
+}
+
+impl Snapshot {
+    fn parse(path: PathBuf, opener: &Opener) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        let (timestamp, label) = name.split_once('-')?;
+
+        Some(Self {
+            label: label.to_string(),
+            created: parse_timestamp(timestamp)?,
+            path,
+            opener: opener.clone(),
+        })
+    }
+
+    /// Open the snapshot as a store (for reading or restoring from).
+    ///
+    /// Items are decrypted with the key of the store the snapshot was taken
+    /// from, if it has one.
+    pub fn store(&self) -> Store {
+        Store::new(&self.path).with_opener(self.opener.clone())
+    }
+}
+
+impl std::fmt::Debug for Snapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Snapshot")
+            .field("label", &self.label)
+            .field("created", &self.created)
+            .field("path", &self.path)
+            .finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for Snapshot {
+    fn eq(&self, other: &Self) -> bool {
+        self.label == other.label && self.created == other.created && self.path == other.path
+    }
+}
+
+impl Eq for Snapshot {}
+
+fn is_valid_label(label: &str) -> bool {
+    !label.is_empty()
+        && !label.starts_with('.')
+        && label
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+}
+
+impl Store {
+    /// The directory that snapshots of this store are kept in.
+    pub fn snapshot_dir(&self) -> PathBuf {
+        let mut name = self.base().file_name().unwrap_or_default().to_os_string();
+        name.push(".snapshots");
+
+        self.base().with_file_name(name)
+    }
+
+    /// Create a snapshot of the store's current contents.
+    ///
+    /// Labels may only contain ASCII letters, digits, `-`, `_`, and `.`.
+    pub fn snapshot(&self, label: &str) -> Result<Snapshot, Error> {
+        if !is_valid_label(label) {
+            return Err(Error::InvalidSnapshotLabel(label.to_string()));
+        }
+
+        let created = Utc::now().naive_utc();
+        let name = format!("{}-{}", to_timestamp(&created), label);
+        let path = self.snapshot_dir().join(&name);
+
+        if path.exists() {
+            return Err(Error::SnapshotExists(path));
+        }
+
+        // Snapshots are built under a hidden name so that an interrupted
+        // snapshot is never listed.
+        let temp = self.snapshot_dir().join(format!(".{}", name));
+        let snapshot = Store::create(&temp)?;
+        let mut linker = Linker::default();
+
+        for result in self.paths() {
+            let (digest, source) = result?;
+            let target = snapshot
+                .location(&digest)
+                .ok_or(Error::InvalidDigest(digest))?;
+
+            linker.link(&source, &target)?;
+        }
+
+        rename(&temp, &path)?;
+        log::info!(
+            "Created snapshot {:?} ({} reflinked, {} hard-linked)",
+            path,
+            linker.reflinked,
+            linker.hard_linked
+        );
+
+        Ok(Snapshot {
+            label: label.to_string(),
+            created: parse_timestamp(&to_timestamp(&created)).unwrap_or(created),
+            path,
+            opener: self.opener().clone(),
+        })
+    }
+
+    /// All snapshots of this store, oldest first.
+    pub fn snapshots(&self) -> Result<Vec<Snapshot>, Error> {
+        let entries = match read_dir(self.snapshot_dir()) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(error) => return Err(error.into()),
+        };
+
+        let mut snapshots = vec![];
+
+        for entry in entries {
+            let entry = entry?;
+
+            if entry.file_type()?.is_dir() && !entry.file_name().to_string_lossy().starts_with('.')
+            {
+                snapshots.extend(Snapshot::parse(entry.path(), self.opener()));
+            }
+        }
+
+        snapshots.sort_by(|a, b| a.created.cmp(&b.created).then(a.label.cmp(&b.label)));
+
+        Ok(snapshots)
+    }
+
+    /// Remove all but the `keep` most recent snapshots, returning the ones
+    /// that were removed.
+    pub fn prune_snapshots(&self, keep: usize) -> Result<Vec<Snapshot>, Error> {
+        let mut snapshots = self.snapshots()?;
+        let removed = snapshots
+            .drain(..snapshots.len().saturating_sub(keep))
+            .collect::<Vec<_>>();
+
+        for snapshot in &removed {
+            remove_dir_all(&snapshot.path)?;
+        }
+
+        Ok(removed)
+    }
+}
+
+/// Links files, falling back to hard links once reflinks fail.
+#[derive(Default)]
+struct Linker {
+    reflinks_unsupported: bool,
+    reflinked: usize,
+    hard_linked: usize,
+}
+
+impl Linker {
+    fn link(&mut self, source: &Path, target: &Path) -> io::Result<()> {
+        if !self.reflinks_unsupported {
+            match reflink(source, target) {
+                Ok(()) => {
+                    self.reflinked += 1;
+                    return Ok(());
+                }
+                Err(error) => {
+                    log::debug!("Reflinks unavailable, using hard links: {:?}", error);
+                    self.reflinks_unsupported = true;
+                }
+            }
+        }
+
+        if let Some(parent) = target.parent() {
+            create_dir_all(parent)?;
+        }
+
+        std::fs::hard_link(source, target)?;
+        self.hard_linked += 1;
+
+        Ok(())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn reflink(source: &Path, target: &Path) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let source_file = std::fs::File::open(source)?;
+    let target_file = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(target)?;
+
+    // Safety: both file descriptors are open for the duration of the call.
+    let result = unsafe {
+        libc::ioctl(
+            target_file.as_raw_fd(),
+            libc::FICLONE,
+            source_file.as_raw_fd(),
+        )
+    };
+
+    if result == -1 {
+        let error = io::Error::last_os_error();
+        drop(target_file);
+        std::fs::remove_file(target)?;
+
+        Err(error)
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn reflink(_source: &Path, _target: &Path) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::data::Store;
+
+    #[test]
+    fn snapshot_and_prune() {
+        let base = crate::fixtures::temp_dir("snapshot").unwrap();
+        let store = Store::create(base.join("items")).unwrap();
+        let items = crate::fixtures::items(3);
+
+        for (item, content) in &items[0..2] {
+            store.save(item, content).unwrap();
+        }
+
+        let first = store.snapshot("before-import").unwrap();
+
+        // Later changes to the store don't affect the snapshot.
+        let (item, content) = &items[2];
+        store.save(item, content).unwrap();
+        store.save(&items[0].0, b"overwritten").unwrap();
+
+        assert_eq!(first.label, "before-import");
+        assert_eq!(first.store().paths().count(), 2);
+        assert_eq!(
+            first
+                .store()
+                .extract_verified(&items[0].0.digest)
+                .unwrap()
+                .unwrap(),
+            items[0].1
+        );
+        assert!(store.snapshot("../escape").is_err());
+
+        let second = store.snapshot("after-import").unwrap();
+        assert_eq!(second.store().paths().count(), 3);
+
+        let snapshots = store.snapshots().unwrap();
+        assert_eq!(snapshots.len(), 2);
+        assert!(snapshots.contains(&first));
+
+        let removed = store.prune_snapshots(1).unwrap();
+        assert_eq!(removed.len(), 1);
+        assert_eq!(store.snapshots().unwrap().len(), 1);
+        assert!(!removed[0].path.exists());
+
+        std::fs::remove_dir_all(base).unwrap();
+    }
+
+    #[cfg(feature = "encryption")]
+    #[test]
+    fn encrypted_snapshot() {
+        let base = crate::fixtures::temp_dir("encrypted-snapshot").unwrap();
+        let store = Store::create(base.join("items"))
+            .unwrap()
+            .with_key(crate::store::crypto::Key::generate());
+        let (item, content) = &crate::fixtures::items(1)[0];
+
+        store.save(item, content).unwrap();
+
+        let snapshot = store.snapshot("encrypted").unwrap();
+        let listed = &store.snapshots().unwrap()[0];
+
+        for snapshot in [&snapshot, listed] {
+            assert_eq!(
+                snapshot
+                    .store()
+                    .extract_verified(&item.digest)
+                    .unwrap()
+                    .unwrap(),
+                *content
+            );
+        }
+
+        std::fs::remove_dir_all(base).unwrap();
+    }
+}
@@ -0,0 +1,141 @@
+//! Classification of HTTP client errors for retry decisions.
+use std::error::Error;
+use std::io;
+
+/// The cause of an HTTP client error.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum ErrorClass {
+    /// Name resolution failed.
+    Dns,
+    /// The server's certificate was rejected (retrying will not help).
+    Certificate,
+    /// The TLS handshake failed for some other reason.
+    Tls,
+    /// Establishing the connection timed out.
+    ConnectTimeout,
+    /// The connection could not be established.
+    Connect,
+    /// Reading the response body timed out.
+    BodyTimeout,
+    /// The request timed out.
+    Timeout,
+    /// Any other error.
+    Other,
+}
+
+impl ErrorClass {
+    /// Classify a `reqwest` error by its kind and the types in its source
+    /// chain.
+    ///
+    /// Connection errors caused by an operating system error are connection
+    /// failures, while other I/O errors come from the resolver. Connection
+    /// errors without an underlying I/O error come from the TLS handshake
+    /// (certificate errors are only distinguished for the OpenSSL backend).
+    pub fn of(error: &reqwest::Error) -> ErrorClass {
+        if error.is_connect() {
+            match source_io_error(error) {
+                _ if error.is_timeout() => ErrorClass::ConnectTimeout,
+                Some(io_error) if io_error.kind() == io::ErrorKind::TimedOut => {
+                    ErrorClass::ConnectTimeout
+                }
+                Some(io_error) if io_error.raw_os_error().is_some() => ErrorClass::Connect,
+                Some(_) => ErrorClass::Dns,
+                None if is_certificate_error(error) => ErrorClass::Certificate,
+                None => ErrorClass::Tls,
+            }
+        } else if error.is_timeout() && (error.is_body() || error.is_decode()) {
+            ErrorClass::BodyTimeout
+        } else if error.is_timeout() {
+            ErrorClass::Timeout
+        } else {
+            ErrorClass::Other
+        }
+    }
+}
+
+fn sources(error: &reqwest::Error) -> impl Iterator<Item = &(dyn Error + 'static)> {
+    std::iter::successors(error.source(), |&source| source.source())
+}
+
+fn source_io_error(error: &reqwest::Error) -> Option<&io::Error> {
+    sources(error).find_map(|source| source.downcast_ref::<io::Error>())
+}
+
+#[cfg(not(any(target_os = "windows", target_vendor = "apple")))]
+fn is_certificate_error(error: &reqwest::Error) -> bool {
+    const ERR_LIB_SSL: i32 = 20;
+    const SSL_R_CERTIFICATE_VERIFY_FAILED: i32 = 134;
+
+    sources(error)
+        .filter_map(|source| {
+            source
+                .downcast_ref::<openssl::error::ErrorStack>()
+                .or_else(|| {
+                    source
+                        .downcast_ref::<openssl::ssl::Error>()
+                        .and_then(|error| error.ssl_error())
+                })
+        })
+        .flat_map(|stack| stack.errors())
+        .any(|error| {
+            error.library_code() == ERR_LIB_SSL
+                && error.reason_code() == SSL_R_CERTIFICATE_VERIFY_FAILED
+        })
+}
+
+#[cfg(any(target_os = "windows", target_vendor = "apple"))]
+fn is_certificate_error(_error: &reqwest::Error) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ErrorClass;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    #[tokio::test]
+    #[ignore = "requires a DNS resolver"]
+    async fn dns_error() {
+        let error = reqwest::get("http://wayback-rs.invalid/")
+            .await
+            .unwrap_err();
+
+        assert_eq!(ErrorClass::of(&error), ErrorClass::Dns);
+    }
+
+    #[tokio::test]
+    async fn connect_error() {
+        // Bind a port and release it, so that nothing is listening on it.
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let error = reqwest::get(format!("http://127.0.0.1:{}/", port))
+            .await
+            .unwrap_err();
+
+        assert_eq!(ErrorClass::of(&error), ErrorClass::Connect);
+    }
+
+    #[tokio::test]
+    async fn tls_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // A server that doesn't speak TLS.
+        std::thread::spawn(move || {
+            if let Ok((mut stream, _)) = listener.accept() {
+                let _ = stream.read(&mut [0; 1024]);
+                let _ = stream.write_all(b"HTTP/1.1 400 Bad Request\r\n\r\n");
+            }
+        });
+
+        let error = reqwest::get(format!("https://127.0.0.1:{}/", port))
+            .await
+            .unwrap_err();
+
+        assert_eq!(ErrorClass::of(&error), ErrorClass::Tls);
+    }
+}
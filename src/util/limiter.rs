@@ -0,0 +1,150 @@
+//! A process-wide request budget that can be shared by several clients.
+//!
+//! Each surface (CDX searches or content downloads) has its own rate and
+//! burst size. Requests are scheduled with a generic cell rate algorithm, and
+//! waiting tasks are served in the order they arrived, so one busy task can't
+//! starve the others.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+/// A request rate that is not a positive, finite number of requests per
+/// second.
+#[derive(thiserror::Error, Clone, Copy, Debug, PartialEq)]
+#[error("Invalid request rate: {0}")]
+pub struct InvalidRate(pub f64);
+
+/// A group of Wayback Machine services that share a request budget.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Surface {
+    Cdx,
+    Content,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    interval: Duration,
+    burst: u32,
+    /// The theoretical arrival time of the next request.
+    next: Option<Instant>,
+}
+
+impl Bucket {
+    fn new(per_second: f64, burst: u32) -> Result<Self, InvalidRate> {
+        let interval = Some(per_second)
+            .filter(|per_second| per_second.is_finite() && *per_second > 0.0)
+            .and_then(|per_second| Duration::try_from_secs_f64(1.0 / per_second).ok())
+            .ok_or(InvalidRate(per_second))?;
+
+        Ok(Self {
+            interval,
+            burst: burst.max(1),
+            next: None,
+        })
+    }
+
+    /// Reserve a slot for a request, returning how long to wait for it.
+    fn reserve(&mut self, now: Instant) -> Duration {
+        let next = self.next.map_or(now, |next| next.max(now));
+        let allowed = next
+            .checked_sub(self.interval.saturating_mul(self.burst - 1))
+            .map_or(now, |allowed| allowed.max(now));
+
+        self.next = Some(next + self.interval);
+        allowed - now
+    }
+}
+
+/// A handle to a shared request budget.
+///
+/// Clones share the same budget, so a single limiter can be attached to an
+/// [`IndexClient`](crate::cdx::IndexClient) and any number of
+/// [`Downloader`](crate::Downloader) clones.
+#[derive(Clone, Debug, Default)]
+pub struct RateLimiter {
+    cdx: Option<Arc<Mutex<Bucket>>>,
+    content: Option<Arc<Mutex<Bucket>>>,
+}
+
+impl RateLimiter {
+    /// A limiter with no limits on any surface.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allow the given number of requests per second on a surface, with
+    /// bursts of up to `burst` requests.
+    ///
+    /// The rate must be positive and finite.
+    pub fn with_rate(
+        mut self,
+        surface: Surface,
+        per_second: f64,
+        burst: u32,
+    ) -> Result<Self, InvalidRate> {
+        let bucket = Some(Arc::new(Mutex::new(Bucket::new(per_second, burst)?)));
+
+        match surface {
+            Surface::Cdx => self.cdx = bucket,
+            Surface::Content => self.content = bucket,
+        }
+        Ok(self)
+    }
+
+    /// Wait until a request on the surface is allowed.
+    pub async fn acquire(&self, surface: Surface) {
+        let bucket = match surface {
+            Surface::Cdx => &self.cdx,
+            Surface::Content => &self.content,
+        };
+
+        if let Some(bucket) = bucket {
+            // The lock is held while waiting, so that tasks are served in the
+            // order they arrived.
+            let mut bucket = bucket.lock().await;
+            let wait = bucket.reserve(Instant::now());
+
+            if !wait.is_zero() {
+                async_std::task::sleep(wait).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bucket, InvalidRate, RateLimiter, Surface};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn bursts_then_paces() {
+        let now = Instant::now();
+        let mut bucket = Bucket::new(2.0, 3).unwrap();
+
+        for _ in 0..3 {
+            assert_eq!(bucket.reserve(now), Duration::ZERO);
+        }
+        assert_eq!(bucket.reserve(now), Duration::from_millis(500));
+        assert_eq!(bucket.reserve(now), Duration::from_millis(1000));
+
+        // After a quiet period the full burst is available again.
+        let later = now + Duration::from_secs(10);
+        for _ in 0..3 {
+            assert_eq!(bucket.reserve(later), Duration::ZERO);
+        }
+    }
+
+    #[test]
+    fn invalid_rates() {
+        for rate in [0.0, -1.0, f64::NAN, f64::INFINITY, 1e-320] {
+            assert!(matches!(
+                RateLimiter::new().with_rate(Surface::Cdx, rate, 1),
+                Err(InvalidRate(_))
+            ));
+        }
+        assert!(RateLimiter::new()
+            .with_rate(Surface::Content, 0.5, 1)
+            .is_ok());
+    }
+}
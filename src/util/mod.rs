@@ -1,10 +1,29 @@
 use chrono::naive::NaiveDateTime;
 
+mod cancel;
+mod classify;
+mod limiter;
+mod rate_limit;
 mod retries;
-pub use retries::{retry_future, Retryable};
+mod throttle;
+mod warm_up;
+pub use cancel::CancellationToken;
+pub use classify::ErrorClass;
+pub use limiter::{InvalidRate, RateLimiter, Surface};
+pub use rate_limit::{parse_retry_after, RateLimit};
+pub use retries::{retry_future, retry_future_with_stats, RetryConfig, RetryStats, Retryable};
+pub use throttle::AdaptiveThrottle;
+pub(crate) use warm_up::{open_connections, PinnedHosts};
 
 const DATE_FMT: &str = "%Y%m%d%H%M%S";
 
+/// The user agent sent by default by the CDX and content clients.
+pub const DEFAULT_USER_AGENT: &str = concat!(
+    "wayback-rs/",
+    env!("CARGO_PKG_VERSION"),
+    " (+https://github.com/travisbrown/wayback-rs)"
+);
+
 /// Parse a 14-digit Wayback Machine timestamp into a date-time value.
 pub fn parse_timestamp(input: &str) -> Option<NaiveDateTime> {
     NaiveDateTime::parse_from_str(input, DATE_FMT).ok()
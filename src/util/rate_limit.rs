@@ -0,0 +1,114 @@
+//! Detection of rate-limited responses.
+
+use chrono::{DateTime, Utc};
+use reqwest::{
+    header::{HeaderMap, RETRY_AFTER},
+    Response, StatusCode,
+};
+use std::time::Duration;
+
+/// The header the Wayback Machine adds to rate-limited responses.
+const RATE_LIMIT_HEADER: &str = "x-rl";
+/// The delay used when the server doesn't say how long to wait.
+const DEFAULT_DELAY: Duration = Duration::from_secs(30);
+/// Server-provided delays are capped at this value.
+const MAX_DELAY: Duration = Duration::from_secs(600);
+
+/// A response indicating that requests should be slowed down.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RateLimit {
+    pub status: StatusCode,
+    /// The delay requested in the `Retry-After` header, if any.
+    pub retry_after: Option<Duration>,
+}
+
+impl RateLimit {
+    /// Check a response for rate limiting.
+    ///
+    /// Responses are rate-limited if they have a 429 or 503 status, or any other
+    /// unsuccessful status along with the Wayback Machine's `x-rl` header.
+    pub fn of(response: &Response) -> Option<Self> {
+        Self::from_parts(response.status(), response.headers(), Utc::now())
+    }
+
+    pub fn from_parts(status: StatusCode, headers: &HeaderMap, now: DateTime<Utc>) -> Option<Self> {
+        let limited = status == StatusCode::TOO_MANY_REQUESTS
+            || status == StatusCode::SERVICE_UNAVAILABLE
+            || (!status.is_success() && headers.contains_key(RATE_LIMIT_HEADER));
+
+        limited.then(|| Self {
+            status,
+            retry_after: headers
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| parse_retry_after(value, now)),
+        })
+    }
+
+    /// How long to wait before retrying.
+    pub fn delay(&self) -> Duration {
+        self.retry_after.unwrap_or(DEFAULT_DELAY).min(MAX_DELAY)
+    }
+}
+
+/// Parse a `Retry-After` value, which may be a number of seconds or an HTTP
+/// date.
+pub fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+
+    match value.parse::<u64>() {
+        Ok(seconds) => Some(Duration::from_secs(seconds)),
+        Err(_) => {
+            let date = DateTime::parse_from_rfc2822(value).ok()?;
+
+            Some(
+                (date.with_timezone(&Utc) - now)
+                    .to_std()
+                    .unwrap_or(Duration::ZERO),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_retry_after, RateLimit};
+    use chrono::{TimeZone, Utc};
+    use reqwest::{header::HeaderMap, StatusCode};
+    use std::time::Duration;
+
+    #[test]
+    fn rate_limits() {
+        let now = Utc.with_ymd_and_hms(2015, 10, 21, 7, 27, 0).unwrap();
+        let mut headers = HeaderMap::new();
+
+        assert_eq!(
+            RateLimit::from_parts(StatusCode::NOT_FOUND, &headers, now),
+            None
+        );
+
+        headers.insert("retry-after", "120".parse().unwrap());
+        let limit = RateLimit::from_parts(StatusCode::TOO_MANY_REQUESTS, &headers, now).unwrap();
+        assert_eq!(limit.delay(), Duration::from_secs(120));
+
+        headers.insert("retry-after", "86400".parse().unwrap());
+        let limit = RateLimit::from_parts(StatusCode::SERVICE_UNAVAILABLE, &headers, now).unwrap();
+        assert_eq!(limit.delay(), Duration::from_secs(600));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-rl", "1".parse().unwrap());
+        let limit = RateLimit::from_parts(StatusCode::FORBIDDEN, &headers, now).unwrap();
+        assert_eq!(limit.delay(), Duration::from_secs(30));
+        assert_eq!(RateLimit::from_parts(StatusCode::OK, &headers, now), None);
+
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT", now),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+}
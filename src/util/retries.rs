@@ -5,8 +5,11 @@ use futures::{
     Future,
 };
 use log::{log, Level};
+use std::collections::hash_map::RandomState;
 use std::fmt::Debug;
+use std::hash::{BuildHasher, Hasher};
 use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tryhard::{
     backoff_strategies::BackoffStrategy, OnRetry, RetryFuture, RetryFutureConfig, RetryPolicy,
@@ -19,7 +22,184 @@ where
     Fut: Future<Output = Result<T, E>>,
     E: Retryable,
 {
-    tryhard::retry_fn(f).with_config(E::retry_config())
+    RetryConfig::for_error::<E>().retry(f)
+}
+
+/// Execute a future with retries, returning the result along with statistics
+/// about the attempts made.
+///
+/// Statistics are returned for both successes and failures.
+pub async fn retry_future_with_stats<F, Fut, T, E>(f: F) -> (Result<T, E>, RetryStats)
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: Retryable + Debug,
+{
+    RetryConfig::for_error::<E>().retry_with_stats(f).await
+}
+
+/// Settings for retrying failed operations, overriding an error type's
+/// defaults.
+///
+/// The maximum delay and jitter only apply to the exponential backoff, so
+/// delays chosen for specific errors (such as a server's `Retry-After`) are
+/// respected.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryConfig {
+    pub max_retries: u32,
+    pub initial_delay: Duration,
+    pub max_delay: Option<Duration>,
+    /// The fraction (between 0 and 1) by which each backoff delay may be
+    /// randomly shortened.
+    pub jitter: f64,
+}
+
+impl RetryConfig {
+    /// The default settings for an error type.
+    pub fn for_error<E: Retryable>() -> Self {
+        Self {
+            max_retries: E::max_retries(),
+            initial_delay: E::default_initial_delay(),
+            max_delay: None,
+            jitter: 0.0,
+        }
+    }
+
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    pub fn with_initial_delay(mut self, delay: Duration) -> Self {
+        self.initial_delay = delay;
+        self
+    }
+
+    pub fn with_max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = Some(delay);
+        self
+    }
+
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    fn backoff<E: Retryable>(&self) -> ErrorBackoff<E> {
+        let delay = self.max_delay.map_or(self.initial_delay, |max_delay| {
+            self.initial_delay.min(max_delay)
+        });
+
+        ErrorBackoff {
+            delay,
+            max_delay: self.max_delay,
+            jitter: self.jitter,
+            _error: PhantomData,
+        }
+    }
+
+    /// The delay before the given retry (counting from zero) after an error,
+    /// or an empty value if the error shouldn't be retried.
+    pub(crate) fn delay_for<E: Retryable>(&self, error: &E, retries: u32) -> Option<Duration> {
+        if retries >= self.max_retries {
+            return None;
+        }
+
+        let mut backoff = self.backoff::<E>();
+        for _ in 0..retries {
+            backoff.next_delay();
+        }
+
+        match backoff.delay(retries, error) {
+            RetryPolicy::Delay(delay) => Some(delay),
+            RetryPolicy::Break => None,
+        }
+    }
+
+    /// Execute a future with retries using these settings.
+    pub fn retry<F, Fut, T, E>(&self, f: F) -> RetryFuture<F, Fut, ErrorBackoff<E>, LogOnRetry>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        E: Retryable,
+    {
+        tryhard::retry_fn(f).with_config(
+            RetryFutureConfig::new(self.max_retries)
+                .on_retry(LogOnRetry {
+                    level: E::log_level(),
+                })
+                .custom_backoff(self.backoff()),
+        )
+    }
+
+    /// Execute a future with retries using these settings, returning the
+    /// result along with statistics about the attempts made.
+    pub async fn retry_with_stats<F, Fut, T, E>(&self, f: F) -> (Result<T, E>, RetryStats)
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        E: Retryable + Debug,
+    {
+        let stats = Arc::new(Mutex::new(RetryStats::default()));
+        let config = RetryFutureConfig::new(self.max_retries)
+            .on_retry(StatsOnRetry {
+                log: LogOnRetry {
+                    level: E::log_level(),
+                },
+                stats: stats.clone(),
+            })
+            .custom_backoff(self.backoff());
+
+        let result = tryhard::retry_fn(f).with_config(config).await;
+
+        let mut stats = std::mem::take(&mut *stats.lock().unwrap());
+        stats.attempts = stats.per_attempt_errors.len() as u32 + u32::from(result.is_ok());
+
+        (result, stats)
+    }
+}
+
+/// Statistics for a retried operation.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RetryStats {
+    /// The number of attempts, including the first.
+    pub attempts: u32,
+    /// The total time spent waiting between attempts.
+    pub total_delay: Duration,
+    /// The error for each failed attempt, in order.
+    pub per_attempt_errors: Vec<String>,
+}
+
+impl RetryStats {
+    /// The number of attempts after the first.
+    pub fn retries(&self) -> u32 {
+        self.attempts.saturating_sub(1)
+    }
+}
+
+struct StatsOnRetry {
+    log: LogOnRetry,
+    stats: Arc<Mutex<RetryStats>>,
+}
+
+impl<E: Debug> OnRetry<E> for StatsOnRetry {
+    type Future = LogFuture;
+
+    fn on_retry(
+        &mut self,
+        attempts: u32,
+        next_delay: Option<Duration>,
+        previous_error: &E,
+    ) -> Self::Future {
+        // This is also called (without a delay) for the final failed attempt.
+        let mut stats = self.stats.lock().unwrap();
+        stats.total_delay += next_delay.unwrap_or_default();
+        stats
+            .per_attempt_errors
+            .push(format!("{:?}", previous_error));
+
+        self.log.on_retry(attempts, next_delay, previous_error)
+    }
 }
 
 pub struct LogFuture {
@@ -85,18 +265,39 @@ where
     E: ?Sized,
 {
     delay: Duration,
+    max_delay: Option<Duration>,
+    jitter: f64,
     _error: PhantomData<E>,
 }
 
+impl<E: ?Sized> ErrorBackoff<E> {
+    fn next_delay(&mut self) -> Duration {
+        let delay = self.delay;
+        let next = self.delay.saturating_mul(2);
+        self.delay = self.max_delay.map_or(next, |max_delay| next.min(max_delay));
+
+        if self.jitter > 0.0 {
+            delay.mul_f64(1.0 - self.jitter * random_fraction())
+        } else {
+            delay
+        }
+    }
+}
+
+/// A random value in `[0, 1)`, using the standard library's random hash keys.
+fn random_fraction() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
 impl<'a, E: Retryable> BackoffStrategy<'a, E> for ErrorBackoff<E> {
     type Output = RetryPolicy;
 
     fn delay(&mut self, _attempt: u32, error: &'a E) -> RetryPolicy {
-        error.custom_retry_policy().unwrap_or_else(|| {
-            let prev_delay = self.delay;
-            self.delay *= 2;
-            RetryPolicy::Delay(prev_delay)
-        })
+        error
+            .custom_retry_policy()
+            .unwrap_or_else(|| RetryPolicy::Delay(self.next_delay()))
     }
 }
 
@@ -119,15 +320,18 @@ pub trait Retryable {
     fn custom_retry_policy(&self) -> Option<RetryPolicy>;
 
     /// Generate a new backoff strategy instance.
-    fn new_backoff() -> ErrorBackoff<Self> {
-        ErrorBackoff {
-            delay: Self::default_initial_delay(),
-            _error: PhantomData,
-        }
+    fn new_backoff() -> ErrorBackoff<Self>
+    where
+        Self: Sized,
+    {
+        RetryConfig::for_error::<Self>().backoff()
     }
 
     /// Generate a new retry configuration instance.
-    fn retry_config() -> RetryFutureConfig<ErrorBackoff<Self>, LogOnRetry> {
+    fn retry_config() -> RetryFutureConfig<ErrorBackoff<Self>, LogOnRetry>
+    where
+        Self: Sized,
+    {
         RetryFutureConfig::new(Self::max_retries())
             .on_retry(LogOnRetry {
                 level: Self::log_level(),
@@ -135,3 +339,106 @@ pub trait Retryable {
             .custom_backoff(Self::new_backoff())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{retry_future_with_stats, ErrorBackoff, RetryConfig, Retryable};
+    use log::Level;
+    use std::time::Duration;
+    use tryhard::RetryPolicy;
+
+    #[derive(Debug)]
+    struct TestError(bool);
+
+    impl Retryable for TestError {
+        fn max_retries() -> u32 {
+            3
+        }
+
+        fn default_initial_delay() -> Duration {
+            Duration::from_millis(1)
+        }
+
+        fn log_level() -> Option<Level> {
+            None
+        }
+
+        fn custom_retry_policy(&self) -> Option<RetryPolicy> {
+            if self.0 {
+                None
+            } else {
+                Some(RetryPolicy::Break)
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn stats() {
+        let mut calls = 0;
+        let (result, stats) = retry_future_with_stats(|| {
+            calls += 1;
+            let result = if calls < 3 {
+                Err(TestError(true))
+            } else {
+                Ok(calls)
+            };
+            async move { result }
+        })
+        .await;
+
+        assert_eq!(result.unwrap(), 3);
+        assert_eq!(stats.attempts, 3);
+        assert_eq!(stats.retries(), 2);
+        assert_eq!(stats.total_delay, Duration::from_millis(3));
+        assert_eq!(stats.per_attempt_errors, vec!["TestError(true)"; 2]);
+
+        let (result, stats) =
+            retry_future_with_stats(|| async { Err::<(), _>(TestError(false)) }).await;
+
+        assert!(result.is_err());
+        assert_eq!(stats.attempts, 1);
+        assert_eq!(stats.total_delay, Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn custom_config() {
+        let config = RetryConfig::for_error::<TestError>().with_max_retries(1);
+        let (result, stats) = config
+            .retry_with_stats(|| async { Err::<(), _>(TestError(true)) })
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(stats.attempts, 2);
+
+        let mut backoff: ErrorBackoff<TestError> = RetryConfig::for_error::<TestError>()
+            .with_initial_delay(Duration::from_secs(1))
+            .with_max_delay(Duration::from_secs(3))
+            .backoff();
+        let delays = (0..4).map(|_| backoff.next_delay()).collect::<Vec<_>>();
+        assert_eq!(delays, [1, 2, 3, 3].map(Duration::from_secs).to_vec());
+
+        // Delays stop growing instead of overflowing after many retries.
+        let mut backoff: ErrorBackoff<TestError> = RetryConfig::for_error::<TestError>()
+            .with_max_retries(200)
+            .backoff();
+        let delays = (0..200).map(|_| backoff.next_delay()).collect::<Vec<_>>();
+        assert_eq!(delays[199], Duration::MAX);
+
+        let mut backoff: ErrorBackoff<TestError> = RetryConfig::for_error::<TestError>()
+            .with_max_delay(Duration::from_secs(3))
+            .backoff();
+        for _ in 0..200 {
+            assert!(backoff.next_delay() <= Duration::from_secs(3));
+        }
+
+        let mut backoff: ErrorBackoff<TestError> = RetryConfig::for_error::<TestError>()
+            .with_initial_delay(Duration::from_secs(8))
+            .with_jitter(0.5)
+            .backoff();
+        for _ in 0..10 {
+            let delay = backoff.next_delay();
+            backoff.delay = Duration::from_secs(8);
+            assert!(delay > Duration::from_secs(4) && delay <= Duration::from_secs(8));
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! Adaptive throttling of requests based on rate-limit feedback.
+//!
+//! Every rate-limited response doubles the delay between requests (up to a
+//! maximum) and pauses all requests for the delay the server asked for.
+//! Each successful response shrinks the delay, so throughput recovers once
+//! the server stops pushing back.
+
+use super::RateLimit;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_DELAY: Duration = Duration::from_secs(60);
+/// Delays below this value are dropped entirely.
+const MIN_DELAY: Duration = Duration::from_millis(50);
+/// The fraction of the delay kept after each successful response.
+const RECOVERY_FACTOR: f64 = 0.9;
+
+#[derive(Debug, Default)]
+struct State {
+    delay: Duration,
+    paused_until: Option<Instant>,
+    next_request: Option<Instant>,
+}
+
+impl State {
+    /// Reserve a slot for a request, returning how long to wait for it.
+    fn reserve(&mut self, now: Instant) -> Duration {
+        let start = [self.paused_until, self.next_request]
+            .into_iter()
+            .flatten()
+            .fold(now, Instant::max);
+
+        self.next_request = Some(start + self.delay);
+        start - now
+    }
+
+    fn rate_limited(&mut self, limit: &RateLimit, now: Instant) {
+        self.delay = (self.delay * 2).clamp(INITIAL_BACKOFF, MAX_DELAY);
+
+        let paused_until = now + limit.delay();
+        self.paused_until = Some(
+            self.paused_until
+                .map_or(paused_until, |current| current.max(paused_until)),
+        );
+    }
+
+    fn succeeded(&mut self) {
+        self.delay = self.delay.mul_f64(RECOVERY_FACTOR);
+
+        if self.delay < MIN_DELAY {
+            self.delay = Duration::ZERO;
+        }
+    }
+}
+
+/// A limiter shared by all clones, which backs off when requests are rate
+/// limited and gradually recovers.
+#[derive(Clone, Debug, Default)]
+pub struct AdaptiveThrottle {
+    state: Arc<Mutex<State>>,
+}
+
+impl AdaptiveThrottle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The current delay between requests.
+    pub fn delay(&self) -> Duration {
+        self.state.lock().unwrap().delay
+    }
+
+    /// Wait until the next request is allowed.
+    pub async fn wait(&self) {
+        let wait = self.state.lock().unwrap().reserve(Instant::now());
+
+        if !wait.is_zero() {
+            log::debug!("Throttling request for {:?}", wait);
+            async_std::task::sleep(wait).await;
+        }
+    }
+
+    pub fn rate_limited(&self, limit: &RateLimit) {
+        let mut state = self.state.lock().unwrap();
+        state.rate_limited(limit, Instant::now());
+        log::warn!(
+            "Rate limited ({}), request delay is now {:?}",
+            limit.status,
+            state.delay
+        );
+    }
+
+    pub fn succeeded(&self) {
+        self.state.lock().unwrap().succeeded();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AdaptiveThrottle, State, MAX_DELAY};
+    use crate::util::RateLimit;
+    use reqwest::StatusCode;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn back_off_and_recover() {
+        let now = Instant::now();
+        let limit = RateLimit {
+            status: StatusCode::TOO_MANY_REQUESTS,
+            retry_after: Some(Duration::from_secs(10)),
+        };
+        let mut state = State::default();
+
+        assert_eq!(state.reserve(now), Duration::ZERO);
+
+        state.rate_limited(&limit, now);
+        assert_eq!(state.delay, Duration::from_millis(500));
+        assert_eq!(state.reserve(now), Duration::from_secs(10));
+        // Later requests are spaced out by the delay.
+        assert_eq!(state.reserve(now), Duration::from_millis(10500));
+
+        for _ in 0..20 {
+            state.rate_limited(&limit, now);
+        }
+        assert_eq!(state.delay, MAX_DELAY);
+
+        for _ in 0..100 {
+            state.succeeded();
+        }
+        assert_eq!(state.delay, Duration::ZERO);
+    }
+
+    #[test]
+    fn shared_across_clones() {
+        let throttle = AdaptiveThrottle::new();
+        let clone = throttle.clone();
+
+        clone.rate_limited(&RateLimit {
+            status: StatusCode::SERVICE_UNAVAILABLE,
+            retry_after: None,
+        });
+        assert_eq!(throttle.delay(), Duration::from_millis(500));
+    }
+}
@@ -0,0 +1,88 @@
+//! Preparing HTTP clients before large runs.
+//!
+//! Resolving hosts and opening connections up front avoids a burst of DNS
+//! lookups and handshakes (and the failures that come with them) when many
+//! requests start at once. Resolved addresses can also be pinned, so that DNS
+//! rotation doesn't change which servers are used partway through a run.
+
+use futures::future::try_join_all;
+use reqwest::{Client, ClientBuilder, Method};
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use url::Url;
+
+/// Hosts with the addresses they should always resolve to.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct PinnedHosts(Vec<(String, Vec<SocketAddr>)>);
+
+impl PinnedHosts {
+    /// Resolve the given host and pin it to the resulting addresses.
+    pub(crate) async fn pin(&mut self, host: &str) -> io::Result<()> {
+        let addrs = resolve(host).await?;
+        log::info!("Pinning {} to {:?}", host, addrs);
+
+        self.0.retain(|(pinned, _)| pinned != host);
+        self.0.push((host.to_string(), addrs));
+        Ok(())
+    }
+
+    /// Configure a client to use the pinned addresses.
+    pub(crate) fn apply(&self, builder: ClientBuilder) -> ClientBuilder {
+        self.0.iter().fold(builder, |builder, (host, addrs)| {
+            builder.resolve_to_addrs(host, addrs)
+        })
+    }
+}
+
+/// Resolve a host on the blocking pool.
+///
+/// The ports of the returned addresses are zero, so that requests use the
+/// port of their URL.
+async fn resolve(host: &str) -> io::Result<Vec<SocketAddr>> {
+    let host = host.to_string();
+
+    tokio::task::spawn_blocking(move || {
+        let addrs = (host.as_str(), 0).to_socket_addrs()?.collect::<Vec<_>>();
+
+        if addrs.is_empty() {
+            Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No addresses found for {}", host),
+            ))
+        } else {
+            Ok(addrs)
+        }
+    })
+    .await?
+}
+
+/// Open the given number of connections to the URL's host by sending
+/// concurrent `HEAD` requests, which leaves the connections in the client's
+/// pool.
+///
+/// Response statuses are ignored.
+pub(crate) async fn open_connections(
+    client: &Client,
+    url: &Url,
+    connections: usize,
+) -> reqwest::Result<()> {
+    try_join_all((0..connections).map(|_| client.request(Method::HEAD, url.clone()).send()))
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PinnedHosts;
+
+    #[tokio::test]
+    async fn pin_localhost() {
+        let mut hosts = PinnedHosts::default();
+        hosts.pin("localhost").await.unwrap();
+        hosts.pin("localhost").await.unwrap();
+
+        assert_eq!(hosts.0.len(), 1);
+        assert!(hosts.0[0].1.iter().all(|addr| addr.ip().is_loopback()));
+    }
+}
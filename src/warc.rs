@@ -0,0 +1,201 @@
+//! Parsing of individual WARC records fetched directly from archive.org items.
+//!
+//! Each record in a `.warc.gz` file is a separate GZip member, so a single
+//! record can be fetched with a range request using the offset and length
+//! from the CDX index.
+
+use crate::{cdx::ItemExt, downloader::OriginalHeaders};
+use bytes::Bytes;
+use flate2::read::GzDecoder;
+use std::collections::BTreeMap;
+use std::io::Read;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("I/O error")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid WARC record: {0}")]
+    InvalidRecord(&'static str),
+}
+
+/// The location of a record in a WARC file stored in an archive.org item.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WarcLocation {
+    /// The archive.org item identifier.
+    pub item: String,
+    pub filename: String,
+    pub offset: u64,
+    /// The compressed length of the record.
+    pub length: u64,
+}
+
+impl WarcLocation {
+    /// Locate a CDX result in the given archive.org item.
+    ///
+    /// The CDX server does not report which item contains a WARC file, so it
+    /// must be provided. Returns `None` if the filename or offset was not
+    /// requested.
+    pub fn from_item_ext(item: &ItemExt, archive_item: &str) -> Option<Self> {
+        Some(Self {
+            item: archive_item.to_string(),
+            filename: item.filename.clone()?,
+            offset: item.offset?,
+            length: item.item.length,
+        })
+    }
+
+    pub fn download_url(&self) -> String {
+        format!(
+            "https://archive.org/download/{}/{}",
+            self.item, self.filename
+        )
+    }
+
+    /// The value of the `Range` header for this record.
+    pub fn range(&self) -> String {
+        format!(
+            "bytes={}-{}",
+            self.offset,
+            self.offset + self.length.saturating_sub(1)
+        )
+    }
+}
+
+/// A WARC response record.
+#[derive(Clone, Debug)]
+pub struct WarcRecord {
+    pub warc_headers: BTreeMap<String, String>,
+    pub status: Option<u16>,
+    /// The HTTP response headers, with lower-case names.
+    pub headers: OriginalHeaders,
+    /// The response body, with any chunked transfer encoding removed.
+    pub payload: Bytes,
+}
+
+impl WarcRecord {
+    /// Parse a single GZip-compressed WARC record.
+    pub fn parse_gz(compressed: &[u8]) -> Result<Self, Error> {
+        let mut bytes = vec![];
+        GzDecoder::new(compressed).read_to_end(&mut bytes)?;
+
+        Self::parse(&bytes)
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        let (warc_head, block) =
+            split_head(bytes).ok_or(Error::InvalidRecord("missing WARC header end"))?;
+
+        let mut lines = warc_head.split(|byte| *byte == b'\n');
+        let version = lines.next().unwrap_or_default();
+        if !version.starts_with(b"WARC/") {
+            return Err(Error::InvalidRecord("missing WARC version"));
+        }
+
+        let mut warc_headers = BTreeMap::new();
+        for (name, value) in lines.filter_map(parse_header_line) {
+            warc_headers.insert(name.to_lowercase(), value);
+        }
+
+        let block_length = warc_headers
+            .get("content-length")
+            .and_then(|value| value.parse::<usize>().ok())
+            .unwrap_or(block.len())
+            .min(block.len());
+        let block = &block[..block_length];
+
+        let (http_head, body) =
+            split_head(block).ok_or(Error::InvalidRecord("missing HTTP header end"))?;
+
+        let mut lines = http_head.split(|byte| *byte == b'\n');
+        let status = lines
+            .next()
+            .and_then(|line| std::str::from_utf8(line).ok())
+            .and_then(|line| line.split_whitespace().nth(1))
+            .and_then(|code| code.parse().ok());
+
+        let mut headers: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (name, value) in lines.filter_map(parse_header_line) {
+            headers.entry(name.to_lowercase()).or_default().push(value);
+        }
+        let headers = OriginalHeaders(headers);
+
+        let chunked = headers
+            .get("transfer-encoding")
+            .is_some_and(|value| value.eq_ignore_ascii_case("chunked"));
+
+        let payload = if chunked {
+            dechunk(body).ok_or(Error::InvalidRecord("invalid chunked encoding"))?
+        } else {
+            body.to_vec()
+        };
+
+        Ok(Self {
+            warc_headers,
+            status,
+            headers,
+            payload: Bytes::from(payload),
+        })
+    }
+}
+
+fn split_head(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    bytes
+        .windows(4)
+        .position(|window| window == b"\r\n\r\n")
+        .map(|i| (&bytes[..i], &bytes[i + 4..]))
+}
+
+fn parse_header_line(line: &[u8]) -> Option<(String, String)> {
+    let line = String::from_utf8_lossy(line);
+    let (name, value) = line.split_once(':')?;
+
+    Some((name.trim().to_string(), value.trim().to_string()))
+}
+
+fn dechunk(mut body: &[u8]) -> Option<Vec<u8>> {
+    let mut result = vec![];
+
+    loop {
+        let line_end = body.windows(2).position(|window| window == b"\r\n")?;
+        let size_line = std::str::from_utf8(&body[..line_end]).ok()?;
+        let size = usize::from_str_radix(size_line.split(';').next()?.trim(), 16).ok()?;
+        body = &body[line_end + 2..];
+
+        if size == 0 {
+            return Some(result);
+        }
+
+        result.extend_from_slice(body.get(..size)?);
+        body = body.get(size + 2..)?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WarcRecord;
+    use flate2::{write::GzEncoder, Compression};
+    use std::io::Write;
+
+    #[test]
+    fn parse_chunked_record() {
+        let http = b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nHello\r\n6\r\n world\r\n0\r\n\r\n";
+        let mut record = format!(
+            "WARC/1.0\r\nWARC-Type: response\r\nContent-Length: {}\r\n\r\n",
+            http.len()
+        )
+        .into_bytes();
+        record.extend_from_slice(http);
+        record.extend_from_slice(b"\r\n\r\n");
+
+        let mut encoder = GzEncoder::new(vec![], Compression::default());
+        encoder.write_all(&record).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let parsed = WarcRecord::parse_gz(&compressed).unwrap();
+
+        assert_eq!(parsed.status, Some(200));
+        assert_eq!(parsed.headers.get("Content-Type"), Some("text/html"));
+        assert_eq!(parsed.warc_headers["warc-type"], "response");
+        assert_eq!(&parsed.payload[..], b"Hello world");
+    }
+}
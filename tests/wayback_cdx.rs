@@ -1,7 +1,10 @@
 use chrono::NaiveDate;
 use std::fs::File;
 use std::io::{BufRead, BufReader, Error};
-use wayback_rs::{cdx::IndexClient, Downloader, Item};
+use wayback_rs::{
+    cdx::{CdxQuery, IndexClient},
+    Downloader, Item,
+};
 
 const EXAMPLE_ITEM_QUERY: &str = "twitter.com/travisbrown/status/1323554460765925376";
 
@@ -31,7 +34,10 @@ fn example_lines() -> Vec<String> {
 #[tokio::test]
 async fn test_search() {
     let client = IndexClient::default();
-    let results = client.search(EXAMPLE_ITEM_QUERY, None, None).await.unwrap();
+    let results = client
+        .search(&CdxQuery::new(EXAMPLE_ITEM_QUERY))
+        .await
+        .unwrap();
 
     assert_eq!(results[0], example_item());
 }